@@ -0,0 +1,494 @@
+use std::{
+    io,
+    net::Shutdown,
+    path::Path,
+    str::FromStr,
+    thread::{self, JoinHandle},
+    time::Duration
+  };
+
+use crate::{
+    connection::{Connection, ServerInfo},
+    fileTransfer::{self, FileOffer, Outgoing, Progress},
+    types::{Capability, Presence, Signal, SignalsData, SignalsHeader}
+  };
+
+// ----- Search types -----
+// filters of a search, 'since' and 'until' are UTC milliseconds,
+// text between slashes is a regex
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+  pub text: String,
+  pub author: Option<String>,
+  pub since: Option<u64>,
+  pub until: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+  pub id: Option<String>,
+  pub username: String,
+  pub text: String,
+  pub timestamp: Option<u64>,
+  pub sequence: Option<u64>,
+  pub reply_to: Option<String>,
+}
+
+impl SearchHit {
+  fn from_signal(signal: SignalsData) -> SearchHit {
+    SearchHit {
+      id: signal.id,
+      username: signal.username.unwrap_or_default(),
+      text: signal.message.unwrap_or_default(),
+      timestamp: signal.timestamp,
+      sequence: signal.sequence,
+      reply_to: signal.replyTo,
+    }
+  }
+}
+
+// ----- Event type -----
+// 'timestamp' is UTC milliseconds since the Unix epoch and 'sequence'
+// is the server's message counter, both missing with older servers;
+// 'reply_to' is the id of the message a reply answers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+  Message {
+    id: Option<String>,
+    username: String,
+    text: String,
+    timestamp: Option<u64>,
+    sequence: Option<u64>,
+    reply_to: Option<String>,
+  },
+  // 'away' is set on messages that came while this user was offline
+  Direct {
+    id: Option<String>,
+    from: String,
+    to: String,
+    text: String,
+    timestamp: Option<u64>,
+    away: bool,
+  },
+  // MOTD, server announcements and anything else from the server itself
+  Notice {
+    id: Option<String>,
+    text: String,
+    timestamp: Option<u64>,
+    sequence: Option<u64>,
+  },
+  Presence {
+    username: String,
+    presence: Presence,
+    timestamp: Option<u64>,
+    sequence: Option<u64>,
+  },
+  // every reaction to the message with its count and the ones of this
+  // user, replayed messages with reactions are followed by one too
+  Reactions {
+    id: String,
+    reactions: Vec<(String, u32)>,
+    own: Vec<String>,
+  },
+  Warning(String),
+  Stats(String),
+  // matches of the last search, oldest first
+  SearchResults(Vec<SearchHit>),
+  // answer to a history request, only the messages of 'thread' if it's set
+  History {
+    thread: Option<String>,
+    messages: Vec<SearchHit>,
+  },
+  // a file someone wants to send, nothing is written before it's accepted
+  FileOffer(FileOffer),
+  // the recipient's answer to an offer of this user
+  FileAnswer {
+    transfer: String,
+    from: String,
+    accepted: bool,
+  },
+  // always the last event, with the reason if there is one
+  Disconnected(Option<String>),
+}
+
+impl Event {
+  // signals the client doesn't know about give no event
+  pub fn from_signal(signal: SignalsData) -> Option<Event> {
+    let text = signal.message.unwrap_or_default();
+    let (timestamp, sequence) = (signal.timestamp, signal.sequence);
+
+    let event = match signal.signalType? {
+      Signal::Message if signal.serverMess => match (signal.presence, signal.username) {
+        (Some(presence), Some(username)) => Event::Presence { username, presence, timestamp, sequence },
+        _ => Event::Notice { id: signal.id, text, timestamp, sequence },
+      },
+      Signal::Message if signal.recipient.is_some() => Event::Direct {
+        id: signal.id,
+        from: signal.username.unwrap_or_default(),
+        to: signal.recipient.unwrap_or_default(),
+        text,
+        timestamp,
+        away: signal.away,
+      },
+      Signal::Message => Event::Message {
+        id: signal.id,
+        username: signal.username.unwrap_or_default(),
+        text,
+        timestamp,
+        sequence,
+        reply_to: signal.replyTo,
+      },
+      Signal::React => Event::Reactions {
+        id: signal.id?,
+        reactions: signal.reactions.unwrap_or_default(),
+        own: signal.reacted.unwrap_or_default(),
+      },
+      Signal::FileOffer => Event::FileOffer(FileOffer {
+        transfer: signal.transfer?,
+        from: signal.username?,
+        name: signal.fileName?,
+        size: signal.fileSize?,
+        sha256: signal.sha256?,
+        address: signal.address?,
+      }),
+      Signal::FileAccept => Event::FileAnswer {
+        transfer: signal.transfer?,
+        from: signal.username.unwrap_or_default(),
+        accepted: !signal.rejected,
+      },
+      Signal::Warning => Event::Warning(text),
+      Signal::Stats => Event::Stats(text),
+      _ => return None,
+    };
+    Some(event)
+  }
+}
+
+// ----- Events iterator -----
+// blocks until the next event, ends after 'Event::Disconnected'
+pub struct Events {
+  connection: Connection,
+  finished: bool,
+  // search matches and history come one by one, they're given out all at once
+  search_hits: Vec<SearchHit>,
+  history: Vec<SearchHit>,
+  // reactions of a replayed message, given out right after it
+  pending: Option<Event>,
+}
+
+impl Iterator for Events {
+  type Item = Event;
+
+  fn next(&mut self) -> Option<Event> {
+    if self.finished {
+      return None;
+    }
+    if let Some(event) = self.pending.take() {
+      return Some(event);
+    }
+
+    loop {
+      let data = match self.connection.readSignal() {
+        Ok(v) => v,
+        Err(e) => {
+          self.finished = true;
+          let reason = (e.kind() != io::ErrorKind::BrokenPipe).then(|| e.to_string());
+          return Some(Event::Disconnected(reason));
+        }
+      };
+      // legacy servers send frames which are not parsed, they are skipped
+      let Ok(signal) = SignalsData::from_str(&data) else { continue };
+      if let Some(Signal::Search) = signal.signalType {
+        if signal.results.is_some() {
+          return Some(Event::SearchResults(std::mem::take(&mut self.search_hits)));
+        }
+        self.search_hits.push(SearchHit::from_signal(signal));
+        continue;
+      }
+      if let Some(Signal::History) = signal.signalType {
+        if signal.results.is_some() {
+          return Some(Event::History { thread: signal.thread, messages: std::mem::take(&mut self.history) });
+        }
+        self.history.push(SearchHit::from_signal(signal));
+        continue;
+      }
+      self.pending = match (signal.signalType, &signal.id, &signal.reactions) {
+        (Some(Signal::Message), Some(id), Some(reactions)) if !signal.serverMess => Some(Event::Reactions {
+          id: id.to_owned(),
+          reactions: reactions.clone(),
+          own: signal.reacted.clone().unwrap_or_default(),
+        }),
+        _ => None,
+      };
+      if let Some(event) = Event::from_signal(signal) {
+        return Some(event);
+      }
+    }
+  }
+}
+
+// ----- Chat client -----
+// what the terminal client is built on, without any drawing:
+//
+//   let mut client = ChatClient::connect("127.0.0.1:8080", "bot")?;
+//   client.send_message("hello")?;
+//   for event in client.events()? { ... }
+pub struct ChatClient {
+  connection: Connection,
+  username: String,
+}
+
+impl ChatClient {
+  pub fn connect(address: &str, username: &str) -> io::Result<ChatClient> {
+    Self::connect_with_token(address, username, None)
+  }
+
+  // owners, moderators and admins of the server log in with their token
+  pub fn connect_with_token(address: &str, username: &str, token: Option<&str>) -> io::Result<ChatClient> {
+    let connection = Connection::new(address, username, token)?;
+    Ok(Self::from_connection(connection, username))
+  }
+
+  // for connections made some other way, e.g. the p2p mesh
+  pub fn from_connection(connection: Connection, username: &str) -> ChatClient {
+    ChatClient {
+      connection,
+      username: username.to_owned(),
+    }
+  }
+
+  pub fn username(&self) -> &str {
+    &self.username
+  }
+
+  pub fn server(&self) -> &ServerInfo {
+    &self.connection.server
+  }
+
+  pub fn send_message(&mut self, text: &str) -> io::Result<()> {
+    let username = self.username.clone();
+    self.connection.send_message(&username, text)
+  }
+
+  // a reply to the message 'id', servers without threads get no such message
+  pub fn reply(&mut self, id: &str, text: &str) -> io::Result<()> {
+    if !self.server().supports(Capability::Threads) {
+      return Err(io::Error::new(io::ErrorKind::Unsupported, "Server doesn't support replies"));
+    }
+    let signal = SignalsData::new(
+      vec![
+        SignalsHeader::signalType(Signal::Message),
+        SignalsHeader::replyTo(id.to_owned()),
+        SignalsHeader::withMess
+      ],
+      Some(text)
+    );
+    self.connection.send_frame(&signal.to_string())
+  }
+
+  // the answer comes as 'Event::History', the thread of any of its messages
+  // or the whole history if 'thread' is None
+  pub fn request_history(&mut self, thread: Option<&str>) -> io::Result<()> {
+    if !self.server().supports(Capability::Threads) {
+      return Err(io::Error::new(io::ErrorKind::Unsupported, "Server doesn't support HISTORY"));
+    }
+    let mut headers = vec![SignalsHeader::signalType(Signal::History)];
+    if let Some(v) = thread {
+      headers.push(SignalsHeader::thread(v.to_owned()));
+    }
+    let signal = SignalsData::new(headers, None);
+    self.connection.send_frame(&signal.to_string())
+  }
+
+  // the recipient gets it at once or on their next login
+  pub fn send_direct(&mut self, to: &str, text: &str) -> io::Result<()> {
+    if !self.server().supports(Capability::DirectMessages) {
+      return Err(io::Error::new(io::ErrorKind::Unsupported, "Server doesn't support direct messages"));
+    }
+    let signal = SignalsData::new(
+      vec![
+        SignalsHeader::signalType(Signal::Message),
+        SignalsHeader::recipient(to.to_owned()),
+        SignalsHeader::withMess
+      ],
+      Some(text)
+    );
+    self.connection.send_frame(&signal.to_string())
+  }
+
+  // reactions to a message by its id, the server answers everyone
+  // with 'Event::Reactions' and errors come as 'Event::Warning'
+  pub fn react(&mut self, id: &str, reaction: &str) -> io::Result<()> {
+    self.send_reaction(id, reaction, false)
+  }
+
+  pub fn unreact(&mut self, id: &str, reaction: &str) -> io::Result<()> {
+    self.send_reaction(id, reaction, true)
+  }
+
+  fn send_reaction(&mut self, id: &str, reaction: &str, remove: bool) -> io::Result<()> {
+    if !self.server().supports(Capability::Reactions) {
+      return Err(io::Error::new(io::ErrorKind::Unsupported, "Server doesn't support reactions"));
+    }
+    let mut headers = vec![
+      SignalsHeader::signalType(Signal::React),
+      SignalsHeader::id(id.to_owned()),
+      SignalsHeader::reaction(reaction.to_owned())
+    ];
+    if remove {
+      headers.push(SignalsHeader::remove);
+    }
+    let signal = SignalsData::new(headers, None);
+    self.connection.send_frame(&signal.to_string())
+  }
+
+  // offers 'path' to 'to' and listens for them, progress of the upload
+  // goes to 'on_progress'; the answer comes as 'Event::FileAnswer' and
+  // a rejected offer should be cancelled
+  pub fn offer_file<F>(&mut self, to: &str, path: &Path, on_progress: F) -> io::Result<Outgoing>
+  where
+    F: FnMut(Progress) + Send + 'static
+  {
+    if !self.server().supports(Capability::FileTransfer) {
+      return Err(io::Error::new(io::ErrorKind::Unsupported, "Server doesn't support file transfers"));
+    }
+    let ip = self.connection.stream.local_addr()?.ip();
+    let outgoing = Outgoing::listen(path, ip, on_progress)?;
+    let signal = SignalsData::new(
+      vec![
+        SignalsHeader::signalType(Signal::FileOffer),
+        SignalsHeader::recipient(to.to_owned()),
+        SignalsHeader::transfer(outgoing.transfer.clone()),
+        SignalsHeader::fileName(outgoing.name.clone()),
+        SignalsHeader::fileSize(outgoing.size),
+        SignalsHeader::sha256(outgoing.sha256.clone()),
+        SignalsHeader::port(outgoing.port)
+      ],
+      None
+    );
+    if let Err(e) = self.connection.send_frame(&signal.to_string()) {
+      outgoing.cancel();
+      return Err(e);
+    }
+    Ok(outgoing)
+  }
+
+  // downloads the offered file into 'directory' on a separate thread
+  pub fn accept_file<F>(&mut self, offer: &FileOffer, directory: &Path, on_progress: F) -> io::Result<()>
+  where
+    F: FnMut(Progress) + Send + 'static
+  {
+    self.answer_file(offer, false)?;
+    fileTransfer::download(offer.clone(), directory, on_progress)
+  }
+
+  pub fn reject_file(&mut self, offer: &FileOffer) -> io::Result<()> {
+    self.answer_file(offer, true)
+  }
+
+  fn answer_file(&mut self, offer: &FileOffer, rejected: bool) -> io::Result<()> {
+    if !self.server().supports(Capability::FileTransfer) {
+      return Err(io::Error::new(io::ErrorKind::Unsupported, "Server doesn't support file transfers"));
+    }
+    let mut headers = vec![
+      SignalsHeader::signalType(Signal::FileAccept),
+      SignalsHeader::transfer(offer.transfer.clone())
+    ];
+    if rejected {
+      headers.push(SignalsHeader::rejected);
+    }
+    let signal = SignalsData::new(headers, None);
+    self.connection.send_frame(&signal.to_string())
+  }
+
+  // moderation, done by the server only for owners and moderators;
+  // the room gets a notice, errors come as 'Event::Warning'
+  pub fn kick(&mut self, username: &str, reason: Option<&str>) -> io::Result<()> {
+    self.moderate(Signal::Kick, username, None, reason)
+  }
+
+  pub fn mute(&mut self, username: &str, duration: Duration, reason: Option<&str>) -> io::Result<()> {
+    self.moderate(Signal::Mute, username, Some(duration), reason)
+  }
+
+  pub fn ban(&mut self, username: &str, reason: Option<&str>) -> io::Result<()> {
+    self.moderate(Signal::Ban, username, None, reason)
+  }
+
+  pub fn unban(&mut self, username: &str) -> io::Result<()> {
+    self.moderate(Signal::Unban, username, None, None)
+  }
+
+  fn moderate(&mut self, action: Signal, username: &str, duration: Option<Duration>, reason: Option<&str>) -> io::Result<()> {
+    if !self.server().supports(Capability::Moderation) {
+      return Err(io::Error::new(io::ErrorKind::Unsupported, "Server doesn't support moderation"));
+    }
+    let mut headers = vec![SignalsHeader::signalType(action), SignalsHeader::target(username.to_owned())];
+    if let Some(v) = duration {
+      headers.push(SignalsHeader::duration(v.as_secs().max(1)));
+    }
+    if reason.is_some() {
+      headers.push(SignalsHeader::withMess);
+    }
+    let signal = SignalsData::new(headers, reason);
+    self.connection.send_frame(&signal.to_string())
+  }
+
+  // the answer comes as 'Event::Stats', or 'Event::Warning' for non admins
+  pub fn request_stats(&mut self) -> io::Result<()> {
+    if !self.server().supports(Capability::Stats) {
+      return Err(io::Error::new(io::ErrorKind::Unsupported, "Server doesn't support STATS"));
+    }
+    let signal = SignalsData::new(vec![SignalsHeader::signalType(Signal::Stats)], None);
+    self.connection.send_frame(&signal.to_string())
+  }
+
+  // the answer comes as 'Event::SearchResults', bad patterns give 'Event::Warning'
+  pub fn search(&mut self, query: &SearchQuery) -> io::Result<()> {
+    if !self.server().supports(Capability::Search) {
+      return Err(io::Error::new(io::ErrorKind::Unsupported, "Server doesn't support SEARCH"));
+    }
+    let mut headers = vec![SignalsHeader::signalType(Signal::Search), SignalsHeader::withMess];
+    if let Some(v) = &query.author {
+      headers.push(SignalsHeader::author(v.to_owned()));
+    }
+    if let Some(v) = query.since {
+      headers.push(SignalsHeader::since(v));
+    }
+    if let Some(v) = query.until {
+      headers.push(SignalsHeader::until(v));
+    }
+    let signal = SignalsData::new(headers, Some(&query.text));
+    self.connection.send_frame(&signal.to_string())
+  }
+
+  // events can be read from one place at a time, the stream is shared
+  pub fn events(&self) -> io::Result<Events> {
+    Ok(Events {
+      connection: self.connection.try_clone()?,
+      finished: false,
+      search_hits: Vec::new(),
+      history: Vec::new(),
+      pending: None,
+    })
+  }
+
+  // calls 'callback' for every event on a separate thread
+  pub fn on_event<F>(&self, mut callback: F) -> io::Result<JoinHandle<()>>
+  where
+    F: FnMut(Event) + Send + 'static
+  {
+    let events = self.events()?;
+    Ok(thread::spawn(move || events.for_each(&mut callback)))
+  }
+
+  // closes the sending side, the server answers by closing the connection,
+  // so events still coming are read till 'Event::Disconnected'
+  pub fn finish(&self) -> io::Result<()> {
+    self.connection.stream.shutdown(Shutdown::Write)
+  }
+
+  pub fn disconnect(&self) -> io::Result<()> {
+    self.connection.stream.shutdown(Shutdown::Both)
+  }
+}
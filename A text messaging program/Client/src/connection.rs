@@ -1,5 +1,7 @@
 use std::{
-    net::TcpStream, 
+    net::{SocketAddr, TcpStream, ToSocketAddrs}, 
+    time::Duration,
+    str::FromStr,
     io::{
       self, 
       Write,
@@ -9,54 +11,197 @@ use std::{
       BufReader
     },
   };
+  use std::sync::Arc;
+  use parking_lot::Mutex;
+  use crate::compression::{compress_frame, decompress_frame};
   use crate::types::{
     Signal, 
     SignalsHeader, 
     SignalsData,
-    Authoritation
+    Authoritation,
+    Capability,
+    PROTOCOL_VERSION,
+    LEGACY_PROTOCOL_VERSION
   };
   
+  const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+  // what this client can do, announced in the CONNECTION signal
+  pub const CLIENT_CAPABILITIES: [Capability; 8] = [
+    Capability::MessageIds,
+    Capability::Motd,
+    Capability::Warnings,
+    Capability::ServerIdentity,
+    Capability::Deflate,
+    Capability::Reactions,
+    Capability::Threads,
+    Capability::FileTransfer
+  ];
+
+  // a compressed frame may not unpack into more than that
+  const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+  // what the server told about itself in the handshake
+  #[derive(Debug, Clone)]
+  pub struct ServerInfo {
+    pub version: u16,
+    pub capabilities: Vec<Capability>,
+    pub name: Option<String>,
+    pub motd: Option<String>,
+  }
+
+  impl ServerInfo {
+    // servers from before the handshake negotiation
+    fn legacy() -> ServerInfo {
+      ServerInfo {
+        version: LEGACY_PROTOCOL_VERSION,
+        capabilities: Vec::new(),
+        name: None,
+        motd: None,
+      }
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+      self.capabilities.contains(&capability)
+    }
+  }
+
   pub struct Connection {
     pub stream: TcpStream,
-    reader: io::BufReader<TcpStream>
+    pub server: ServerInfo,
+    // shared by clones, frames read ahead during the handshake stay in it
+    reader: Arc<Mutex<io::BufReader<TcpStream>>>
   }
   
   impl Connection {
-    pub fn new(address: &str, username: &str) -> io::Result<Connection> {
-      let signal = SignalsData::new(
-        vec![
-          SignalsHeader::signalType(Signal::Connection),
-          SignalsHeader::username(username.to_owned())
-        ],
-        None
-      );
+    // 'token' is for owners, moderators and admins of the server
+    pub fn new(address: &str, username: &str, token: Option<&str>) -> io::Result<Connection> {
+      let mut headers = vec![
+        SignalsHeader::signalType(Signal::Connection),
+        SignalsHeader::username(username.to_owned()),
+        SignalsHeader::protocolVersion(PROTOCOL_VERSION),
+        SignalsHeader::capabilities(CLIENT_CAPABILITIES.to_vec())
+      ];
+      if let Some(v) = token {
+        headers.push(SignalsHeader::secret(v.to_owned()));
+      }
+      let signal = SignalsData::new(headers, None);
       
       // try to connect to the address
-      let mut connection = TcpStream::connect(address)?;
+      let mut connection = Self::dial(address)?;
       // sending to the server
       connection.write_all(signal.to_string().as_bytes())?;
-      let reader = BufReader::new(connection.try_clone()?);
+      let reader = Arc::new(Mutex::new(BufReader::new(connection.try_clone()?)));
   
       let mut instance = Connection {
         stream: connection,
+        server: ServerInfo::legacy(),
         reader
       };
   
       let data_from_socket = instance.readSignal()?;
-      if data_from_socket.contains(&Authoritation::Denied.to_string()) {
-        return Err(Error::new(ErrorKind::ConnectionAborted, "Access denied"));
+      match SignalsData::from_str(&data_from_socket) {
+        Ok(data) if data.auth == Some(Authoritation::Denied) => {
+          return Err(Error::new(ErrorKind::PermissionDenied, "Access denied"));
+        },
+        Ok(data) => {
+          instance.server = ServerInfo {
+            version: data.peer_version(),
+            capabilities: data.capabilities.clone().unwrap_or_default(),
+            name: data.serverName.clone(),
+            motd: data.message.clone().filter(|_| data.supports(Capability::Motd)),
+          };
+        },
+        // legacy servers answer with the bare AUTH_STATUS, which doesn't parse
+        Err(_) if data_from_socket.contains(&SignalsHeader::auth(Authoritation::Denied).to_string()) => {
+          return Err(Error::new(ErrorKind::PermissionDenied, "Access denied"));
+        },
+        Err(_) => {},
       }
     
       return Ok(instance)
     }
+
+    // tries every address the host resolves to, IPv6 literals go in brackets
+    pub fn dial(address: &str) -> io::Result<TcpStream> {
+      let addresses = Self::resolve(address)?;
+      let mut last_error = Error::new(ErrorKind::NotFound, format!("'{address}' has no addresses"));
+      for addr in addresses {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+          Ok(v) => return Ok(v),
+          Err(e) => last_error = Error::new(e.kind(), format!("{addr}: {e}")),
+        }
+      }
+      Err(last_error)
+    }
+
+    fn resolve(address: &str) -> io::Result<Vec<SocketAddr>> {
+      let address = address.trim();
+      if let Ok(v) = address.parse::<SocketAddr>() {
+        return Ok(vec![v]);
+      }
+
+      let (host, port) = address.rsplit_once(':').ok_or_else(|| Error::new(
+        ErrorKind::InvalidInput,
+        format!("'{address}' has no port, expected host:port")
+      ))?;
+      if host.contains(':') && !host.starts_with('[') {
+        return Err(Error::new(
+          ErrorKind::InvalidInput,
+          format!("IPv6 address must be written in brackets, e.g. [{host}]:{port}")
+        ));
+      }
+      let port = port.parse::<u16>().map_err(|_| Error::new(
+        ErrorKind::InvalidInput,
+        format!("'{port}' is not a valid port")
+      ))?;
+
+      let host = host.trim_start_matches('[').trim_end_matches(']');
+      Ok((host, port).to_socket_addrs()?.collect())
+    }
+
+    pub fn send_message(&mut self, username: &str, text: &str) -> io::Result<()> {
+      let mut headers = vec![
+        SignalsHeader::signalType(Signal::Message),
+        SignalsHeader::withMess
+      ];
+      // older servers take the author from the message itself
+      if !self.server.supports(Capability::ServerIdentity) {
+        headers.push(SignalsHeader::username(username.to_owned()));
+      }
+      let signal = SignalsData::new(headers, Some(text));
+
+      self.send_frame(&signal.to_string())
+    }
+
+    // large bodies go compressed to servers that take it
+    pub fn send_frame(&mut self, frame: &str) -> io::Result<()> {
+      let compressed = self.server.supports(Capability::Deflate).then(|| compress_frame(frame)).flatten();
+      self.stream.write_all(compressed.as_deref().unwrap_or(frame).as_bytes())
+    }
+
+    // wraps already established stream (peer links in p2p mode)
+    pub fn from_stream(stream: TcpStream) -> io::Result<Connection> {
+      let reader = Arc::new(Mutex::new(BufReader::new(stream.try_clone()?)));
+      Ok(Connection { stream, server: ServerInfo::legacy(), reader })
+    }
   
+    pub fn try_clone(&self) -> io::Result<Connection> {
+      Ok(Connection {
+        stream: self.stream.try_clone()?,
+        server: self.server.clone(),
+        reader: self.reader.clone()
+      })
+    }
+
     pub fn readSignal(&mut self) -> io::Result<String> {
       let mut res_line = String::new();
       let mut isHeadersRead = false;
+      let mut reader = self.reader.lock();
       loop {
         let mut buf_line = String::new();
-        match self.reader.read_line(&mut buf_line) {
-          Err(e) => panic!("Got an error: {}", e),
+        match reader.read_line(&mut buf_line) {
+          Err(e) => return Err(e),
           Ok(0) => return Err(Error::new(ErrorKind::BrokenPipe, "Connection closed")),
           Ok(_) => (),
         };
@@ -70,7 +215,7 @@ use std::{
         }
       }
     
-      Ok(res_line)
+      decompress_frame(res_line, MAX_BODY_BYTES).map_err(|e| Error::new(ErrorKind::InvalidData, e))
     }
   }
   
@@ -78,7 +223,8 @@ use std::{
     fn clone(&self) -> Self {
       Connection { 
         stream: self.stream.try_clone().unwrap(), 
-        reader: BufReader::new(self.stream.try_clone().unwrap())
+        server: self.server.clone(),
+        reader: self.reader.clone()
       }
     }
   }
\ No newline at end of file
@@ -0,0 +1,465 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+      atomic::{AtomicBool, Ordering},
+      Arc
+    },
+    thread,
+    time::{Duration, Instant}
+  };
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+// ----- Direct file transfer -----
+// the server passes the offer and the answer only, the file goes
+// over a connection of its own from the recipient to the sender:
+//
+//   recipient: '<transfer> <offset>\n'
+//   sender:    '<bytes left>\n' and the file from 'offset' on
+//   recipient: 'OK\n' or 'BAD\n' once the SHA-256 is checked
+//
+// a connection that breaks is opened again from the bytes already written
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// a quiet connection counts as a broken one
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+// the sender stops waiting for the recipient after that
+const OFFER_TIMEOUT: Duration = Duration::from_secs(600);
+// tries in a row without a byte coming before the recipient gives up
+const RETRIES: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+// unfinished downloads are kept next to where the file goes
+const PART_SUFFIX: &str = ".part";
+
+// ----- Progress type -----
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+  // bytes of the file there are on the recipient's side so far
+  Transferred { done: u64, size: u64 },
+  // the connection broke, it goes on from this byte
+  Resuming(u64),
+  // checked, with where the file is: the sent file or the saved one
+  Finished(PathBuf),
+  Failed(String),
+}
+
+// ----- File offer -----
+// what the recipient is told, 'address' is the sender as the server saw it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileOffer {
+  pub transfer: String,
+  pub from: String,
+  pub name: String,
+  pub size: u64,
+  pub sha256: String,
+  pub address: String,
+}
+
+// size and SHA-256 in lowercase hex
+pub fn hash_file(path: &Path) -> io::Result<(u64, String)> {
+  let mut file = File::open(path)?;
+  let mut hasher = Sha256::new();
+  let size = io::copy(&mut file, &mut hasher)?;
+  Ok((size, hex(&hasher.finalize())))
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|v| format!("{v:02x}")).collect()
+}
+
+// ----- Sending side -----
+// listens for the recipient till the file is through, the offer is
+// rejected or nobody comes for 'OFFER_TIMEOUT'; the recipient is told
+// the address the server sees, so 'ip' should be the one of the chat
+// connection, IPv4 or IPv6
+pub struct Outgoing {
+  pub transfer: String,
+  pub name: String,
+  pub size: u64,
+  pub sha256: String,
+  pub port: u16,
+  // set by 'cancel' and once the sender stops listening
+  closed: Arc<AtomicBool>,
+}
+
+impl Outgoing {
+  pub fn listen<F>(path: &Path, ip: IpAddr, on_progress: F) -> io::Result<Outgoing>
+  where
+    F: FnMut(Progress) + Send + 'static
+  {
+    let name = path.file_name()
+      .and_then(|v| v.to_str())
+      .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "not a file name"))?
+      .to_owned();
+    if !path.is_file() {
+      return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is not a file", path.display())));
+    }
+    let (size, sha256) = hash_file(path)?;
+    let listener = TcpListener::bind((ip, 0))?;
+    listener.set_nonblocking(true)?;
+
+    let outgoing = Outgoing {
+      transfer: Uuid::new_v4().to_string(),
+      name,
+      size,
+      sha256,
+      port: listener.local_addr()?.port(),
+      closed: Arc::new(AtomicBool::new(false)),
+    };
+    let transfer = outgoing.transfer.clone();
+    let closed = outgoing.closed.clone();
+    let path = path.to_owned();
+    thread::spawn(move || {
+      Self::serve(listener, &path, size, &transfer, &closed, on_progress);
+      closed.store(true, Ordering::SeqCst);
+    });
+    Ok(outgoing)
+  }
+
+  // after a rejection, a transfer on the way is not broken off
+  pub fn cancel(&self) {
+    self.closed.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::SeqCst)
+  }
+
+  fn serve<F>(listener: TcpListener, path: &Path, size: u64, transfer: &str, closed: &AtomicBool, mut on_progress: F)
+  where
+    F: FnMut(Progress)
+  {
+    let mut deadline = Instant::now() + OFFER_TIMEOUT;
+    while !closed.load(Ordering::SeqCst) {
+      if Instant::now() > deadline {
+        on_progress(Progress::Failed("the recipient didn't come".to_owned()));
+        return;
+      }
+      let stream = match listener.accept() {
+        Ok((v, _)) => v,
+        Err(e) if e.kind() == ErrorKind::WouldBlock => {
+          thread::sleep(Duration::from_millis(50));
+          continue;
+        },
+        Err(e) => return on_progress(Progress::Failed(e.to_string())),
+      };
+
+      // a connection for another transfer is just closed
+      match Self::send(stream, path, size, transfer, &mut on_progress) {
+        Ok(Some(true)) => return on_progress(Progress::Finished(path.to_owned())),
+        Ok(Some(false)) => return on_progress(Progress::Failed("the file didn't match its SHA-256".to_owned())),
+        Ok(None) | Err(_) => {},
+      }
+      deadline = Instant::now() + OFFER_TIMEOUT;
+    }
+  }
+
+  // the recipient's check, None if the connection ended before it
+  fn send<F>(stream: TcpStream, path: &Path, size: u64, transfer: &str, on_progress: &mut F) -> io::Result<Option<bool>>
+  where
+    F: FnMut(Progress)
+  {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.by_ref().take(256).read_line(&mut request)?;
+    let offset = match request.trim().split_once(' ') {
+      Some((id, offset)) if id == transfer => offset.parse::<u64>().ok().filter(|v| *v <= size),
+      _ => None,
+    };
+    let Some(offset) = offset else { return Ok(None) };
+
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut stream = stream;
+    stream.write_all(format!("{}\n", size - offset).as_bytes())?;
+
+    let mut done = offset;
+    let mut reported = Instant::now();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    while done < size {
+      let read = file.read(&mut buf)?;
+      if read == 0 {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "the file got shorter"));
+      }
+      stream.write_all(&buf[..read])?;
+      done += read as u64;
+      if reported.elapsed() >= PROGRESS_INTERVAL {
+        on_progress(Progress::Transferred { done, size });
+        reported = Instant::now();
+      }
+    }
+    on_progress(Progress::Transferred { done, size });
+
+    let mut answer = String::new();
+    reader.take(16).read_line(&mut answer)?;
+    match answer.trim() {
+      "OK" => Ok(Some(true)),
+      "BAD" => Ok(Some(false)),
+      _ => Ok(None),
+    }
+  }
+}
+
+// ----- Receiving side -----
+// nothing is written before the offer is accepted; the file goes to
+// '<name>.part' in 'directory' and gets its name once it's checked
+pub fn download<F>(offer: FileOffer, directory: &Path, mut on_progress: F) -> io::Result<()>
+where
+  F: FnMut(Progress) + Send + 'static
+{
+  let address: SocketAddr = offer.address.parse()
+    .map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("{} is not an address", offer.address)))?;
+  if offer.name.contains(['/', '\\']) || offer.name == ".." || offer.name == "." {
+    return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is not a file name", offer.name)));
+  }
+  fs::create_dir_all(directory)?;
+  let part = directory.join(format!("{}{PART_SUFFIX}", offer.name));
+  let directory = directory.to_owned();
+
+  thread::spawn(move || {
+    match receive(&offer, address, &part, &mut on_progress) {
+      Ok(()) => {},
+      Err(e) => return on_progress(Progress::Failed(e.to_string())),
+    }
+    match free_path(&directory, &offer.name).and_then(|v| fs::rename(&part, &v).map(|_| v)) {
+      Ok(path) => on_progress(Progress::Finished(path)),
+      Err(e) => on_progress(Progress::Failed(e.to_string())),
+    }
+  });
+  Ok(())
+}
+
+// an unfinished '.part' of the same name is taken as the start of the file
+fn receive<F>(offer: &FileOffer, address: SocketAddr, part: &Path, on_progress: &mut F) -> io::Result<()>
+where
+  F: FnMut(Progress)
+{
+  let mut file = OpenOptions::new().create(true).append(true).open(part)?;
+  if file.metadata()?.len() > offer.size {
+    file.set_len(0)?;
+  }
+
+  let mut failures = 0;
+  loop {
+    let offset = file.metadata()?.len();
+    let error = match fetch(offer, address, offset, &mut file, on_progress) {
+      Ok(Some(stream)) => return finish(offer, part, stream),
+      Ok(None) => io::Error::new(ErrorKind::ConnectionAborted, "the sender stopped sending"),
+      Err(e) => e,
+    };
+    // a try that brought bytes starts the count again
+    let written = file.metadata()?.len();
+    if written > offset {
+      failures = 0;
+    } else {
+      failures += 1;
+      if failures >= RETRIES {
+        return Err(error);
+      }
+      thread::sleep(RETRY_DELAY * failures);
+    }
+    on_progress(Progress::Resuming(written));
+  }
+}
+
+// the connection if the file is all there, None if it broke off
+fn fetch<F>(offer: &FileOffer, address: SocketAddr, offset: u64, file: &mut File, on_progress: &mut F) -> io::Result<Option<TcpStream>>
+where
+  F: FnMut(Progress)
+{
+  let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+  stream.set_read_timeout(Some(IO_TIMEOUT))?;
+  stream.set_write_timeout(Some(IO_TIMEOUT))?;
+  stream.write_all(format!("{} {offset}\n", offer.transfer).as_bytes())?;
+
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let mut header = String::new();
+  reader.by_ref().take(32).read_line(&mut header)?;
+  let left: u64 = header.trim().parse()
+    .map_err(|_| io::Error::new(ErrorKind::InvalidData, "the sender refused the transfer"))?;
+  if offset + left != offer.size {
+    return Err(io::Error::new(ErrorKind::InvalidData, "the sender's file is not the offered one"));
+  }
+
+  let mut done = offset;
+  let mut reported = Instant::now();
+  let mut buf = vec![0u8; CHUNK_SIZE];
+  while done < offer.size {
+    let wanted = buf.len().min((offer.size - done) as usize);
+    let read = match reader.read(&mut buf[..wanted]) {
+      Ok(0) => return Ok(None),
+      Ok(v) => v,
+      Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+      Err(_) => return Ok(None),
+    };
+    file.write_all(&buf[..read])?;
+    done += read as u64;
+    if reported.elapsed() >= PROGRESS_INTERVAL {
+      on_progress(Progress::Transferred { done, size: offer.size });
+      reported = Instant::now();
+    }
+  }
+  file.flush()?;
+  on_progress(Progress::Transferred { done, size: offer.size });
+  Ok(Some(stream))
+}
+
+// a file which doesn't match is dropped, a new try would get the same
+fn finish(offer: &FileOffer, part: &Path, mut stream: TcpStream) -> io::Result<()> {
+  let (_, sha256) = hash_file(part)?;
+  if sha256.eq_ignore_ascii_case(&offer.sha256) {
+    let _ = stream.write_all(b"OK\n");
+    return Ok(());
+  }
+  let _ = stream.write_all(b"BAD\n");
+  fs::remove_file(part)?;
+  Err(io::Error::new(ErrorKind::InvalidData, "the file didn't match its SHA-256"))
+}
+
+// 'name', or 'name (1)' and so on if that's taken
+fn free_path(directory: &Path, name: &str) -> io::Result<PathBuf> {
+  let path = directory.join(name);
+  if !path.exists() {
+    return Ok(path);
+  }
+  let (stem, extension) = match name.rsplit_once('.') {
+    Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+    _ => (name, String::new()),
+  };
+  (1..1000)
+    .map(|v| directory.join(format!("{stem} ({v}){extension}")))
+    .find(|v| !v.exists())
+    .ok_or_else(|| io::Error::new(ErrorKind::AlreadyExists, format!("{name} already exists")))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::mpsc::{self, Receiver};
+
+  use super::*;
+
+  const WAIT: Duration = Duration::from_secs(10);
+
+  // an empty directory of its own for each test
+  fn directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("chat-transfer-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    directory
+  }
+
+  fn offer(transfer: &str, address: SocketAddr, content: &[u8]) -> FileOffer {
+    FileOffer {
+      transfer: transfer.to_owned(),
+      from: "bob".to_owned(),
+      name: "notes.txt".to_owned(),
+      size: content.len() as u64,
+      sha256: hex(&Sha256::digest(content)),
+      address: address.to_string(),
+    }
+  }
+
+  fn progress() -> (impl FnMut(Progress) + Send + 'static, Receiver<Progress>) {
+    let (sender, receiver) = mpsc::channel();
+    (move |v| { let _ = sender.send(v); }, receiver)
+  }
+
+  // everything reported up to the end of the transfer
+  fn until_done(progress: &Receiver<Progress>) -> Vec<Progress> {
+    let mut reported = Vec::new();
+    loop {
+      let v = progress.recv_timeout(WAIT).expect("the transfer didn't end");
+      let done = matches!(v, Progress::Finished(_) | Progress::Failed(_));
+      reported.push(v);
+      if done {
+        return reported;
+      }
+    }
+  }
+
+  fn read_line(stream: &TcpStream) -> String {
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).unwrap();
+    line.trim().to_owned()
+  }
+
+  #[test]
+  fn interrupted_transfer_resumes() {
+    let content = b"0123456789".repeat(100);
+    let half = content.len() / 2;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let address = listener.local_addr().unwrap();
+
+    // a sender whose first connection breaks halfway
+    let (requests, received) = mpsc::channel();
+    let sent = content.clone();
+    thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      requests.send(read_line(&stream)).unwrap();
+      stream.write_all(format!("{}\n", sent.len()).as_bytes()).unwrap();
+      stream.write_all(&sent[..half]).unwrap();
+      drop(stream);
+
+      let (mut stream, _) = listener.accept().unwrap();
+      requests.send(read_line(&stream)).unwrap();
+      stream.write_all(format!("{}\n", sent.len() - half).as_bytes()).unwrap();
+      stream.write_all(&sent[half..]).unwrap();
+      requests.send(read_line(&stream)).unwrap();
+    });
+
+    let directory = directory("resume");
+    let (on_progress, progress) = progress();
+    download(offer("transfer", address, &content), &directory, on_progress).unwrap();
+    let reported = until_done(&progress);
+
+    assert!(reported.contains(&Progress::Resuming(half as u64)), "{reported:?}");
+    assert_eq!(reported.last(), Some(&Progress::Finished(directory.join("notes.txt"))));
+    let requests: Vec<String> = received.iter().take(3).collect();
+    assert_eq!(requests, ["transfer 0".to_owned(), format!("transfer {half}"), "OK".to_owned()]);
+    assert_eq!(fs::read(directory.join("notes.txt")).unwrap(), content);
+    assert!(!directory.join("notes.txt.part").exists());
+    fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[test]
+  fn file_not_matching_its_hash_is_dropped() {
+    let source = directory("corrupted-source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("notes.txt"), b"the real notes").unwrap();
+    let (on_sent, sent) = progress();
+    let outgoing = Outgoing::listen(&source.join("notes.txt"), IpAddr::from([127, 0, 0, 1]), on_sent).unwrap();
+
+    // the hash of something else, as if the bytes got corrupted on the way
+    let address = SocketAddr::from(([127, 0, 0, 1], outgoing.port));
+    let offer = FileOffer { size: outgoing.size, ..offer(&outgoing.transfer, address, b"other notes!!!") };
+    let directory = directory("corrupted");
+    let (on_progress, progress) = progress();
+    download(offer, &directory, on_progress).unwrap();
+
+    let reported = until_done(&progress);
+    assert_eq!(reported.last(), Some(&Progress::Failed("the file didn't match its SHA-256".to_owned())));
+    let reported = until_done(&sent);
+    assert_eq!(reported.last(), Some(&Progress::Failed("the file didn't match its SHA-256".to_owned())));
+    assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+    fs::remove_dir_all(&directory).unwrap();
+    fs::remove_dir_all(&source).unwrap();
+  }
+
+  #[test]
+  fn names_leaving_the_directory_are_refused() {
+    let directory = directory("names");
+    let address = SocketAddr::from(([127, 0, 0, 1], 1));
+    for name in ["../notes.txt", "a/b", "..", "a\\b"] {
+      let offer = FileOffer { name: name.to_owned(), ..offer("transfer", address, b"notes") };
+      assert_eq!(download(offer, &directory, |_| {}).unwrap_err().kind(), ErrorKind::InvalidInput, "{name}");
+    }
+    assert!(!directory.exists());
+  }
+}
@@ -0,0 +1,198 @@
+use std::{
+    io::{self, BufRead, ErrorKind, Write},
+    process,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant}
+  };
+use client::{types::Capability, ChatClient, Event, Presence, SearchHit};
+use serde_json::{json, Value};
+
+use crate::{
+    service::Service,
+    settings::Settings
+  };
+
+// how long '--send' waits for the server to take or refuse the message
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Client without the terminal UI, for scripts and bots:
+// '--send' posts one message, '--headless' posts every stdin line
+// and prints incoming events to stdout as JSON lines
+pub struct Headless;
+
+impl Headless {
+  pub fn send(settings: &Settings, username: &str, text: &str) -> io::Result<()> {
+    let mut client = Service::connect(settings, username)?;
+
+    // peers and servers without warnings can't refuse a message, the server
+    // drops the user once the write side is closed, waiting for that makes
+    // sure the message got there
+    if settings.p2p || !client.server().supports(Capability::Warnings) {
+      client.send_message(text)?;
+      let events = client.events()?;
+      if client.finish().is_ok() {
+        events.for_each(drop);
+      }
+      return Ok(());
+    }
+
+    let result = Self::confirm(&mut client, username, text);
+    let _ = client.disconnect();
+    result
+  }
+
+  // the message is taken once it comes back after the history replay, which
+  // ends with the user's own join; a warning before that means it was refused
+  fn confirm(client: &mut ChatClient, username: &str, text: &str) -> io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    client.on_event(move |event| {
+      let _ = tx.send(event);
+    })?;
+    client.send_message(text)?;
+
+    let joined_notice = format!("{username} joined the chat!");
+    let deadline = Instant::now() + CONFIRM_TIMEOUT;
+    let mut joined = false;
+    loop {
+      let left = deadline.saturating_duration_since(Instant::now());
+      let event = rx.recv_timeout(left).map_err(|_| io::Error::new(
+        ErrorKind::TimedOut,
+        format!("Message was not confirmed within {} seconds", CONFIRM_TIMEOUT.as_secs())
+      ))?;
+      match event {
+        Event::Presence { username: user, presence: Presence::Joined, .. } if user == username => joined = true,
+        // servers before presence headers
+        Event::Notice { text: notice, .. } if notice == joined_notice => joined = true,
+        Event::Message { username: user, text: echo, .. } if joined && user == username && echo == text.trim() => {
+          return Ok(());
+        },
+        Event::Warning(reason) => {
+          return Err(io::Error::other(format!("Message rejected: {reason}")));
+        },
+        Event::Disconnected(reason) => {
+          let reason = reason.unwrap_or_else(|| "Connection closed".to_owned());
+          return Err(io::Error::new(ErrorKind::BrokenPipe, format!("{reason} before the message was confirmed")));
+        },
+        _ => {},
+      }
+    }
+  }
+
+  pub fn run(settings: &Settings, username: &str) -> io::Result<()> {
+    let mut client = Service::connect(settings, username)?;
+    let events = client.events()?;
+
+    let printer = thread::spawn(move || {
+      let mut stdout = io::stdout();
+      for event in events {
+        let Some(value) = Self::to_json(&event) else { continue };
+        if writeln!(stdout, "{value}").and_then(|_| stdout.flush()).is_err() {
+          break;
+        }
+      }
+    });
+
+    for line in io::stdin().lock().lines() {
+      let line = line?;
+      let text = line.trim();
+      if text.is_empty() {
+        continue;
+      }
+      if let Err(e) = client.send_message(text) {
+        eprintln!("Connection lost: {e}");
+        process::exit(1);
+      }
+    }
+
+    // stdin is over, the rest of the incoming messages is still printed
+    let _ = client.finish();
+    let _ = printer.join();
+    Ok(())
+  }
+
+  pub fn to_json(event: &Event) -> Option<Value> {
+    let value = match event {
+      Event::Message { id, username, text, timestamp, sequence, reply_to } => json!({
+        "type": "message",
+        "id": id,
+        "username": username,
+        "text": text,
+        "timestamp": timestamp,
+        "sequence": sequence,
+        "reply_to": reply_to,
+      }),
+      Event::Direct { id, from, to, text, timestamp, away } => json!({
+        "type": "direct",
+        "id": id,
+        "username": from,
+        "to": to,
+        "text": text,
+        "timestamp": timestamp,
+        "away": away,
+      }),
+      Event::Notice { id, text, timestamp, sequence } => json!({
+        "type": "notice",
+        "id": id,
+        "text": text,
+        "timestamp": timestamp,
+        "sequence": sequence,
+      }),
+      Event::Presence { username, presence, timestamp, sequence } => json!({
+        "type": "presence",
+        "username": username,
+        "presence": presence.to_string().to_lowercase(),
+        "timestamp": timestamp,
+        "sequence": sequence,
+      }),
+      Event::Reactions { id, reactions, own } => json!({
+        "type": "reactions",
+        "id": id,
+        "reactions": reactions.iter().map(|(text, count)| json!({
+          "reaction": text,
+          "count": count,
+          "own": own.contains(text),
+        })).collect::<Vec<Value>>(),
+      }),
+      Event::Warning(text) => json!({ "type": "warning", "text": text }),
+      Event::Stats(text) => json!({ "type": "stats", "text": text }),
+      Event::SearchResults(hits) => json!({
+        "type": "search_results",
+        "results": hits.iter().map(Self::hit_to_json).collect::<Vec<Value>>(),
+      }),
+      Event::History { thread, messages } => json!({
+        "type": "history",
+        "thread": thread,
+        "messages": messages.iter().map(Self::hit_to_json).collect::<Vec<Value>>(),
+      }),
+      Event::FileOffer(offer) => json!({
+        "type": "file_offer",
+        "transfer": offer.transfer,
+        "username": offer.from,
+        "name": offer.name,
+        "size": offer.size,
+        "sha256": offer.sha256,
+        "address": offer.address,
+      }),
+      Event::FileAnswer { transfer, from, accepted } => json!({
+        "type": "file_answer",
+        "transfer": transfer,
+        "username": from,
+        "accepted": accepted,
+      }),
+      Event::Disconnected(_) => return None,
+    };
+    Some(value)
+  }
+
+  fn hit_to_json(hit: &SearchHit) -> Value {
+    json!({
+      "id": hit.id,
+      "username": hit.username,
+      "text": hit.text,
+      "timestamp": hit.timestamp,
+      "sequence": hit.sequence,
+      "reply_to": hit.reply_to,
+    })
+  }
+}
@@ -0,0 +1,11 @@
+// Chat client library, the terminal client in 'main.rs' is built on it
+pub mod types;
+pub mod connection;
+mod compression;
+mod chatClient;
+mod fileTransfer;
+
+pub use chatClient::{ChatClient, Event, Events, SearchHit, SearchQuery};
+pub use connection::ServerInfo;
+pub use fileTransfer::{FileOffer, Outgoing, Progress};
+pub use types::Presence;
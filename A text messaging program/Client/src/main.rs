@@ -1,22 +1,53 @@
-use std::io;
+use std::{io::{self, ErrorKind}, process};
 
+use client::{connection, types};
+use headless::Headless;
 use service::Service;
 
 use crate::{
-  settings::Settings, 
+  settings::Settings,
   state::State
 };
 
 mod settings;
-mod types;
-mod connection;
+mod peer;
 mod state;
 mod service;
+mod headless;
+mod mentions;
+mod markup;
+mod transcript;
+mod search;
 
-fn main() -> io::Result<()> {
+// exit codes for scripts, 2 is used by the args parser;
+// a message refused by the server is an error too
+const EXIT_ERROR: i32 = 1;
+const EXIT_DENIED: i32 = 3;
+
+fn main() {
   let settings = Settings::new();
-  let state = State::new()?;
-  
+
+  if let Err(e) = run(settings) {
+    eprintln!("{e}");
+    process::exit(match e.kind() {
+      ErrorKind::PermissionDenied => EXIT_DENIED,
+      _ => EXIT_ERROR,
+    });
+  }
+}
+
+fn run(settings: Settings) -> io::Result<()> {
+  if let Some(username) = settings.username.clone() {
+    if let Some(text) = settings.send.clone() {
+      return Headless::send(&settings, &username, &text);
+    }
+    if settings.headless {
+      return Headless::run(&settings, &username);
+    }
+  }
+
+  let state = State::new(settings.username.clone())?;
+
   Service::run(settings, state)?;
   Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,298 @@
+use crossterm::style::{Attribute, Color, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor};
+
+use crate::mentions::Highlighter;
+
+// usernames get one of these, picked by a hash of the name
+const USERNAME_COLORS: [Color; 10] = [
+  Color::Red,
+  Color::Green,
+  Color::Yellow,
+  Color::Blue,
+  Color::Magenta,
+  Color::Cyan,
+  Color::DarkRed,
+  Color::DarkGreen,
+  Color::DarkYellow,
+  Color::DarkCyan,
+];
+
+// Text from the network may carry escape sequences which would take over
+// the terminal, so they are dropped whole before drawing together with
+// every other control character
+pub fn strip_controls(text: &str) -> String {
+  let mut stripped = String::with_capacity(text.len());
+  let mut chars = text.chars().peekable();
+  while let Some(c) = chars.next() {
+    // ESC with '@'..'_' is the 7-bit form of a C1 control, both go the same way
+    let c = match c {
+      '\x1b' => match chars.next() {
+        Some(v @ '@'..='_') => char::from_u32(u32::from(v) + 0x40).unwrap_or_default(),
+        // 'ESC ( B' and the like: intermediate bytes, then one final
+        Some(' '..='/') => {
+          while chars.next_if(|v| (' '..='/').contains(v)).is_some() {}
+          chars.next();
+          continue;
+        },
+        _ => continue,
+      },
+      c => c,
+    };
+    match c {
+      '\n' | '\t' => stripped.push(' '),
+      // CSI: parameters and intermediates up to the final byte
+      '\u{9b}' => {
+        for v in chars.by_ref() {
+          if !(' '..='?').contains(&v) {
+            break;
+          }
+        }
+      },
+      // OSC, DCS, SOS, PM and APC: a string up to BEL or ST
+      '\u{9d}' | '\u{90}' | '\u{98}' | '\u{9e}' | '\u{9f}' => {
+        while let Some(v) = chars.next() {
+          if v == '\x07' || v == '\u{9c}' {
+            break;
+          }
+          if v == '\x1b' {
+            chars.next_if_eq(&'\\');
+            break;
+          }
+        }
+      },
+      c if c.is_control() => {},
+      c => stripped.push(c),
+    }
+  }
+  stripped
+}
+
+// FNV-1a, the same name gets the same color on every run
+fn name_hash(name: &str) -> u32 {
+  name.bytes().fold(0x811c9dc5u32, |hash, v| (hash ^ u32::from(v)).wrapping_mul(0x01000193))
+}
+
+// ----- Styled text -----
+// everything the UI draws goes through here, so '--no-color'
+// turns off styling at one place
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+  color: bool,
+}
+
+impl Theme {
+  pub fn new(color: bool) -> Theme {
+    Theme { color }
+  }
+
+  fn styled(&self, text: &str, style: &str) -> String {
+    if self.color {
+      format!("{style}{text}{ResetColor}")
+    } else {
+      text.to_owned()
+    }
+  }
+
+  pub fn notice(&self, text: &str) -> String {
+    self.styled(&strip_controls(text), &format!("{}{}", SetAttribute(Attribute::Dim), SetAttribute(Attribute::Bold)))
+  }
+
+  pub fn dim(&self, text: &str) -> String {
+    self.styled(&strip_controls(text), &SetAttribute(Attribute::Dim).to_string())
+  }
+
+  pub fn warning(&self, text: &str) -> String {
+    self.styled(
+      &strip_controls(text),
+      &format!("{}{}", SetForegroundColor(Color::Yellow), SetAttribute(Attribute::Bold))
+    )
+  }
+
+  // marks messages with mentions of the local user
+  pub fn marker(&self) -> String {
+    self.styled("*", &format!("{}{}", SetForegroundColor(Color::Magenta), SetAttribute(Attribute::Bold)))
+  }
+
+  // points at the message picked for a reply or a thread
+  pub fn pointer(&self) -> String {
+    self.styled(">", &format!("{}{}", SetForegroundColor(Color::Cyan), SetAttribute(Attribute::Bold)))
+  }
+
+  pub fn username(&self, name: &str) -> String {
+    let name = strip_controls(name);
+    let color = USERNAME_COLORS[name_hash(&name) as usize % USERNAME_COLORS.len()];
+    self.styled(&name, &SetForegroundColor(color).to_string())
+  }
+
+  pub fn prompt(&self, text: &str) -> String {
+    self.styled(text, &format!("{}{}", SetBackgroundColor(Color::White), SetForegroundColor(Color::Black)))
+  }
+
+  // **bold**, *italic* or _italic_, `code`, [label](url) and bare links
+  pub fn message(&self, text: &str, highlighter: &Highlighter) -> String {
+    let text = strip_controls(text);
+    if !self.color {
+      return text;
+    }
+
+    let mut rendered = String::new();
+    let mut plain = String::new();
+    let mut index = 0;
+    while let Some(c) = text[index..].chars().next() {
+      let Some((styled, length)) = self.span(&text, index) else {
+        plain.push(c);
+        index += c.len_utf8();
+        continue;
+      };
+      rendered.push_str(&highlighter.render(&plain, self));
+      plain.clear();
+      rendered.push_str(&styled);
+      index += length;
+    }
+    rendered.push_str(&highlighter.render(&plain, self));
+    rendered
+  }
+
+  // styled span starting at byte 'start' and its length in bytes
+  fn span(&self, text: &str, start: usize) -> Option<(String, usize)> {
+    let rest = &text[start..];
+    let word_start = !text[..start].chars().next_back().is_some_and(|v| v.is_alphanumeric());
+
+    if rest.starts_with('`') {
+      let (inner, length) = Self::enclosed(rest, "`")?;
+      return Some((self.styled(inner, &SetForegroundColor(Color::Cyan).to_string()), length));
+    }
+    if rest.starts_with("**") {
+      let (inner, length) = Self::enclosed(rest, "**")?;
+      return Some((self.styled(inner, &SetAttribute(Attribute::Bold).to_string()), length));
+    }
+    // '_' inside words is part of names like snake_case
+    if rest.starts_with('*') || (rest.starts_with('_') && word_start) {
+      let (inner, length) = Self::enclosed(rest, &rest[..1])?;
+      return Some((self.styled(inner, &SetAttribute(Attribute::Italic).to_string()), length));
+    }
+    if rest.starts_with('[') {
+      let label_end = rest.find("](")?;
+      let url_end = rest[label_end..].find(')')? + label_end;
+      let label = &rest[1..label_end];
+      let url = &rest[label_end + 2..url_end];
+      if label.is_empty() || !Self::is_link(url) {
+        return None;
+      }
+      let styled = format!("{} ({})", self.link(label), self.dim(url));
+      return Some((styled, url_end + 1));
+    }
+    if word_start && Self::is_link(rest) {
+      let url = rest.split(char::is_whitespace).next().unwrap_or_default();
+      // sentence punctuation after a link is not a part of it
+      let url = url.trim_end_matches(['.', ',', '!', '?', ')', ';', ':']);
+      return Some((self.link(url), url.len()));
+    }
+    None
+  }
+
+  // text between 'delimiter' at the start and the next one, not empty
+  // and not padded with spaces, so '2 * 3 * 4' stays as it is
+  fn enclosed<'a>(text: &'a str, delimiter: &str) -> Option<(&'a str, usize)> {
+    let body = &text[delimiter.len()..];
+    let end = body.find(delimiter)?;
+    let inner = &body[..end];
+    if inner.is_empty() || inner.starts_with(' ') || inner.ends_with(' ') {
+      return None;
+    }
+    Some((inner, delimiter.len() * 2 + inner.len()))
+  }
+
+  fn is_link(text: &str) -> bool {
+    text.starts_with("https://") || text.starts_with("http://")
+  }
+
+  fn link(&self, text: &str) -> String {
+    self.styled(text, &format!("{}{}", SetForegroundColor(Color::Blue), SetAttribute(Attribute::Underlined)))
+  }
+
+  // local user's mentions stand out, others are just bold
+  pub fn mention(&self, mention: &str, is_local_user: bool) -> String {
+    if is_local_user {
+      self.styled(mention, &format!("{}{}", SetForegroundColor(Color::Magenta), SetAttribute(Attribute::Bold)))
+    } else {
+      self.styled(mention, &SetAttribute(Attribute::Bold).to_string())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn render(text: &str) -> String {
+    Theme::new(true).message(text, &Highlighter::new("alice", &[]))
+  }
+
+  #[test]
+  fn escape_sequences_are_dropped_whole() {
+    // CSI colors, cursor moves and screen clearing
+    assert_eq!(strip_controls("\x1b[31mred\x1b[0m"), "red");
+    assert_eq!(strip_controls("a\x1b[2J\x1b[1;1Hb"), "ab");
+    assert_eq!(strip_controls("\x1b[?1049hhidden"), "hidden");
+    // OSC window titles and hyperlinks, ended by BEL or ST
+    assert_eq!(strip_controls("\x1b]0;pwned\x07title"), "title");
+    assert_eq!(strip_controls("\x1b]8;;https://evil\x1b\\link\x1b]8;;\x1b\\"), "link");
+    // DCS and a charset switch
+    assert_eq!(strip_controls("\x1bPq#0;2;0;0;0\x1b\\ok"), "ok");
+    assert_eq!(strip_controls("\x1b(Bok"), "ok");
+    // reset, and an ESC at the end
+    assert_eq!(strip_controls("\x1bcok\x1b"), "ok");
+  }
+
+  #[test]
+  fn c1_controls_are_dropped() {
+    assert_eq!(strip_controls("\u{9b}31mred"), "red");
+    assert_eq!(strip_controls("\u{9d}0;pwned\u{9c}title"), "title");
+    assert_eq!(strip_controls("a\u{85}b\u{8d}c"), "abc");
+  }
+
+  #[test]
+  fn other_controls_are_dropped_or_spaced() {
+    assert_eq!(strip_controls("ding\x07 dong\x08\x00\x7f"), "ding dong");
+    assert_eq!(strip_controls("one\ntwo\tthree\rfour"), "one two threefour");
+    assert_eq!(strip_controls("plain ünïcode ✓ [31m"), "plain ünïcode ✓ [31m");
+  }
+
+  #[test]
+  fn no_color_theme_only_strips() {
+    let text = Theme::new(false).message("**bold** \x1b[31m`code`", &Highlighter::new("alice", &[]));
+    assert_eq!(text, "**bold** `code`");
+  }
+
+  #[test]
+  fn spans_are_styled() {
+    let theme = Theme::new(true);
+    let bold = SetAttribute(Attribute::Bold).to_string();
+    let italic = SetAttribute(Attribute::Italic).to_string();
+    let code = SetForegroundColor(Color::Cyan).to_string();
+
+    assert_eq!(render("**bold** end"), format!("{} end", theme.styled("bold", &bold)));
+    assert_eq!(render("*it* and _it_"), format!("{} and {}", theme.styled("it", &italic), theme.styled("it", &italic)));
+    assert_eq!(render("run `a * b`"), format!("run {}", theme.styled("a * b", &code)));
+  }
+
+  #[test]
+  fn lone_delimiters_stay_as_they_are() {
+    assert_eq!(render("2 * 3 * 4"), "2 * 3 * 4");
+    assert_eq!(render("snake_case_name"), "snake_case_name");
+    assert_eq!(render("**unclosed"), "**unclosed");
+    assert_eq!(render("``"), "``");
+  }
+
+  #[test]
+  fn links_are_styled_without_punctuation() {
+    let theme = Theme::new(true);
+    assert_eq!(render("see https://example.com."), format!("see {}.", theme.link("https://example.com")));
+    assert_eq!(
+      render("[docs](https://example.com/a)"),
+      format!("{} ({})", theme.link("docs"), theme.dim("https://example.com/a"))
+    );
+    // only web links become links
+    assert_eq!(render("[x](javascript:alert)"), "[x](javascript:alert)");
+  }
+}
@@ -0,0 +1,127 @@
+use crate::markup::Theme;
+
+// characters allowed in '@name', the rest ends the mention
+fn is_name_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+// Finds '@name' mentions and the configured keywords in messages,
+// matching is case insensitive
+pub struct Highlighter {
+  username: String,
+  keywords: Vec<String>,
+}
+
+impl Highlighter {
+  pub fn new(username: &str, keywords: &[String]) -> Highlighter {
+    Highlighter {
+      username: username.to_lowercase(),
+      keywords: keywords.iter()
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect(),
+    }
+  }
+
+  // names mentioned in the text, without '@'
+  pub fn mentions(text: &str) -> Vec<&str> {
+    let mut mentions = Vec::new();
+    for (index, _) in text.match_indices('@') {
+      // 'mail@example.com' is not a mention
+      if text[..index].chars().next_back().is_some_and(is_name_char) {
+        continue;
+      }
+      let rest = &text[index + 1..];
+      let end = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+      // a trailing dot ends the sentence, not the name
+      let name = rest[..end].trim_end_matches('.');
+      if !name.is_empty() {
+        mentions.push(name);
+      }
+    }
+    mentions
+  }
+
+  // true if the local user is mentioned or a keyword is used
+  pub fn is_highlighted(&self, text: &str) -> bool {
+    if Self::mentions(text).iter().any(|v| v.to_lowercase() == self.username) {
+      return true;
+    }
+    let words: Vec<String> = text
+      .split(|c: char| !is_name_char(c))
+      .map(|v| v.trim_end_matches('.').to_lowercase())
+      .collect();
+    self.keywords.iter().any(|v| words.contains(v))
+  }
+
+  // plain text with mentions styled by the theme
+  pub fn render(&self, text: &str, theme: &Theme) -> String {
+    let mut rendered = String::new();
+    let mut rest = text;
+    for name in Self::mentions(text) {
+      let mention = format!("@{name}");
+      let Some(index) = rest.find(&mention) else { continue };
+      rendered.push_str(&rest[..index]);
+      rendered.push_str(&theme.mention(&mention, name.to_lowercase() == self.username));
+      rest = &rest[index + mention.len()..];
+    }
+    rendered.push_str(rest);
+    rendered
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn highlighter() -> Highlighter {
+    Highlighter::new("Alice", &["Deploy".to_owned(), " ".to_owned()])
+  }
+
+  #[test]
+  fn mentions_end_at_the_name() {
+    assert_eq!(Highlighter::mentions("@bob, @carol: hi @dave."), ["bob", "carol", "dave"]);
+    assert_eq!(Highlighter::mentions("ask @john.smith or @jane_doe-2"), ["john.smith", "jane_doe-2"]);
+    assert_eq!(Highlighter::mentions("(@bob)"), ["bob"]);
+  }
+
+  #[test]
+  fn addresses_and_lone_signs_are_not_mentions() {
+    assert!(Highlighter::mentions("mail alice@example.com").is_empty());
+    assert!(Highlighter::mentions("meet @ 5, @ noon, @.").is_empty());
+  }
+
+  #[test]
+  fn own_name_is_highlighted_in_any_case() {
+    let highlighter = highlighter();
+    assert!(highlighter.is_highlighted("@alice look"));
+    assert!(highlighter.is_highlighted("look, @ALICE."));
+    assert!(!highlighter.is_highlighted("@alicia look"));
+    assert!(!highlighter.is_highlighted("@bob look"));
+    // the name without '@' is not a mention
+    assert!(!highlighter.is_highlighted("alice said hi"));
+    assert!(!highlighter.is_highlighted("mail alice@example.com"));
+  }
+
+  #[test]
+  fn keywords_match_whole_words() {
+    let highlighter = highlighter();
+    assert!(highlighter.is_highlighted("time to deploy"));
+    assert!(highlighter.is_highlighted("DEPLOY."));
+    assert!(!highlighter.is_highlighted("deployment is done"));
+    assert!(!highlighter.is_highlighted("redeploy it"));
+    // blank keywords are dropped
+    assert!(!highlighter.is_highlighted("a b  c"));
+  }
+
+  #[test]
+  fn render_styles_own_mentions_apart() {
+    let highlighter = highlighter();
+    let theme = Theme::new(true);
+    let rendered = highlighter.render("@Alice and @bob", &theme);
+    assert_eq!(rendered, format!("{} and {}", theme.mention("@Alice", true), theme.mention("@bob", false)));
+    assert_ne!(theme.mention("@bob", true), theme.mention("@bob", false));
+
+    assert_eq!(highlighter.render("@Alice and @bob", &Theme::new(false)), "@Alice and @bob");
+  }
+}
@@ -0,0 +1,466 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, Error, ErrorKind, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpListener, TcpStream, UdpSocket},
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::Duration
+  };
+use parking_lot::Mutex;
+use socket2::{Domain, Protocol, Socket, Type};
+use uuid::Uuid;
+
+use crate::{
+    connection::Connection,
+    settings::Settings,
+    types::{
+      Signal,
+      SignalsData,
+      SignalsHeader
+    }
+  };
+
+const ANNOUNCE_PREFIX: &str = "CHAT_PEER";
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+const SEEN_IDS_LIMIT: usize = 1024;
+// a peer that doesn't take a frame in this time is skipped for it
+const PEER_WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// ids of the last delivered messages, so copies flooded through
+// the other peers are dropped
+struct SeenIds {
+  ids: HashSet<String>,
+  order: VecDeque<String>,
+}
+
+impl SeenIds {
+  fn new() -> SeenIds {
+    SeenIds {
+      ids: HashSet::new(),
+      order: VecDeque::new(),
+    }
+  }
+
+  // returns false if the id was already seen
+  fn insert(&mut self, id: &str) -> bool {
+    if self.ids.contains(id) {
+      return false;
+    }
+    if self.order.len() == SEEN_IDS_LIMIT {
+      if let Some(old) = self.order.pop_front() {
+        self.ids.remove(&old);
+      }
+    }
+    self.ids.insert(id.to_owned());
+    self.order.push_back(id.to_owned());
+    true
+  }
+}
+
+struct PeerLink {
+  // made up by the peer that dialed, so both ends know it
+  link_id: String,
+  // node id of the peer that dialed
+  dialer: String,
+  stream: TcpStream,
+}
+
+impl PeerLink {
+  // of two links between the same peers, both of them keep this one
+  fn wins_over(&self, other: &PeerLink) -> bool {
+    (&self.dialer, &self.link_id) < (&other.dialer, &other.link_id)
+  }
+}
+
+// Serverless node: keeps direct links with every other peer and hands
+// messages to the UI through a local connection which speaks the same
+// protocol as the server does
+#[derive(Clone)]
+pub struct Mesh {
+  node_id: String,
+  username: String,
+  listen_port: u16,
+  peers: Arc<Mutex<HashMap<String, PeerLink>>>,
+  // discovered peers being dialed
+  dialing: Arc<Mutex<HashSet<String>>>,
+  seen: Arc<Mutex<SeenIds>>,
+  local: Arc<Mutex<TcpStream>>,
+}
+
+impl Mesh {
+  pub fn start(settings: &Settings, username: &str) -> io::Result<Connection> {
+    let listener = TcpListener::bind(("0.0.0.0", settings.listen_port))?;
+    let listen_port = listener.local_addr()?.port();
+
+    // local link between the UI and the mesh
+    let local_listener = TcpListener::bind(("127.0.0.1", 0))?;
+    let ui_stream = TcpStream::connect(local_listener.local_addr()?)?;
+    let (local_stream, _) = local_listener.accept()?;
+
+    let mesh = Mesh {
+      node_id: Uuid::new_v4().to_string(),
+      username: username.to_owned(),
+      listen_port,
+      peers: Arc::new(Mutex::new(HashMap::new())),
+      dialing: Arc::new(Mutex::new(HashSet::new())),
+      seen: Arc::new(Mutex::new(SeenIds::new())),
+      local: Arc::new(Mutex::new(local_stream.try_clone()?)),
+    };
+
+    let cloned = mesh.clone();
+    thread::spawn(move || cloned.accept_peers(listener));
+
+    let cloned = mesh.clone();
+    let local_connection = Connection::from_stream(local_stream)?;
+    thread::spawn(move || cloned.process_local(local_connection));
+
+    if let Ok(socket) = Self::discovery_socket(settings.discovery_port) {
+      let cloned = mesh.clone();
+      let announce_socket = socket.try_clone()?;
+      let discovery_port = settings.discovery_port;
+      thread::spawn(move || cloned.announce(announce_socket, discovery_port));
+
+      let cloned = mesh.clone();
+      thread::spawn(move || cloned.discover(socket));
+    }
+
+    for address in settings.peers.iter() {
+      let cloned = mesh.clone();
+      let address = address.to_owned();
+      thread::spawn(move || cloned.connect_peer(&address));
+    }
+
+    mesh.notify(&format!("Waiting for peers on port {listen_port}"))?;
+
+    Connection::from_stream(ui_stream)
+  }
+
+  fn accept_peers(self, listener: TcpListener) {
+    for stream in listener.incoming() {
+      let stream = match stream {
+        Ok(v) => v,
+        Err(_) => continue
+      };
+      let cloned = self.clone();
+      thread::spawn(move || cloned.process_peer(stream, false));
+    }
+  }
+
+  fn connect_peer(self, address: &str) -> io::Result<()> {
+    let stream = Connection::dial(address)?;
+    self.process_peer(stream, true)
+  }
+
+  fn process_peer(self, stream: TcpStream, dialed: bool) -> io::Result<()> {
+    // both sides introduce themselves right after connecting,
+    // the one that dialed names the link
+    let link_id = Uuid::new_v4().to_string();
+    let mut headers = vec![
+      SignalsHeader::signalType(Signal::Connection),
+      SignalsHeader::username(self.username.clone()),
+      SignalsHeader::key(self.node_id.clone())
+    ];
+    if dialed {
+      headers.push(SignalsHeader::id(link_id.clone()));
+    }
+    (&stream).write_all(SignalsData::new(headers, None).to_string().as_bytes())?;
+
+    let mut connection = Connection::from_stream(stream.try_clone()?)?;
+    let data = SignalsData::from_str(&connection.readSignal()?)
+      .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let (peer_id, peer_name, peer_link_id) = match (data.signalType, data.key, data.username) {
+      (Some(Signal::Connection), Some(k), Some(u)) => (k, u, data.id),
+      _ => return Err(Error::new(ErrorKind::InvalidData, "invalid peer handshake"))
+    };
+    if peer_id == self.node_id {
+      return Ok(())
+    }
+
+    stream.set_write_timeout(Some(PEER_WRITE_TIMEOUT))?;
+    let link = PeerLink {
+      // older peers don't name links, then each side has its own name
+      link_id: if dialed { link_id } else { peer_link_id.unwrap_or(link_id) },
+      dialer: if dialed { self.node_id.clone() } else { peer_id.clone() },
+      stream: stream.try_clone()?,
+    };
+    let link_id = link.link_id.clone();
+    match self.add_link(&peer_id, link) {
+      Some(true) => self.notify(&format!("{peer_name} joined the chat!"))?,
+      Some(false) => {},
+      None => return stream.shutdown(Shutdown::Both),
+    }
+
+    while let Ok(raw) = connection.readSignal() {
+      if let Ok(data) = SignalsData::from_str(&raw) {
+        self.deliver(data, Some(&peer_id));
+      }
+    }
+
+    // a link replaced by another one is not the peer leaving
+    let replaced = self.peers.lock().get(&peer_id).is_some_and(|v| v.link_id != link_id);
+    self.drop_link(&peer_id, &link_id);
+    if replaced {
+      return Ok(())
+    }
+    self.notify(&format!("{peer_name} left the chat!"))
+  }
+
+  // peers dialing each other at once get two links, both sides keep the
+  // same one and close the other; None if 'link' is to be closed, else
+  // true if the peer is new
+  fn add_link(&self, peer_id: &str, link: PeerLink) -> Option<bool> {
+    let mut peers = self.peers.lock();
+    match peers.get(peer_id) {
+      Some(old) if !link.wins_over(old) => return None,
+      Some(old) => {
+        let _ = old.stream.shutdown(Shutdown::Both);
+      },
+      None => {},
+    }
+    Some(peers.insert(peer_id.to_owned(), link).is_none())
+  }
+
+  // closes the link if it's still the one of the peer, the thread
+  // reading it tells the UI the peer left
+  fn drop_link(&self, peer_id: &str, link_id: &str) {
+    let mut peers = self.peers.lock();
+    if peers.get(peer_id).is_some_and(|v| v.link_id == link_id) {
+      if let Some(link) = peers.remove(peer_id) {
+        let _ = link.stream.shutdown(Shutdown::Both);
+      }
+    }
+  }
+
+  fn process_local(self, mut connection: Connection) {
+    while let Ok(raw) = connection.readSignal() {
+      if let Ok(mut data) = SignalsData::from_str(&raw) {
+        data.username = Some(self.username.clone());
+        data.id = None;
+        data.message = data.message.map(|v| v.trim().to_owned());
+        self.deliver(data, None);
+      }
+    }
+  }
+
+  // passes message to the UI and floods it to the rest of the mesh
+  fn deliver(&self, mut data: SignalsData, from_peer: Option<&str>) {
+    if !matches!(data.signalType, Some(Signal::Message)) || !data.withMess {
+      return;
+    }
+    let id = data.id.get_or_insert_with(|| Uuid::new_v4().to_string()).clone();
+    if !self.seen.lock().insert(&id) {
+      return;
+    }
+    data.serverMess = false;
+
+    let frame = data.to_string();
+    // written to outside of the lock, a stalled peer holds up only this message
+    let links: Vec<(String, String, TcpStream)> = self.peers.lock().iter()
+      .filter(|(peer_id, _)| Some(peer_id.as_str()) != from_peer)
+      .filter_map(|(peer_id, link)| Some((peer_id.clone(), link.link_id.clone(), link.stream.try_clone().ok()?)))
+      .collect();
+    for (peer_id, link_id, mut stream) in links {
+      // a write that failed or timed out may have left half a frame,
+      // nothing sent after it would make sense to the peer
+      if stream.write_all(frame.as_bytes()).is_err() {
+        self.drop_link(&peer_id, &link_id);
+      }
+    }
+    let _ = self.local.lock().write_all(frame.as_bytes());
+  }
+
+  // shows a server-like notice in the UI
+  fn notify(&self, text: &str) -> io::Result<()> {
+    let signal = SignalsData::new(
+      vec![
+        SignalsHeader::signalType(Signal::Message),
+        SignalsHeader::id(Uuid::new_v4().to_string()),
+        SignalsHeader::withMess,
+        SignalsHeader::serverMess
+      ],
+      Some(text)
+    );
+    self.local.lock().write_all(signal.to_string().as_bytes())
+  }
+
+  fn discovery_socket(port: u16) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // several clients on one machine share the discovery port
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_broadcast(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into())?;
+    Ok(socket.into())
+  }
+
+  fn announce(self, socket: UdpSocket, port: u16) {
+    let datagram = format!("{ANNOUNCE_PREFIX} {} {}", self.node_id, self.listen_port);
+    loop {
+      let _ = socket.send_to(datagram.as_bytes(), (Ipv4Addr::BROADCAST, port));
+      thread::sleep(ANNOUNCE_INTERVAL);
+    }
+  }
+
+  fn discover(self, socket: UdpSocket) {
+    let mut buf = [0u8; 256];
+    while let Ok((len, from)) = socket.recv_from(&mut buf) {
+      let datagram = String::from_utf8_lossy(&buf[..len]).to_string();
+      let mut parts = datagram.split_whitespace();
+      let (peer_id, port) = match (parts.next(), parts.next(), parts.next().map(|v| v.parse::<u16>())) {
+        (Some(ANNOUNCE_PREFIX), Some(id), Some(Ok(port))) => (id.to_owned(), port),
+        _ => continue
+      };
+
+      // only the peer with the smaller id dials, so a pair is linked once,
+      // and announces coming during a dial don't start another one
+      if self.node_id >= peer_id
+        || self.peers.lock().contains_key(&peer_id)
+        || !self.dialing.lock().insert(peer_id.clone()) {
+        continue;
+      }
+      let cloned = self.clone();
+      let address = SocketAddr::new(from.ip(), port).to_string();
+      thread::spawn(move || {
+        let dialing = cloned.dialing.clone();
+        let _ = cloned.connect_peer(&address);
+        dialing.lock().remove(&peer_id);
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Read;
+
+  use super::*;
+
+  // both ends of a loopback connection
+  fn pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    server.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    (client, server)
+  }
+
+  fn read_all(stream: &mut TcpStream) -> String {
+    let mut received = Vec::new();
+    let mut buf = [0u8; 4096];
+    while let Ok(v) = stream.read(&mut buf) {
+      if v == 0 {
+        break;
+      }
+      received.extend_from_slice(&buf[..v]);
+    }
+    String::from_utf8_lossy(&received).to_string()
+  }
+
+  fn mesh(local: TcpStream) -> Mesh {
+    Mesh {
+      node_id: "node".to_owned(),
+      username: "alice".to_owned(),
+      listen_port: 0,
+      peers: Arc::new(Mutex::new(HashMap::new())),
+      dialing: Arc::new(Mutex::new(HashSet::new())),
+      seen: Arc::new(Mutex::new(SeenIds::new())),
+      local: Arc::new(Mutex::new(local)),
+    }
+  }
+
+  fn link(link_id: &str, dialer: &str, stream: TcpStream) -> PeerLink {
+    PeerLink { link_id: link_id.to_owned(), dialer: dialer.to_owned(), stream }
+  }
+
+  fn message(id: &str, text: &str) -> SignalsData {
+    SignalsData::new(
+      vec![
+        SignalsHeader::signalType(Signal::Message),
+        SignalsHeader::id(id.to_owned()),
+        SignalsHeader::withMess
+      ],
+      Some(text)
+    )
+  }
+
+  #[test]
+  fn seen_ids_refuse_repeats() {
+    let mut seen = SeenIds::new();
+    assert!(seen.insert("a"));
+    assert!(seen.insert("b"));
+    assert!(!seen.insert("a"));
+  }
+
+  #[test]
+  fn seen_ids_forget_the_oldest() {
+    let mut seen = SeenIds::new();
+    for v in 0..=SEEN_IDS_LIMIT {
+      assert!(seen.insert(&v.to_string()));
+    }
+    assert_eq!(seen.ids.len(), SEEN_IDS_LIMIT);
+    assert!(seen.insert("0"));
+    assert!(!seen.insert(&SEEN_IDS_LIMIT.to_string()));
+  }
+
+  #[test]
+  fn copies_are_delivered_once_and_not_sent_back() {
+    let (local, mut ui) = pair();
+    let mesh = mesh(local);
+    let (bob_link, mut bob) = pair();
+    let (carol_link, mut carol) = pair();
+    mesh.peers.lock().insert("bob".to_owned(), link("1", "node", bob_link));
+    mesh.peers.lock().insert("carol".to_owned(), link("2", "node", carol_link));
+
+    // bob's message comes again through carol
+    mesh.deliver(message("m1", "hello"), Some("bob"));
+    mesh.deliver(message("m1", "hello"), Some("carol"));
+
+    assert_eq!(read_all(&mut ui).matches("hello").count(), 1);
+    assert_eq!(read_all(&mut carol).matches("hello").count(), 1);
+    assert_eq!(read_all(&mut bob).matches("hello").count(), 0);
+  }
+
+  #[test]
+  fn failed_write_drops_the_link() {
+    let (local, mut ui) = pair();
+    let mesh = mesh(local);
+    let (bob_link, _bob) = pair();
+    let (carol_link, mut carol) = pair();
+    // writes to bob fail from now on
+    bob_link.shutdown(Shutdown::Write).unwrap();
+    mesh.peers.lock().insert("bob".to_owned(), link("1", "node", bob_link));
+    mesh.peers.lock().insert("carol".to_owned(), link("2", "node", carol_link));
+
+    mesh.deliver(message("m1", "hello"), None);
+    assert!(!mesh.peers.lock().contains_key("bob"));
+    assert!(mesh.peers.lock().contains_key("carol"));
+    assert_eq!(read_all(&mut carol).matches("hello").count(), 1);
+    assert_eq!(read_all(&mut ui).matches("hello").count(), 1);
+  }
+
+  #[test]
+  fn peers_dialing_each_other_keep_the_same_link() {
+    // 'bob' dialed the link named 'b', this node the one named 'a'
+    for bob_first in [true, false] {
+      let (local, _ui) = pair();
+      let mesh = mesh(local);
+      let (ours, mut ours_far) = pair();
+      let (bobs, _bobs_far) = pair();
+      let (ours, bobs) = (link("a", "node", ours), link("b", "bob", bobs));
+
+      if bob_first {
+        assert_eq!(mesh.add_link("bob", bobs), Some(true));
+        assert_eq!(mesh.add_link("bob", ours), None);
+      }
+      else {
+        assert_eq!(mesh.add_link("bob", ours), Some(true));
+        assert_eq!(mesh.add_link("bob", bobs), Some(false));
+        // the replaced link is closed
+        assert_eq!(read_all(&mut ours_far), "");
+      }
+      assert_eq!(mesh.peers.lock()["bob"].link_id, "b");
+    }
+  }
+}
@@ -0,0 +1,44 @@
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use client::SearchQuery;
+
+// '/search [from:name] [since:time] [until:time] text', where time is
+// '30m', '2h' or '7d' ago, a local date '2024-03-01' or '2024-03-01T14:30'
+pub fn parse_command(args: &str) -> Result<SearchQuery, String> {
+  let mut query = SearchQuery::default();
+  let mut words = Vec::new();
+  for word in args.split_whitespace() {
+    match word.split_once(':') {
+      Some(("from", v)) if !v.is_empty() => query.author = Some(v.trim_start_matches('@').to_owned()),
+      Some(("since", v)) => query.since = Some(parse_time(v)?),
+      Some(("until", v)) => query.until = Some(parse_time(v)?),
+      _ => words.push(word),
+    }
+  }
+  query.text = words.join(" ");
+
+  if query.text.is_empty() && query.author.is_none() {
+    return Err("Usage: /search [from:name] [since:time] [until:time] text".to_owned());
+  }
+  Ok(query)
+}
+
+// UTC milliseconds of a relative or a local time
+fn parse_time(value: &str) -> Result<u64, String> {
+  let invalid = || format!("'{value}' is not a time, use e.g. 2h, 7d, 2024-03-01 or 2024-03-01T14:30");
+
+  let time = if let Some(amount) = value.strip_suffix(['m', 'h', 'd']) {
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let duration = match value.chars().last() {
+      Some('m') => Duration::try_minutes(amount),
+      Some('h') => Duration::try_hours(amount),
+      _ => Duration::try_days(amount),
+    };
+    Local::now() - duration.ok_or_else(invalid)?
+  } else {
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+      .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|v| v.and_hms_opt(0, 0, 0).unwrap_or_default()))
+      .map_err(|_| invalid())?;
+    Local.from_local_datetime(&naive).earliest().ok_or_else(invalid)?
+  };
+  u64::try_from(time.timestamp_millis()).map_err(|_| invalid())
+}
@@ -1,39 +1,69 @@
 use std::{
+    collections::HashMap,
+    fs,
+    str::FromStr,
     thread, 
+    time::Duration,
     io::{self, Write},
-    str::FromStr
+    path::PathBuf,
+    sync::Arc
   };
-use crossterm::{event::{self, Event, KeyCode}, execute, style::{Attribute, Color, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor}, terminal::{self, enable_raw_mode, ClearType}};
+use crossterm::{event::{self, Event, KeyCode}, execute, terminal::{self, enable_raw_mode, ClearType}};
+
+use chrono::{DateTime, Local};
+use parking_lot::Mutex;
+use client::{ChatClient, Event as ChatEvent, Presence, Progress};
+use uuid::Uuid;
 
 use crate::{
     settings::Settings, 
-    state::State, 
-    connection::Connection, 
-    types::{
-      Signal, 
-      SignalsData, 
-      SignalsHeader
-    }
+    state::{ChatLine, State}, 
+    types::Capability,
+    connection::{Connection, CLIENT_CAPABILITIES}, 
+    markup::{strip_controls, Theme},
+    mentions::Highlighter,
+    search,
+    peer::Mesh,
+    transcript::{Format, Transcript}
   };
   
+// characters of a message quoted in a reply
+const QUOTE_LENGTH: usize = 40;
+
 pub struct Service {
-    pub connection: Connection,
+    pub client: ChatClient,
     pub settings: Settings,
     pub state: State,
+    pub transcript: Arc<Mutex<Transcript>>,
   }
   
 impl Service {
     pub fn run(settings: Settings, state: State) -> io::Result<()> {
-      let connection = Connection::new(
-        &settings.server_address.to_owned(), 
-        &state.username
-      )?;
+      let client = Self::connect(&settings, &state.username)?;
+      let mut transcript = Transcript::new();
+      let log = match &settings.transcript_dir {
+        Some(directory) => {
+          let server = match &client.server().name {
+            _ if settings.p2p => "p2p".to_owned(),
+            Some(name) => name.to_owned(),
+            None => settings.server_address.clone(),
+          };
+          Some(transcript.log_to(directory, &server, &state.username)?)
+        },
+        None => None,
+      };
   
       let mut instance = Service {
-        connection,
+        client,
         settings,
-        state
+        state,
+        transcript: Arc::new(Mutex::new(transcript)),
       }.enable_print();
+
+      instance.show_server_info();
+      if let Some(path) = log {
+        instance.show_notice(&format!("Logging to {}", path.display()));
+      }
   
       instance.proccess_incoming_messages();
       instance.read_inputs();
@@ -41,60 +71,301 @@ impl Service {
       Ok(())
     }
 
+    pub fn connect(settings: &Settings, username: &str) -> io::Result<ChatClient> {
+      if settings.p2p {
+        Ok(ChatClient::from_connection(Mesh::start(settings, username)?, username))
+      }
+      else {
+        Ok(ChatClient::from_connection(Connection::new(&settings.server_address, username, settings.token.as_deref())?, username))
+      }
+    }
+
+    fn show_server_info(&self) {
+      let server = self.client.server();
+      let mut notices = Vec::new();
+      if let Some(name) = &server.name {
+        notices.push(format!("Connected to {name} (protocol v{})", server.version));
+      }
+      // features the server lacks are just not used
+      let missing: Vec<String> = CLIENT_CAPABILITIES.iter()
+        .filter(|v| !server.supports(**v))
+        .map(|v| v.to_string())
+        .collect();
+      if !self.settings.p2p && !missing.is_empty() {
+        notices.push(format!("Server doesn't support {}", missing.join(", ")));
+      }
+      if let Some(motd) = &server.motd {
+        notices.push(motd.to_owned());
+      }
+
+      let theme = self.theme();
+      let mut messages = self.state.messagesThr.lock();
+      for notice in notices {
+        messages.push(theme.notice(&notice));
+      }
+      let _ = self.state.chatReloadTX.send(());
+    }
+
     pub fn proccess_incoming_messages(&self) {
       let messages = self.state.messagesThr.clone();
       let tx = self.state.chatReloadTX.clone();
-      let mut connection = self.connection.clone();
-      thread::spawn(move || -> io::Result<()> {
-        loop {
-          let data_from_socket = match connection.readSignal() {
-            Ok(v) => v,
-            Err(_) => break
-          };
-          let signal = SignalsData::from_str(&data_from_socket);
+      let time_format = self.settings.time_format.clone();
+      let highlighter = Highlighter::new(&self.state.username, &self.settings.highlight);
+      let local_username = self.state.username.clone();
+      let bell = self.settings.bell;
+      let scroll_offset = self.state.scrollOffset.clone();
+      let unread_mentions = self.state.unreadMentions.clone();
+      let theme = self.theme();
+      let transcript = self.transcript.clone();
+      let search_results = self.state.searchResults.clone();
+      let chat_messages = self.state.chatMessages.clone();
+      let reaction_lines = self.state.reactionLines.clone();
+      let file_offers = self.state.fileOffers.clone();
+      let outgoing_files = self.state.outgoingFiles.clone();
+      let events = match self.client.events() {
+        Ok(v) => v,
+        Err(_) => return
+      };
+      thread::spawn(move || {
+        for event in events {
+          transcript.lock().record(&event);
           let mut messages = messages.lock();
-          if let Ok(s) = signal {
-            if let Some(Signal::Message) = s.signalType {
-              if s.serverMess {
-                messages.push(
+          let length_before = messages.len();
+          match event {
+            ChatEvent::Message { id, username, text, timestamp, reply_to, .. } => {
+              let quote = reply_to.map(|v| Self::quote(&chat_messages.lock(), &v, &theme)).unwrap_or_default();
+              if let Some(id) = id {
+                chat_messages.lock().push(ChatLine { id, line: messages.len(), username: username.clone(), text: text.clone() });
+              }
+              let highlighted = username != local_username && highlighter.is_highlighted(&text);
+              let marker = if highlighted { format!("{} ", theme.marker()) } else { String::new() };
+              messages.push(
+                format!(
+                  "{}{marker}<{}> {quote}{}",
+                  Self::time_prefix(timestamp, &time_format),
+                  theme.username(&username),
+                  theme.message(&text, &highlighter)
+                )
+              );
+
+              if highlighted {
+                Self::notify(&scroll_offset, &unread_mentions, bell);
+              }
+            },
+            ChatEvent::Direct { from, to, text, timestamp, away, .. } => {
+              let incoming = from != local_username;
+              let marker = if incoming { format!("{} ", theme.marker()) } else { String::new() };
+              let away = if away { theme.dim("[while you were away] ") } else { String::new() };
+              messages.push(
+                format!(
+                  "{}{marker}{away}<{} -> {}> {}",
+                  Self::time_prefix(timestamp, &time_format),
+                  theme.username(&from),
+                  theme.username(&to),
+                  theme.message(&text, &highlighter)
+                )
+              );
+
+              if incoming {
+                Self::notify(&scroll_offset, &unread_mentions, bell);
+              }
+            },
+            ChatEvent::Notice { text, timestamp, .. } => {
+              messages.push(format!("{}{}", Self::time_prefix(timestamp, &time_format), theme.notice(&text)));
+            },
+            ChatEvent::Presence { username, presence, timestamp, .. } => {
+              let action = match presence {
+                Presence::Joined => "joined the chat!",
+                Presence::Left => "left the chat!",
+              };
+              messages.push(
+                format!(
+                  "{}{}",
+                  Self::time_prefix(timestamp, &time_format),
+                  theme.notice(&format!("{username} {action}"))
+                )
+              );
+            },
+            ChatEvent::Stats(text) => {
+              for line in text.lines() {
+                messages.push(theme.dim(line));
+              }
+            },
+            ChatEvent::SearchResults(hits) => {
+              let header = match hits.len() {
+                0 => "Search: no messages found, Esc to close".to_owned(),
+                v => format!("Search: {v} message{} found, Esc to close", if v == 1 { "" } else { "s" }),
+              };
+              let mut lines = vec![theme.notice(&header)];
+              for hit in hits {
+                lines.push(
                   format!(
-                    "{}{}{}{}",
-                    // termion::style::Faint,
-                    // termion::style::Bold,
-                    SetAttribute(Attribute::Dim),
-                    SetAttribute(Attribute::Bold),
-                    s.message.unwrap(),
-                    // termion::style::Reset,
-                    ResetColor,
+                    "{}<{}> {}",
+                    Self::time_prefix(hit.timestamp, &time_format),
+                    theme.username(&hit.username),
+                    theme.message(&hit.text, &highlighter)
                   )
                 );
               }
-              else {
-                messages.push(
+              *search_results.lock() = Some(lines);
+            },
+            ChatEvent::History { thread, messages: history } => {
+              let header = match (&thread, history.len()) {
+                (Some(_), v) => format!("Thread: {v} message{}, Esc to close", if v == 1 { "" } else { "s" }),
+                (None, v) => format!("History: {v} message{}, Esc to close", if v == 1 { "" } else { "s" }),
+              };
+              // replies go indented under the message they answer
+              let mut depths: HashMap<String, usize> = HashMap::new();
+              let mut lines = vec![theme.notice(&header)];
+              for hit in history {
+                let depth = hit.reply_to.as_ref().and_then(|v| depths.get(v)).map_or(0, |v| v + 1);
+                if let Some(id) = &hit.id {
+                  depths.insert(id.clone(), depth);
+                }
+                lines.push(
                   format!(
-                    "<{}> {}", 
-                    s.username.unwrap(), 
-                    s.message.unwrap()
+                    "{}{}<{}> {}",
+                    "  ".repeat(depth.min(8)),
+                    Self::time_prefix(hit.timestamp, &time_format),
+                    theme.username(&hit.username),
+                    theme.message(&hit.text, &highlighter)
                   )
                 );
               }
-            }
+              *search_results.lock() = Some(lines);
+            },
+            ChatEvent::Reactions { id, reactions, own } => {
+              let line = chat_messages.lock().iter().rev().find(|v| v.id == id).map(|v| v.line);
+              if let Some(line) = line {
+                if reactions.is_empty() {
+                  reaction_lines.lock().remove(&line);
+                } else {
+                  reaction_lines.lock().insert(line, Self::reaction_line(&reactions, &own, &theme));
+                }
+              }
+            },
+            ChatEvent::FileOffer(offer) => {
+              messages.push(
+                theme.notice(
+                  &format!(
+                    "{} offers {} ({}), /accept or /reject",
+                    strip_controls(&offer.from),
+                    strip_controls(&offer.name),
+                    Self::file_size(offer.size)
+                  )
+                )
+              );
+              file_offers.lock().push(offer);
+              Self::notify(&scroll_offset, &unread_mentions, bell);
+            },
+            // a rejected file is not listened for any more
+            ChatEvent::FileAnswer { transfer, from, accepted } => {
+              let mut outgoing = outgoing_files.lock();
+              let name = outgoing.get(&transfer).map(|v| strip_controls(&v.name)).unwrap_or_default();
+              if !accepted {
+                if let Some(v) = outgoing.remove(&transfer) {
+                  v.cancel();
+                }
+              }
+              drop(outgoing);
+              let action = if accepted { "accepted" } else { "rejected" };
+              messages.push(theme.notice(&format!("{} {action} {name}", strip_controls(&from))));
+            },
+            ChatEvent::Warning(text) => {
+              messages.push(theme.warning(&text));
+            },
+            ChatEvent::Disconnected(_) => break,
+          }
+          // a scrolled up view stays where it is
+          let mut offset = scroll_offset.lock();
+          if *offset > 0 {
+            *offset += messages.len() - length_before;
           }
+          drop(offset);
+          drop(messages);
           match tx.send(()) {
             Ok(_) => {},
             Err(_) => break
           };
         }
-    
-        Ok(())
       });
     }
   
+    // counts mentions missed while scrolled up and rings the bell
+    fn notify(scroll_offset: &Mutex<usize>, unread_mentions: &Mutex<usize>, bell: bool) {
+      if *scroll_offset.lock() > 0 {
+        *unread_mentions.lock() += 1;
+      }
+      if bell {
+        print!("\x07");
+        let _ = io::stdout().flush();
+      }
+    }
+
+    // '[re alice: who's up for lunch?] ', parents from before
+    // this session are not known
+    fn quote(chat_messages: &[ChatLine], id: &str, theme: &Theme) -> String {
+      let quoted = match chat_messages.iter().rev().find(|v| v.id == id) {
+        Some(v) if v.text.chars().count() > QUOTE_LENGTH => {
+          format!("re {}: {}…", v.username, v.text.chars().take(QUOTE_LENGTH).collect::<String>())
+        },
+        Some(v) => format!("re {}: {}", v.username, v.text),
+        None => "re an earlier message".to_owned(),
+      };
+      format!("{} ", theme.dim(&format!("[{quoted}]")))
+    }
+
+    // '👍 2 [🎉 1]', reactions of this user are in brackets
+    fn reaction_line(reactions: &[(String, u32)], own: &[String], theme: &Theme) -> String {
+      let items: Vec<String> = reactions.iter()
+        .map(|(text, count)| {
+          let text = strip_controls(text);
+          if own.iter().any(|v| strip_controls(v) == text) { format!("[{text} {count}]") } else { format!("{text} {count}") }
+        })
+        .collect();
+      format!("    {}", theme.dim(&items.join("  ")))
+    }
+
+    // '2.5 MB'
+    fn file_size(bytes: u64) -> String {
+      const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+      if bytes < 1024 {
+        return format!("{bytes} B");
+      }
+      let mut size = bytes as f64 / 1024.0;
+      let mut unit = 0;
+      while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+      }
+      format!("{size:.1} {}", UNITS[unit])
+    }
+
+    // server time in the local time zone, nothing for servers without timestamps
+    fn time_prefix(timestamp: Option<u64>, format: &str) -> String {
+      let time = timestamp
+        .and_then(|v| i64::try_from(v).ok())
+        .and_then(DateTime::from_timestamp_millis);
+      match time {
+        Some(v) => format!("[{}] ", v.with_timezone(&Local).format(format)),
+        None => String::new(),
+      }
+    }
+  
     pub fn enable_print(self) -> Service {
+      let theme = self.theme();
       let rx = self.state.chatReloadRX.unwrap();
       let messages = self.state.messagesThr.clone();
       let user_input = self.state.userInp.clone();
       let username = self.state.username.clone();
+      let scroll_offset = self.state.scrollOffset.clone();
+      let unread_mentions = self.state.unreadMentions.clone();
+      let search_results = self.state.searchResults.clone();
+      let reaction_lines = self.state.reactionLines.clone();
+      let chat_messages = self.state.chatMessages.clone();
+      let selected = self.state.selected.clone();
+      let replying_to = self.state.replyingTo.clone();
+      let transfer_lines = self.state.transferLines.clone();
   
       thread::spawn(move || -> io::Result<()> {
         loop {
@@ -108,23 +379,68 @@ impl Service {
             terminal::Clear(ClearType::All),
           )?;
 
-          for (index, m) in messages.lock().iter().enumerate() {
-            if index == 0 {
-              print!("\r\n{m}\r\n");
+          print!("\r\n");
+          // search results on top, at most half of the screen with the newest matches
+          let mut rows = Self::visible_rows();
+          if let Some(lines) = search_results.lock().as_ref() {
+            let height = (rows / 2).clamp(1, lines.len());
+            print!("{}\r\n", lines[0]);
+            for line in &lines[(lines.len() + 1).saturating_sub(height).max(1)..] {
+              print!("{line}\r\n");
+            }
+            let width = terminal::size().map(|v| v.0).unwrap_or(80);
+            print!("{}\r\n", theme.dim(&"-".repeat(width.into())));
+            rows = rows.saturating_sub(height + 1).max(1);
+          }
+          // transfers under the messages, right above the input
+          let mut transfers: Vec<String> = transfer_lines.lock().values().cloned().collect();
+          transfers.sort();
+          rows = rows.saturating_sub(transfers.len()).max(1);
+
+          let selected_line = {
+            let index = *selected.lock();
+            index.and_then(|v| chat_messages.lock().get(v).map(|m| m.line))
+          };
+          let messages = messages.lock();
+          let offset = *scroll_offset.lock();
+          let end = messages.len().saturating_sub(offset);
+          // reactions take a line of their own under the message
+          let reactions = reaction_lines.lock();
+          let mut lines: Vec<String> = Vec::new();
+          for (index, m) in messages[..end].iter().enumerate().rev() {
+            if lines.len() >= rows {
+              break;
             }
-            else {
-              print!("{m}\r\n");
+            if let Some(v) = reactions.get(&index) {
+              lines.push(v.clone());
             }
+            if selected_line == Some(index) {
+              lines.push(format!("{} {m}", theme.pointer()));
+            } else {
+              lines.push(m.clone());
+            }
+          }
+          for m in lines.iter().rev().skip(lines.len().saturating_sub(rows)) {
+            print!("{m}\r\n");
+          }
+          drop(reactions);
+          drop(messages);
+          for line in &transfers {
+            print!("{}\r\n", theme.dim(line));
           }
+
+          let unread = *unread_mentions.lock();
+          let status = match (offset, unread) {
+            (0, _) => String::new(),
+            (_, 0) => "[scrolled up] ".to_owned(),
+            (_, v) => format!("[{v} unread mention{}] ", if v == 1 { "" } else { "s" }),
+          };
+          let replying = replying_to.lock().as_ref()
+            .map(|v| format!("[reply to {}] ", strip_controls(&v.username)))
+            .unwrap_or_default();
+          let status = format!("{replying}{status}");
           let input = user_input.lock().clone();
-          print!(
-            "{}{}{} >{} {}", 
-            SetBackgroundColor(Color::White),
-            SetForegroundColor(Color::Black),
-            username, 
-            ResetColor,
-            input
-          );
+          print!("{} {}", theme.prompt(&format!("{status}{username} >")), input);
   
           std::io::stdout().flush()?;
         }
@@ -132,7 +448,7 @@ impl Service {
       });
   
       Service { 
-        connection: self.connection,
+        client: self.client,
         settings: self.settings, 
         state: State {
           username: self.state.username.clone(),
@@ -140,10 +456,335 @@ impl Service {
           chatReloadTX: self.state.chatReloadTX.clone(),
           userInp: self.state.userInp.clone(),
           messagesThr: self.state.messagesThr.clone(),
-        }
+          scrollOffset: self.state.scrollOffset.clone(),
+          unreadMentions: self.state.unreadMentions.clone(),
+          searchResults: self.state.searchResults.clone(),
+          chatMessages: self.state.chatMessages.clone(),
+          reactionLines: self.state.reactionLines.clone(),
+          selected: self.state.selected.clone(),
+          replyingTo: self.state.replyingTo.clone(),
+          fileOffers: self.state.fileOffers.clone(),
+          outgoingFiles: self.state.outgoingFiles.clone(),
+          transferLines: self.state.transferLines.clone(),
+        },
+        transcript: self.transcript,
+      }
+    }
+
+    fn theme(&self) -> Theme {
+      Theme::new(!self.settings.no_color)
+    }
+
+    // lines left for messages, one for the top margin and one for the input
+    fn visible_rows() -> usize {
+      let height = terminal::size().map(|v| v.1).unwrap_or(24);
+      usize::from(height.saturating_sub(2)).max(1)
+    }
+
+    // positive 'lines' scroll up, back at the bottom mentions are read
+    fn scroll(&mut self, lines: isize) {
+      let length = self.state.messagesThr.lock().len();
+      let mut offset = self.state.scrollOffset.lock();
+      *offset = offset.saturating_add_signed(lines).min(length.saturating_sub(1));
+      if *offset == 0 {
+        *self.state.unreadMentions.lock() = 0;
       }
+      drop(offset);
+      let _ = self.state.chatReloadTX.send(());
     }
   
+    // server counters, answered only for admins
+    fn request_stats(&mut self) {
+      if let Err(e) = self.client.request_stats() {
+        self.show_warning(&e.to_string());
+      }
+    }
+
+    // '/msg name text'
+    fn send_direct(&mut self, args: &str) {
+      let Some((to, text)) = args.trim().split_once(' ').filter(|v| !v.1.trim().is_empty()) else {
+        return self.show_warning("Usage: /msg name text");
+      };
+      if let Err(e) = self.client.send_direct(to.trim_start_matches('@'), text.trim()) {
+        self.show_warning(&e.to_string());
+      }
+    }
+
+    // '/kick name [reason]', '/mute name 10m [reason]', '/ban name [reason]', '/unban name'
+    fn moderate(&mut self, command: &str) {
+      let mut words = command.split_whitespace();
+      let action = words.next().unwrap_or_default();
+      let Some(username) = words.next().map(|v| v.trim_start_matches('@').to_owned()) else {
+        return self.show_warning(&format!("Usage: {action} name{}", if action == "/unban" { "" } else { " [reason]" }));
+      };
+
+      let result = match action {
+        "/mute" => {
+          let Some(duration) = words.next().and_then(Self::parse_duration) else {
+            return self.show_warning("Usage: /mute name duration [reason], e.g. 90s, 10m, 2h or 1d");
+          };
+          let reason = words.collect::<Vec<_>>().join(" ");
+          self.client.mute(&username, duration, Some(reason.as_str()).filter(|v| !v.is_empty()))
+        },
+        "/unban" => self.client.unban(&username),
+        _ => {
+          let reason = words.collect::<Vec<_>>().join(" ");
+          let reason = Some(reason.as_str()).filter(|v| !v.is_empty());
+          if action == "/kick" {
+            self.client.kick(&username, reason)
+          } else {
+            self.client.ban(&username, reason)
+          }
+        },
+      };
+      if let Err(e) = result {
+        self.show_warning(&e.to_string());
+      }
+    }
+
+    // '90' or '90s' seconds, '10m', '2h', '1d'
+    fn parse_duration(text: &str) -> Option<Duration> {
+      let (amount, unit) = match text.strip_suffix(['s', 'm', 'h', 'd']) {
+        Some(v) => (v, text.chars().last()?),
+        None => (text, 's'),
+      };
+      let amount: u64 = amount.parse().ok().filter(|v| *v > 0)?;
+      let seconds = match unit {
+        'm' => amount.checked_mul(60)?,
+        'h' => amount.checked_mul(60 * 60)?,
+        'd' => amount.checked_mul(24 * 60 * 60)?,
+        _ => amount,
+      };
+      Some(Duration::from_secs(seconds))
+    }
+
+    // '/react [n] reaction' and '/unreact [n] reaction', n counts messages
+    // from the bottom, 1 is the last one
+    fn react(&mut self, command: &str) {
+      let mut words = command.split_whitespace();
+      let action = words.next().unwrap_or_default();
+      let args: Vec<&str> = words.collect();
+      let (position, reaction) = match args.as_slice() {
+        [reaction] => (1, *reaction),
+        [position, reaction] => match position.parse::<usize>() {
+          Ok(v) if v > 0 => (v, *reaction),
+          _ => return self.show_warning(&format!("Usage: {action} [n] reaction, n is 1 for the last message")),
+        },
+        _ => return self.show_warning(&format!("Usage: {action} [n] reaction, n is 1 for the last message")),
+      };
+
+      let id = self.state.chatMessages.lock().iter().rev().nth(position - 1).map(|v| v.id.clone());
+      let Some(id) = id else {
+        return self.show_warning(&format!("There is no message {position} to react to"));
+      };
+      let result = if action == "/unreact" {
+        self.client.unreact(&id, reaction)
+      } else {
+        self.client.react(&id, reaction)
+      };
+      if let Err(e) = result {
+        self.show_warning(&e.to_string());
+      }
+    }
+
+    fn search(&mut self, args: &str) {
+      let query = match search::parse_command(args) {
+        Ok(v) => v,
+        Err(e) => return self.show_warning(&e),
+      };
+      if let Err(e) = self.client.search(&query) {
+        self.show_warning(&e.to_string());
+      }
+    }
+
+    // Esc closes the search pane first, then drops the reply and the pick
+    fn escape(&mut self) {
+      if self.state.searchResults.lock().take().is_none() && self.state.replyingTo.lock().take().is_none() {
+        *self.state.selected.lock() = None;
+      }
+      let _ = self.state.chatReloadTX.send(());
+    }
+
+    // Alt+Up picks older messages, Alt+Down newer ones and past the last none;
+    // the view follows the pick
+    fn select(&mut self, step: isize) {
+      let chat_messages = self.state.chatMessages.lock();
+      let mut selected = self.state.selected.lock();
+      *selected = match *selected {
+        None if step < 0 => chat_messages.len().checked_sub(1),
+        None => None,
+        Some(v) if step < 0 => Some(v.saturating_sub(1)),
+        Some(v) => Some(v + 1).filter(|v| *v < chat_messages.len()),
+      };
+      let line = selected.and_then(|v| chat_messages.get(v)).map(|v| v.line);
+      drop(selected);
+      drop(chat_messages);
+
+      if let Some(line) = line {
+        let length = self.state.messagesThr.lock().len();
+        let rows = Self::visible_rows();
+        let mut offset = self.state.scrollOffset.lock();
+        let end = length.saturating_sub(*offset);
+        if line >= end {
+          *offset = length - line - 1;
+        } else if line + rows < end {
+          *offset = length - line - rows;
+        }
+        if *offset == 0 {
+          *self.state.unreadMentions.lock() = 0;
+        }
+      }
+      let _ = self.state.chatReloadTX.send(());
+    }
+
+    // the picked message, or the last one if none is
+    fn picked(&self) -> Option<ChatLine> {
+      let selected = *self.state.selected.lock();
+      let chat_messages = self.state.chatMessages.lock();
+      selected.and_then(|v| chat_messages.get(v)).or(chat_messages.last()).cloned()
+    }
+
+    // Ctrl+R, the next message goes as a reply to the picked one
+    fn start_reply(&mut self) {
+      if !self.client.server().supports(Capability::Threads) {
+        return self.show_warning("Server doesn't support replies");
+      }
+      let Some(parent) = self.picked() else {
+        return self.show_warning("There is no message to reply to");
+      };
+      *self.state.replyingTo.lock() = Some(parent);
+      let _ = self.state.chatReloadTX.send(());
+    }
+
+    // Ctrl+T, the thread of the picked message in the search pane
+    fn show_thread(&mut self) {
+      let Some(message) = self.picked() else {
+        return self.show_warning("There is no message to show the thread of");
+      };
+      if let Err(e) = self.client.request_history(Some(&message.id)) {
+        self.show_warning(&e.to_string());
+      }
+    }
+
+    // '/send name path', the file goes once the recipient accepts it
+    fn send_file(&mut self, args: &str) {
+      let Some((to, path)) = args.trim().split_once(' ').filter(|v| !v.1.trim().is_empty()) else {
+        return self.show_warning("Usage: /send name path");
+      };
+      let to = to.trim_start_matches('@').to_owned();
+      let path = PathBuf::from(path.trim());
+      let name = path.file_name().map(|v| v.to_string_lossy().into_owned()).unwrap_or_default();
+
+      let on_progress = self.transfer_progress(format!("{name} to {to}"));
+      match self.client.offer_file(&to, &path, on_progress) {
+        Ok(outgoing) => {
+          self.show_notice(&format!("Offered {name} ({}) to {to}", Self::file_size(outgoing.size)));
+          let mut outgoing_files = self.state.outgoingFiles.lock();
+          outgoing_files.retain(|_, v| !v.is_closed());
+          outgoing_files.insert(outgoing.transfer.clone(), outgoing);
+        },
+        Err(e) => self.show_warning(&format!("Sending {} failed: {e}", path.display())),
+      }
+    }
+
+    // '/accept [n]' and '/reject [n]', n counts offers from the newest, 1 is the last one
+    fn answer_file(&mut self, command: &str) {
+      let mut words = command.split_whitespace();
+      let action = words.next().unwrap_or_default();
+      let position = match words.next().map(|v| v.parse::<usize>()) {
+        None => 1,
+        Some(Ok(v)) if v > 0 => v,
+        _ => return self.show_warning(&format!("Usage: {action} [n], n is 1 for the last offer")),
+      };
+
+      let mut file_offers = self.state.fileOffers.lock();
+      let Some(index) = file_offers.len().checked_sub(position) else {
+        drop(file_offers);
+        return self.show_warning(&format!("There is no offer {position} to {}", &action[1..]));
+      };
+      let offer = file_offers.remove(index);
+      drop(file_offers);
+
+      let name = strip_controls(&offer.name);
+      if action == "/reject" {
+        match self.client.reject_file(&offer) {
+          Ok(()) => self.show_notice(&format!("Rejected {name}")),
+          Err(e) => self.show_warning(&e.to_string()),
+        }
+        return;
+      }
+      let on_progress = self.transfer_progress(format!("{name} from {}", strip_controls(&offer.from)));
+      if let Err(e) = self.client.accept_file(&offer, &self.settings.download_dir, on_progress) {
+        self.show_warning(&format!("Receiving {name} failed: {e}"));
+      }
+    }
+
+    // a line above the input while the file goes, a notice at the end
+    fn transfer_progress(&self, label: String) -> impl FnMut(Progress) + Send + 'static {
+      let key = Uuid::new_v4().to_string();
+      let transfer_lines = self.state.transferLines.clone();
+      let messages = self.state.messagesThr.clone();
+      let tx = self.state.chatReloadTX.clone();
+      let theme = self.theme();
+      move |progress| {
+        let line = match progress {
+          Progress::Transferred { done, size } => Some(
+            format!(
+              "{label}: {}% ({} of {})",
+              (done * 100).checked_div(size).unwrap_or(100),
+              Self::file_size(done),
+              Self::file_size(size)
+            )
+          ),
+          Progress::Resuming(offset) => Some(format!("{label}: connection lost, resuming at {}", Self::file_size(offset))),
+          Progress::Finished(path) => {
+            messages.lock().push(theme.notice(&format!("{label}: done, {}", path.display())));
+            None
+          },
+          Progress::Failed(e) => {
+            messages.lock().push(theme.warning(&format!("{label}: failed, {e}")));
+            None
+          },
+        };
+        match line {
+          Some(v) => transfer_lines.lock().insert(key.clone(), v),
+          None => transfer_lines.lock().remove(&key),
+        };
+        let _ = tx.send(());
+      }
+    }
+
+    // '/export [text|markdown|json] [path]'
+    fn export(&mut self, args: &str) {
+      let mut args = args.split_whitespace();
+      let format = match args.next().map(Format::from_str).transpose() {
+        Ok(v) => v.unwrap_or(Format::Text),
+        Err(e) => return self.show_warning(&e),
+      };
+      let path = args.next().map(PathBuf::from).unwrap_or_else(|| Transcript::default_path(format));
+
+      let rendered = {
+        let transcript = self.transcript.lock();
+        transcript.render(format).map(|v| (v, transcript.len()))
+      };
+      // written without the lock, so incoming messages aren't held up by the disk
+      let result = rendered.and_then(|(content, count)| fs::write(&path, content).map(|_| count));
+      match result {
+        Ok(count) => self.show_notice(&format!("Exported {count} entries to {}", path.display())),
+        Err(e) => self.show_warning(&format!("Export to {} failed: {e}", path.display())),
+      }
+    }
+
+    fn show_notice(&self, text: &str) {
+      self.state.messagesThr.lock().push(self.theme().notice(text));
+      let _ = self.state.chatReloadTX.send(());
+    }
+
+    fn show_warning(&self, text: &str) {
+      self.state.messagesThr.lock().push(self.theme().warning(text));
+      let _ = self.state.chatReloadTX.send(());
+    }
+
     pub fn read_inputs(&mut self) {
       enable_raw_mode().unwrap();  
       loop {
@@ -161,17 +802,59 @@ impl Service {
                   continue;
                 }
                 self.state.userInp.lock().clear();
-                let signal = SignalsData::new(
-                  vec![
-                    SignalsHeader::signalType(Signal::Message),
-                    SignalsHeader::withMess,
-                    SignalsHeader::username(self.state.username.to_owned())
-                  ],
-                  Some(&ms)
-                );
-      
-                self.connection.stream.write_all(signal.to_string().as_bytes()).unwrap();
+                if ms == "/stats" {
+                  self.request_stats();
+                  continue;
+                }
+                if ["/kick", "/mute", "/ban", "/unban"].iter().any(|v| ms == *v || ms.starts_with(&format!("{v} "))) {
+                  self.moderate(&ms);
+                  continue;
+                }
+                if ms == "/msg" || ms.starts_with("/msg ") {
+                  self.send_direct(&ms["/msg".len()..]);
+                  continue;
+                }
+                if ["/react", "/unreact"].iter().any(|v| ms == *v || ms.starts_with(&format!("{v} "))) {
+                  self.react(&ms);
+                  continue;
+                }
+                if ms == "/send" || ms.starts_with("/send ") {
+                  self.send_file(&ms["/send".len()..]);
+                  continue;
+                }
+                if ["/accept", "/reject"].iter().any(|v| ms == *v || ms.starts_with(&format!("{v} "))) {
+                  self.answer_file(&ms);
+                  continue;
+                }
+                if ms == "/search" || ms.starts_with("/search ") {
+                  self.search(&ms["/search".len()..]);
+                  continue;
+                }
+                if ms == "/export" || ms.starts_with("/export ") {
+                  self.export(&ms["/export".len()..]);
+                  continue;
+                }
+                let reply_to = self.state.replyingTo.lock().take();
+                match reply_to {
+                  Some(parent) => {
+                    *self.state.selected.lock() = None;
+                    if let Err(e) = self.client.reply(&parent.id, &ms) {
+                      self.show_warning(&e.to_string());
+                    }
+                  },
+                  None => self.client.send_message(&ms).unwrap(),
+                }
               },
+              KeyCode::PageUp => self.scroll((Self::visible_rows() / 2).max(1) as isize),
+              KeyCode::PageDown => self.scroll(-((Self::visible_rows() / 2).max(1) as isize)),
+              KeyCode::Up if key_event.modifiers.contains(event::KeyModifiers::ALT) => self.select(-1),
+              KeyCode::Down if key_event.modifiers.contains(event::KeyModifiers::ALT) => self.select(1),
+              KeyCode::Char('r') if key_event.modifiers.contains(event::KeyModifiers::CONTROL) => self.start_reply(),
+              KeyCode::Char('t') if key_event.modifiers.contains(event::KeyModifiers::CONTROL) => self.show_thread(),
+              KeyCode::Up => self.scroll(1),
+              KeyCode::Down => self.scroll(-1),
+              KeyCode::End => self.scroll(isize::MIN),
+              KeyCode::Esc => self.escape(),
               KeyCode::Backspace => {
                 self.state.userInp.lock().pop();
                 match self.state.chatReloadTX.send(()) {
@@ -195,4 +878,31 @@ impl Service {
         }
       }
     }
-  }
\ No newline at end of file
+  }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // 2024-07-01 12:00:07 UTC, the same month and seconds in every time zone
+  const TIMESTAMP: u64 = 1_719_835_207_000;
+
+  #[test]
+  fn time_prefix_uses_the_format() {
+    assert_eq!(Service::time_prefix(Some(TIMESTAMP), "%Y-%m"), "[2024-07] ");
+    assert_eq!(Service::time_prefix(Some(TIMESTAMP), "%S"), "[07] ");
+    assert_eq!(Service::time_prefix(Some(TIMESTAMP), "at %S s"), "[at 07 s] ");
+  }
+
+  #[test]
+  fn time_prefix_is_local_time() {
+    let local = DateTime::from_timestamp_millis(TIMESTAMP as i64).unwrap().with_timezone(&Local);
+    assert_eq!(Service::time_prefix(Some(TIMESTAMP), "%H:%M"), format!("[{}] ", local.format("%H:%M")));
+  }
+
+  #[test]
+  fn no_prefix_without_a_timestamp() {
+    assert_eq!(Service::time_prefix(None, "%H:%M"), "");
+    assert_eq!(Service::time_prefix(Some(u64::MAX), "%H:%M"), "");
+  }
+}
@@ -1,10 +1,72 @@
+use std::{env, path::PathBuf};
+
+use chrono::format::{Item, StrftimeItems};
 use clap::Parser;
 
 // using macros for generating parser for command args
 #[derive(Parser)]
 pub struct Args {
-  #[arg(short, long, help = "Server address")]
-  pub address: String,
+  #[arg(short, long, help = "Server address", required_unless_present = "p2p")]
+  pub address: Option<String>,
+
+  #[arg(short, long, help = "Username, asked for on start if not set")]
+  pub username: Option<String>,
+
+  #[arg(long, help = "Token of a server owner, moderator or admin, read from CHAT_TOKEN if not set")]
+  pub token: Option<String>,
+
+  #[arg(long, requires = "username", help = "Send one message and exit")]
+  pub send: Option<String>,
+
+  #[arg(
+    long,
+    requires = "username",
+    conflicts_with = "send",
+    help = "No UI: send every line of stdin, print incoming messages as JSON lines"
+  )]
+  pub headless: bool,
+
+  #[arg(
+    long,
+    default_value = "%H:%M",
+    value_parser = parse_time_format,
+    help = "Format of message times in local time, strftime-like (e.g. '%Y-%m-%d %H:%M:%S')"
+  )]
+  pub time_format: String,
+
+  #[arg(long, help = "Highlight messages containing this word, besides mentions of you (can be repeated)")]
+  pub highlight: Vec<String>,
+
+  #[arg(long, help = "Ring the terminal bell on highlighted messages")]
+  pub bell: bool,
+
+  #[arg(long, help = "Append every session to a transcript file per server in this directory")]
+  pub transcript_dir: Option<PathBuf>,
+
+  #[arg(long, default_value = ".", help = "Where accepted files are saved")]
+  pub download_dir: PathBuf,
+
+  #[arg(long, help = "Plain text without colors or styles, for dumb terminals (also set by NO_COLOR)")]
+  pub no_color: bool,
+
+  #[arg(long, help = "Chat without a server, directly with peers in the local network")]
+  pub p2p: bool,
+
+  #[arg(long, default_value_t = 0, help = "TCP port for incoming peer connections (0 - any free port)")]
+  pub listen_port: u16,
+
+  #[arg(long, default_value_t = 7979, help = "UDP port used for peer discovery")]
+  pub discovery_port: u16,
+
+  #[arg(long, help = "Address of a peer to connect to directly (can be repeated)")]
+  pub peer: Vec<String>,
+}
+
+fn parse_time_format(s: &str) -> Result<String, String> {
+  if StrftimeItems::new(s).any(|v| v == Item::Error) {
+    return Err(format!("'{s}' is not a valid time format"));
+  }
+  Ok(s.to_owned())
 }
 
 // using macros for generating code for right output ({:?}) and 
@@ -12,6 +74,20 @@ pub struct Args {
 #[derive(Debug, Clone)]
 pub struct Settings {
   pub server_address: String,
+  pub username: Option<String>,
+  pub token: Option<String>,
+  pub send: Option<String>,
+  pub headless: bool,
+  pub time_format: String,
+  pub highlight: Vec<String>,
+  pub bell: bool,
+  pub no_color: bool,
+  pub transcript_dir: Option<PathBuf>,
+  pub download_dir: PathBuf,
+  pub p2p: bool,
+  pub listen_port: u16,
+  pub discovery_port: u16,
+  pub peers: Vec<String>,
 }
 
 impl Settings {
@@ -19,7 +95,43 @@ impl Settings {
     let args = Args::parse();
     
     Settings { 
-      server_address: args.address,
+      server_address: args.address.unwrap_or_default(),
+      username: args.username,
+      // the environment keeps it out of the process list
+      token: args.token.or_else(|| env::var("CHAT_TOKEN").ok().filter(|v| !v.is_empty())),
+      send: args.send,
+      headless: args.headless,
+      time_format: args.time_format,
+      highlight: args.highlight,
+      bell: args.bell,
+      // https://no-color.org, any non empty value
+      no_color: args.no_color || env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()),
+      transcript_dir: args.transcript_dir,
+      download_dir: args.download_dir,
+      p2p: args.p2p,
+      listen_port: args.listen_port,
+      discovery_port: args.discovery_port,
+      peers: args.peer,
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn time_formats_are_checked() {
+    assert_eq!(parse_time_format("%Y-%m-%d %H:%M:%S").unwrap(), "%Y-%m-%d %H:%M:%S");
+    assert!(parse_time_format("%H:%M").is_ok());
+    assert!(parse_time_format("%H:%").is_err());
+    assert!(parse_time_format("%Q").is_err());
+  }
+
+  #[test]
+  fn time_format_defaults_to_hours_and_minutes() {
+    let args = Args::try_parse_from(["client", "--address", "127.0.0.1:7878"]).unwrap();
+    assert_eq!(args.time_format, "%H:%M");
+    assert!(Args::try_parse_from(["client", "--address", "127.0.0.1:7878", "--time-format", "%Q"]).is_err());
+  }
+}
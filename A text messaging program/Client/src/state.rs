@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     sync::{
         mpsc::{Sender, Receiver, self},
         Arc
@@ -7,17 +8,47 @@ use std::{
 };
 use crossterm::terminal::{self, Clear, ClearType};
 use parking_lot::Mutex;
+use client::{FileOffer, Outgoing};
+
+// a chat message on the screen, 'line' is its index in 'messagesThr'
+#[derive(Debug, Clone)]
+pub struct ChatLine{
+    pub id: String,
+    pub line: usize,
+    pub username: String,
+    pub text: String,
+}
 
 pub struct State{
     pub username: String,
     pub chatReloadRX: Option<Receiver<()>>,
     pub chatReloadTX: Sender<()>,
     pub userInp: Arc<Mutex<String>>,
-    pub messagesThr: Arc<Mutex<Vec<String>>>
+    pub messagesThr: Arc<Mutex<Vec<String>>>,
+    // how many messages the view is scrolled up from the bottom
+    pub scrollOffset: Arc<Mutex<usize>>,
+    // mentions which came while the view was scrolled up
+    pub unreadMentions: Arc<Mutex<usize>>,
+    // lines of the search pane, no pane if None
+    pub searchResults: Arc<Mutex<Option<Vec<String>>>>,
+    // chat messages with ids, lines are never removed, so the indexes stay right
+    pub chatMessages: Arc<Mutex<Vec<ChatLine>>>,
+    // reactions shown under the line of a message
+    pub reactionLines: Arc<Mutex<HashMap<usize, String>>>,
+    // index in 'chatMessages' of the message picked with Alt+Up/Down
+    pub selected: Arc<Mutex<Option<usize>>>,
+    // the next message goes as a reply to this one
+    pub replyingTo: Arc<Mutex<Option<ChatLine>>>,
+    // offers waiting for /accept or /reject, the newest last
+    pub fileOffers: Arc<Mutex<Vec<FileOffer>>>,
+    // files this user offers, by transfer id
+    pub outgoingFiles: Arc<Mutex<HashMap<String, Outgoing>>>,
+    // progress of running transfers, shown above the input
+    pub transferLines: Arc<Mutex<HashMap<String, String>>>
 }
 
 impl State{
-    pub fn new() -> io::Result<State> {
+    pub fn new(username: Option<String>) -> io::Result<State> {
         let (tx, rx) = mpsc::channel::<()>();
 
         let mut instance = State{
@@ -26,9 +57,22 @@ impl State{
             chatReloadTX: tx,
             userInp: Arc::new(Mutex::new(String::new())),
             messagesThr: Arc::new(Mutex::new(Vec::<String>::new())),
+            scrollOffset: Arc::new(Mutex::new(0)),
+            unreadMentions: Arc::new(Mutex::new(0)),
+            searchResults: Arc::new(Mutex::new(None)),
+            chatMessages: Arc::new(Mutex::new(Vec::new())),
+            reactionLines: Arc::new(Mutex::new(HashMap::new())),
+            selected: Arc::new(Mutex::new(None)),
+            replyingTo: Arc::new(Mutex::new(None)),
+            fileOffers: Arc::new(Mutex::new(Vec::new())),
+            outgoingFiles: Arc::new(Mutex::new(HashMap::new())),
+            transferLines: Arc::new(Mutex::new(HashMap::new())),
         };
 
-        instance.readUserName()?;
+        match username {
+            Some(v) => instance.username = v.trim().to_owned(),
+            None => instance.readUserName()?,
+        }
         Ok(instance)
     }
 
@@ -0,0 +1,309 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr
+  };
+use chrono::{DateTime, Local};
+use client::{Event, Presence};
+use serde_json::{json, Value};
+
+use crate::{headless::Headless, markup::strip_controls};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// ----- Export format -----
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Text,
+  Markdown,
+  Json,
+}
+
+impl FromStr for Format {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "text" | "txt" => Ok(Format::Text),
+      "markdown" | "md" => Ok(Format::Markdown),
+      "json" => Ok(Format::Json),
+      _ => Err(format!("Unknown export format '{s}', use text, markdown or json")),
+    }
+  }
+}
+
+impl Format {
+  fn extension(self) -> &'static str {
+    match self {
+      Format::Text => "txt",
+      Format::Markdown => "md",
+      Format::Json => "json",
+    }
+  }
+}
+
+// ----- Transcript entry -----
+// events without a server timestamp get the time they came
+struct Entry {
+  received: DateTime<Local>,
+  event: Event,
+}
+
+impl Entry {
+  fn time(&self) -> String {
+    let timestamp = match &self.event {
+      Event::Message { timestamp, .. }
+      | Event::Direct { timestamp, .. }
+      | Event::Notice { timestamp, .. }
+      | Event::Presence { timestamp, .. } => *timestamp,
+      _ => None,
+    };
+    timestamp
+      .and_then(|v| i64::try_from(v).ok())
+      .and_then(DateTime::from_timestamp_millis)
+      .map(|v| v.with_timezone(&Local))
+      .unwrap_or(self.received)
+      .format(TIME_FORMAT)
+      .to_string()
+  }
+
+  // author and text, no author for system messages
+  fn parts(&self) -> (Option<String>, String) {
+    match &self.event {
+      Event::Message { username, text, .. } => (Some(strip_controls(username)), strip_controls(text)),
+      Event::Direct { from, to, text, .. } => {
+        (Some(format!("{} -> {}", strip_controls(from), strip_controls(to))), strip_controls(text))
+      },
+      Event::Notice { text, .. } | Event::Warning(text) => (None, strip_controls(text)),
+      Event::Stats(text) => (None, strip_controls(&text.lines().collect::<Vec<_>>().join(", "))),
+      Event::SearchResults(hits) => (None, format!("Search found {} messages", hits.len())),
+      Event::History { messages, .. } => (None, format!("History of {} messages", messages.len())),
+      Event::Reactions { id, reactions, .. } => {
+        let counts: Vec<String> = reactions.iter().map(|(text, count)| format!("{} {count}", strip_controls(text))).collect();
+        (None, format!("Reactions to {id}: {}", counts.join(", ")))
+      },
+      Event::FileOffer(offer) => {
+        (None, format!("{} offered {} ({} bytes)", strip_controls(&offer.from), strip_controls(&offer.name), offer.size))
+      },
+      Event::FileAnswer { transfer, from, accepted } => {
+        (None, format!("{} {} transfer {transfer}", strip_controls(from), if *accepted { "accepted" } else { "rejected" }))
+      },
+      Event::Presence { username, presence, .. } => {
+        let action = match presence {
+          Presence::Joined => "joined the chat",
+          Presence::Left => "left the chat",
+        };
+        (None, format!("{} {action}", strip_controls(username)))
+      },
+      Event::Disconnected(reason) => {
+        (None, format!("Disconnected{}", reason.as_ref().map(|v| format!(": {v}")).unwrap_or_default()))
+      },
+    }
+  }
+
+  fn to_text(&self) -> String {
+    match self.parts() {
+      (Some(username), text) => format!("[{}] <{username}> {text}", self.time()),
+      (None, text) => format!("[{}] * {text}", self.time()),
+    }
+  }
+
+  fn to_markdown(&self) -> String {
+    match self.parts() {
+      (Some(username), text) => format!("- `{}` **{username}**: {text}", self.time()),
+      (None, text) => format!("- `{}` _{text}_", self.time()),
+    }
+  }
+
+  fn to_json(&self) -> Value {
+    let mut value = Headless::to_json(&self.event)
+      .unwrap_or_else(|| json!({ "type": "disconnected", "text": self.parts().1 }));
+    value["received"] = json!(self.received.to_rfc3339());
+    value["system"] = json!(!matches!(self.event, Event::Message { .. } | Event::Direct { .. }));
+    value
+  }
+}
+
+// ----- Session transcript -----
+// everything received in this session, optionally appended
+// to a log file per server as it comes
+pub struct Transcript {
+  entries: Vec<Entry>,
+  log: Option<File>,
+}
+
+impl Transcript {
+  pub fn new() -> Transcript {
+    Transcript { entries: Vec::new(), log: None }
+  }
+
+  // starts logging to '<directory>/<server>.log'
+  pub fn log_to(&mut self, directory: &Path, server: &str, username: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(directory)?;
+    let name: String = server.chars()
+      .map(|c| if c.is_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+      .collect();
+    let path = directory.join(format!("{name}.log"));
+
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "--- Session started {} as {username} ---", Local::now().format(TIME_FORMAT))?;
+    self.log = Some(file);
+    Ok(path)
+  }
+
+  // search results and history are answers to this user, not a part
+  // of the chat, and reactions only change messages already there
+  pub fn record(&mut self, event: &Event) {
+    if let Event::SearchResults(_) | Event::History { .. } | Event::Reactions { .. } = event {
+      return;
+    }
+    let entry = Entry { received: Local::now(), event: event.clone() };
+    // a failing log doesn't stop the chat, it just stops logging
+    if let Some(file) = &mut self.log {
+      if writeln!(file, "{}", entry.to_text()).is_err() {
+        self.log = None;
+      }
+    }
+    self.entries.push(entry);
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  // file name for an export without a path given
+  pub fn default_path(format: Format) -> PathBuf {
+    PathBuf::from(format!("transcript-{}.{}", Local::now().format("%Y%m%d-%H%M%S"), format.extension()))
+  }
+
+  // the whole transcript in 'format', ready to be written to a file
+  pub fn render(&self, format: Format) -> io::Result<String> {
+    let content = match format {
+      Format::Text => self.entries.iter().map(|v| v.to_text() + "\n").collect(),
+      Format::Markdown => {
+        let mut content = format!("# Chat transcript\n\nExported {}\n\n", Local::now().format(TIME_FORMAT));
+        for entry in &self.entries {
+          content.push_str(&entry.to_markdown());
+          content.push('\n');
+        }
+        content
+      },
+      Format::Json => {
+        let entries: Vec<Value> = self.entries.iter().map(|v| v.to_json()).collect();
+        serde_json::to_string_pretty(&entries)? + "\n"
+      },
+    };
+    Ok(content)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TIMESTAMP: u64 = 1_719_835_207_000;
+
+  fn time() -> String {
+    DateTime::from_timestamp_millis(TIMESTAMP as i64).unwrap().with_timezone(&Local).format(TIME_FORMAT).to_string()
+  }
+
+  fn transcript() -> Transcript {
+    let mut transcript = Transcript::new();
+    let timestamp = Some(TIMESTAMP);
+    transcript.record(&Event::Presence { username: "bob".to_owned(), presence: Presence::Joined, timestamp, sequence: Some(1) });
+    transcript.record(&Event::Message {
+      id: Some("1".to_owned()),
+      username: "bob".to_owned(),
+      text: "hi \x1b[31m**all**".to_owned(),
+      timestamp,
+      sequence: Some(2),
+      reply_to: None,
+    });
+    transcript.record(&Event::Direct {
+      id: None,
+      from: "bob".to_owned(),
+      to: "alice".to_owned(),
+      text: "psst".to_owned(),
+      timestamp,
+      away: false,
+    });
+    transcript.record(&Event::Notice { id: None, text: "Welcome".to_owned(), timestamp, sequence: Some(3) });
+    // answers to this user are not a part of the chat
+    transcript.record(&Event::SearchResults(Vec::new()));
+    transcript.record(&Event::Reactions { id: "1".to_owned(), reactions: vec![("+1".to_owned(), 1)], own: Vec::new() });
+    transcript
+  }
+
+  #[test]
+  fn only_chat_events_are_recorded() {
+    assert_eq!(transcript().len(), 4);
+  }
+
+  #[test]
+  fn text_marks_system_messages() {
+    let text = transcript().render(Format::Text).unwrap();
+    let time = time();
+    assert_eq!(text, format!(
+      "[{time}] * bob joined the chat\n[{time}] <bob> hi **all**\n[{time}] <bob -> alice> psst\n[{time}] * Welcome\n"
+    ));
+  }
+
+  #[test]
+  fn markdown_has_a_header_and_list() {
+    let markdown = transcript().render(Format::Markdown).unwrap();
+    let time = time();
+    assert!(markdown.starts_with("# Chat transcript\n\nExported "), "{markdown}");
+    assert!(markdown.contains(&format!("\n- `{time}` _bob joined the chat_\n")), "{markdown}");
+    assert!(markdown.contains(&format!("\n- `{time}` **bob**: hi **all**\n")), "{markdown}");
+    assert!(markdown.ends_with(&format!("- `{time}` _Welcome_\n")), "{markdown}");
+  }
+
+  #[test]
+  fn json_keeps_the_events() {
+    let json = transcript().render(Format::Json).unwrap();
+    let entries: Vec<Value> = serde_json::from_str(&json).unwrap();
+    assert_eq!(entries.len(), 4);
+
+    assert_eq!(entries[0]["type"], "presence");
+    assert_eq!(entries[0]["system"], true);
+    assert_eq!(entries[1]["type"], "message");
+    assert_eq!(entries[1]["username"], "bob");
+    assert_eq!(entries[1]["system"], false);
+    assert_eq!(entries[1]["timestamp"], TIMESTAMP);
+    assert_eq!(entries[2]["type"], "direct");
+    assert_eq!(entries[2]["system"], false);
+    assert_eq!(entries[3]["type"], "notice");
+    assert_eq!(entries[3]["system"], true);
+    assert!(entries.iter().all(|v| DateTime::parse_from_rfc3339(v["received"].as_str().unwrap()).is_ok()));
+  }
+
+  #[test]
+  fn events_without_a_timestamp_get_the_received_time() {
+    let mut transcript = Transcript::new();
+    transcript.record(&Event::Warning("slow down".to_owned()));
+    transcript.record(&Event::Disconnected(Some("kicked".to_owned())));
+    let text = transcript.render(Format::Text).unwrap();
+    let today = Local::now().format("%Y-%m-%d").to_string();
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines[0].starts_with(&format!("[{today}")) && lines[0].ends_with("] * slow down"), "{text}");
+    assert!(lines[1].ends_with("] * Disconnected: kicked"), "{text}");
+  }
+
+  #[test]
+  fn log_goes_to_a_file_per_server() {
+    let directory = std::env::temp_dir().join(format!("chat-transcript-{}", std::process::id()));
+    let mut transcript = Transcript::new();
+    let path = transcript.log_to(&directory, "chat/server:7878", "alice").unwrap();
+    assert_eq!(path, directory.join("chat_server_7878.log"));
+
+    transcript.record(&Event::Notice { id: None, text: "Welcome".to_owned(), timestamp: Some(TIMESTAMP), sequence: None });
+    drop(transcript);
+    let log = fs::read_to_string(&path).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+
+    let lines: Vec<&str> = log.lines().collect();
+    assert!(lines[0].starts_with("--- Session started ") && lines[0].ends_with(" as alice ---"), "{log}");
+    assert_eq!(lines[1], format!("[{}] * Welcome", time()));
+  }
+}
@@ -0,0 +1,69 @@
+#![allow(dead_code)]
+
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    thread,
+    time::Duration
+  };
+
+// how long the fake server keeps the connection after its last reply
+const LINGER: Duration = Duration::from_secs(2);
+
+// a server that takes one connection, waits for the handshake and
+// answers with 'replies' in one write, returns its address
+pub fn serve(replies: &str) -> String {
+  serve_script(vec![("\r\n\r\n", replies.to_owned())])
+}
+
+// like 'serve', each reply is written once what the client sent
+// so far contains its marker
+pub fn serve_script(script: Vec<(&'static str, String)>) -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap().to_string();
+  thread::spawn(move || {
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut received = Vec::new();
+    let mut buf = [0u8; 1024];
+    for (marker, reply) in script {
+      while !String::from_utf8_lossy(&received).contains(marker) {
+        match stream.read(&mut buf) {
+          Ok(0) | Err(_) => return,
+          Ok(v) => received.extend_from_slice(&buf[..v]),
+        }
+      }
+      received.clear();
+      stream.write_all(reply.as_bytes()).unwrap();
+    }
+    thread::sleep(LINGER);
+  });
+  address
+}
+
+// the answer of a current server, the MOTD goes as the body if given
+pub fn accepted(capabilities: &str, motd: Option<&str>) -> String {
+  let mut frame = format!(
+    "SIGNAL_TYPE: CONNECTION\r\nAUTH_STATUS: ACCEPTED\r\nPROTOCOL_VERSION: 1\r\nCAPABILITIES: {capabilities}\r\nSERVER_NAME: test\r\n"
+  );
+  match motd {
+    Some(v) => frame.push_str(&format!("WITH_MESSAGE\r\n\r\n{v}\r\n\r\n")),
+    None => frame.push_str("\r\n"),
+  }
+  frame
+}
+
+// a chat message as the server relays it
+pub fn message(username: &str, text: &str) -> String {
+  format!("SIGNAL_TYPE: MESSAGE\r\nMESSAGE_ID: {text}\r\nUSERNAME: {username}\r\nWITH_MESSAGE\r\n\r\n{text}\r\n\r\n")
+}
+
+// the join notice which ends the history replay
+pub fn joined(username: &str) -> String {
+  format!(
+    "SIGNAL_TYPE: MESSAGE\r\nMESSAGE_ID: joined\r\nUSERNAME: {username}\r\nWITH_MESSAGE\r\nSERVER_MESSAGE\r\nPRESENCE: JOINED\r\n\r\n{username} joined the chat!\r\n\r\n"
+  )
+}
+
+pub fn warning(text: &str) -> String {
+  format!("SIGNAL_TYPE: WARNING\r\nMESSAGE_ID: warning\r\nWITH_MESSAGE\r\nSERVER_MESSAGE\r\n\r\n{text}\r\n\r\n")
+}
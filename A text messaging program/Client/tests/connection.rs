@@ -0,0 +1,54 @@
+mod common;
+
+use std::{io::ErrorKind, path::Path};
+use client::{types::Capability, ChatClient, FileOffer};
+
+use common::{accepted, serve};
+
+fn offer() -> FileOffer {
+  FileOffer {
+    transfer: "transfer".to_owned(),
+    from: "bob".to_owned(),
+    name: "notes.txt".to_owned(),
+    size: 5,
+    sha256: "0".repeat(64),
+    address: "127.0.0.1:1".to_owned(),
+  }
+}
+
+#[test]
+fn denied_frame_refuses_the_connection() {
+  let address = serve("SIGNAL_TYPE: CONNECTION\r\nAUTH_STATUS: DENIED\r\nPROTOCOL_VERSION: 1\r\n\r\n");
+  let error = ChatClient::connect(&address, "alice").err().unwrap();
+  assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn legacy_denied_status_refuses_the_connection() {
+  let address = serve("AUTH_STATUS: DENIED\r\n\r\n");
+  let error = ChatClient::connect(&address, "alice").err().unwrap();
+  assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn denied_in_the_motd_is_not_a_denial() {
+  // a line of the body is text, even one that looks like a header
+  let address = serve(&accepted("MESSAGE_IDS,MOTD", Some("What spammers get:\r\nAUTH_STATUS: DENIED")));
+  let client = ChatClient::connect(&address, "alice").unwrap();
+  assert_eq!(client.server().name.as_deref(), Some("test"));
+  assert!(client.server().motd.as_deref().unwrap().contains("\r\nAUTH_STATUS: DENIED"));
+}
+
+#[test]
+fn file_transfer_is_refused_without_server_support() {
+  let address = serve(&accepted("MESSAGE_IDS,WARNINGS", None));
+  let mut client = ChatClient::connect(&address, "alice").unwrap();
+  assert!(!client.server().supports(Capability::FileTransfer));
+
+  let error = client.offer_file("bob", Path::new("Cargo.toml"), |_| {}).err().unwrap();
+  assert_eq!(error.kind(), ErrorKind::Unsupported);
+  let error = client.accept_file(&offer(), &std::env::temp_dir(), |_| {}).unwrap_err();
+  assert_eq!(error.kind(), ErrorKind::Unsupported);
+  let error = client.reject_file(&offer()).unwrap_err();
+  assert_eq!(error.kind(), ErrorKind::Unsupported);
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use std::str::FromStr;
+use client::{types::SignalsData, ChatClient, Event, Presence};
+
+use common::{accepted, joined, message, serve, warning};
+
+#[test]
+fn frames_sent_with_the_handshake_answer_are_not_lost() {
+  // all in one write, the handshake reads the rest into its buffer
+  let replies = accepted("MESSAGE_IDS,WARNINGS", None) + &joined("alice") + &message("bob", "hi") + &warning("slow down");
+  let address = serve(&replies);
+  let client = ChatClient::connect(&address, "alice").unwrap();
+
+  let events: Vec<Event> = client.events().unwrap().take(3).collect();
+  assert!(
+    matches!(&events[0], Event::Presence { username, presence: Presence::Joined, .. } if username == "alice"),
+    "{:?}", events[0]
+  );
+  assert!(
+    matches!(&events[1], Event::Message { username, text, .. } if username == "bob" && text == "hi"),
+    "{:?}", events[1]
+  );
+  assert!(matches!(&events[2], Event::Warning(text) if text == "slow down"), "{:?}", events[2]);
+}
+
+#[test]
+fn closed_connection_ends_the_events() {
+  let address = serve(&accepted("MESSAGE_IDS", None));
+  let client = ChatClient::connect(&address, "alice").unwrap();
+  client.disconnect().unwrap();
+
+  let events: Vec<Event> = client.events().unwrap().collect();
+  assert_eq!(events.len(), 1);
+  assert!(matches!(events[0], Event::Disconnected(_)), "{:?}", events[0]);
+}
+
+#[test]
+fn direct_messages_are_told_apart() {
+  let frame = "SIGNAL_TYPE: MESSAGE\r\nUSERNAME: bob\r\nTO: alice\r\nAWAY\r\nWITH_MESSAGE\r\n\r\nwhile you were out\r\n\r\n";
+  let event = Event::from_signal(SignalsData::from_str(frame).unwrap()).unwrap();
+  assert!(
+    matches!(&event, Event::Direct { from, to, text, away: true, .. } if from == "bob" && to == "alice" && text == "while you were out"),
+    "{event:?}"
+  );
+}
+
+#[test]
+fn header_lines_in_a_body_are_text() {
+  let frame = "SIGNAL_TYPE: MESSAGE\r\nMESSAGE_ID: 1\r\nUSERNAME: bob\r\nWITH_MESSAGE\r\n\r\nsee you\r\nSERVER_MESSAGE\r\nPRESENCE: LEFT\r\n\r\n";
+  let event = Event::from_signal(SignalsData::from_str(frame).unwrap()).unwrap();
+  assert!(
+    matches!(&event, Event::Message { id, username, text, .. } if id.as_deref() == Some("1") && username == "bob" && text == "see you\r\nSERVER_MESSAGE\r\nPRESENCE: LEFT"),
+    "{event:?}"
+  );
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use std::process::{Command, Output};
+
+use common::{accepted, joined, message, serve, serve_script, warning};
+
+fn send(address: &str, text: &str) -> Output {
+  Command::new(env!("CARGO_BIN_EXE_client"))
+    .args(["--address", address, "--username", "alice", "--send", text])
+    .output()
+    .unwrap()
+}
+
+#[test]
+fn confirmed_message_exits_with_zero() {
+  // an older copy of the same message in the replay doesn't count
+  let address = serve_script(vec![
+    ("\r\n\r\n", accepted("MESSAGE_IDS,WARNINGS", None) + &message("alice", "hello") + &joined("alice")),
+    ("hello", message("alice", "hello")),
+  ]);
+  let output = send(&address, "hello");
+  assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn rejected_message_exits_with_one() {
+  let address = serve_script(vec![
+    ("\r\n\r\n", accepted("MESSAGE_IDS,WARNINGS", None) + &message("alice", "hello") + &joined("alice")),
+    ("hello", warning("You are sending messages too fast, slow down")),
+  ]);
+  let output = send(&address, "hello");
+  assert_eq!(output.status.code(), Some(1));
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.contains("Message rejected: You are sending messages too fast"), "{stderr}");
+}
+
+#[test]
+fn unconfirmed_message_exits_with_one() {
+  let address = serve_script(vec![
+    ("\r\n\r\n", accepted("MESSAGE_IDS,WARNINGS", None) + &joined("alice")),
+  ]);
+  let output = send(&address, "hello");
+  assert_eq!(output.status.code(), Some(1));
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  // the server went away before echoing it
+  assert!(stderr.contains("before the message was confirmed"), "{stderr}");
+}
+
+#[test]
+fn denied_user_exits_with_three() {
+  let address = serve("SIGNAL_TYPE: CONNECTION\r\nAUTH_STATUS: DENIED\r\nPROTOCOL_VERSION: 1\r\n\r\n");
+  let output = send(&address, "hello");
+  assert_eq!(output.status.code(), Some(3));
+}
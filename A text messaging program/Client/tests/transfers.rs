@@ -0,0 +1,54 @@
+mod common;
+
+use std::{
+    env,
+    fs,
+    sync::mpsc
+  };
+use client::{ChatClient, Event, Progress};
+
+use common::{accepted, serve};
+
+#[test]
+fn nothing_is_written_before_the_offer_is_accepted() {
+  let root = env::temp_dir().join(format!("chat-transfers-{}", std::process::id()));
+  let _ = fs::remove_dir_all(&root);
+  fs::create_dir_all(&root).unwrap();
+  let path = root.join("notes.txt");
+  fs::write(&path, b"lunch at noon").unwrap();
+
+  // bob offers the file, it's served on the address of his chat connection
+  let mut bob = ChatClient::connect(&serve(&accepted("MESSAGE_IDS,FILE_TRANSFER", None)), "bob").unwrap();
+  let (sent, sender_progress) = mpsc::channel();
+  let outgoing = bob.offer_file("alice", &path, move |v| { let _ = sent.send(v); }).unwrap();
+
+  // and alice is told about it
+  let frame = format!(
+    "SIGNAL_TYPE: FILE_OFFER\r\nUSERNAME: bob\r\nTO: alice\r\nTRANSFER: {}\r\nFILE_NAME: {}\r\nFILE_SIZE: {}\r\nSHA256: {}\r\nADDRESS: 127.0.0.1:{}\r\n\r\n\r\n",
+    outgoing.transfer, outgoing.name, outgoing.size, outgoing.sha256, outgoing.port
+  );
+  let address = serve(&(accepted("MESSAGE_IDS,FILE_TRANSFER", None) + &frame));
+  let mut alice = ChatClient::connect(&address, "alice").unwrap();
+  let offer = alice.events().unwrap()
+    .find_map(|v| match v {
+      Event::FileOffer(v) => Some(v),
+      _ => None,
+    })
+    .unwrap();
+  assert_eq!(offer.name, "notes.txt");
+
+  let downloads = root.join("downloads");
+  assert!(!downloads.exists());
+  let (received, progress) = mpsc::channel();
+  alice.accept_file(&offer, &downloads, move |v| { let _ = received.send(v); }).unwrap();
+
+  let finished = progress.iter()
+    .find(|v| matches!(v, Progress::Finished(_) | Progress::Failed(_)));
+  assert_eq!(finished, Some(Progress::Finished(downloads.join("notes.txt"))));
+  assert_eq!(fs::read(downloads.join("notes.txt")).unwrap(), b"lunch at noon");
+  // the progress channels close once the transfer threads end
+  let sent = sender_progress.iter()
+    .find(|v| matches!(v, Progress::Finished(_) | Progress::Failed(_)));
+  assert_eq!(sent, Some(Progress::Finished(path)));
+  fs::remove_dir_all(&root).unwrap();
+}
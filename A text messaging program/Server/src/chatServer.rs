@@ -0,0 +1,278 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant}
+  };
+use anyhow::Result;
+
+use crate::{
+    reader::FrameLimits,
+    service::Service,
+    settings::{Args, RateLimits, Settings},
+    state::State
+  };
+
+// how long 'shutdown' waits for connections to close
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// ----- Embeddable server -----
+//
+//   let server = ChatServer::builder().name("test").start()?;
+//   let address = server.local_addr();
+//   ...
+//   server.shutdown();
+pub struct ChatServer;
+
+impl ChatServer {
+  pub fn builder() -> ChatServerBuilder {
+    ChatServerBuilder::new()
+  }
+
+  // what the binary does: runs until killed, prints the bound addresses
+  pub fn run(settings: Settings) -> Result<()> {
+    Service::run(State::new(settings))
+  }
+}
+
+// starts from the defaults of the command line server,
+// but on a free port of 127.0.0.1 and without a config file
+pub struct ChatServerBuilder {
+  settings: Settings,
+}
+
+impl ChatServerBuilder {
+  fn new() -> ChatServerBuilder {
+    let args = Args {
+      port: Some(0),
+      bind: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+      ..Args::default()
+    };
+    ChatServerBuilder {
+      settings: Settings::from_args(args).expect("defaults without a config file are valid"),
+    }
+  }
+
+  // replaces everything set so far
+  pub fn settings(mut self, settings: Settings) -> ChatServerBuilder {
+    self.settings = settings;
+    self
+  }
+
+  pub fn port(mut self, port: u16) -> ChatServerBuilder {
+    self.settings.port = port;
+    self
+  }
+
+  pub fn bind(mut self, addresses: Vec<IpAddr>) -> ChatServerBuilder {
+    self.settings.bind = if addresses.is_empty() {
+      vec![IpAddr::V6(Ipv6Addr::UNSPECIFIED)]
+    } else {
+      addresses
+    };
+    self
+  }
+
+  pub fn name(mut self, name: &str) -> ChatServerBuilder {
+    self.settings.name = name.to_owned();
+    self
+  }
+
+  pub fn max_users(mut self, max_users: u16) -> ChatServerBuilder {
+    self.settings.max_users = max_users;
+    self
+  }
+
+  pub fn motd(mut self, motd: &str) -> ChatServerBuilder {
+    self.settings.motd = Some(motd.to_owned());
+    self
+  }
+
+  pub fn banned(mut self, banned: Vec<String>) -> ChatServerBuilder {
+    self.settings.banned = banned;
+    self
+  }
+
+  pub fn admins(mut self, admins: Vec<String>) -> ChatServerBuilder {
+    self.settings.admins = admins;
+    self
+  }
+
+  pub fn owners(mut self, owners: Vec<String>) -> ChatServerBuilder {
+    self.settings.owners = owners;
+    self
+  }
+
+  pub fn moderators(mut self, moderators: Vec<String>) -> ChatServerBuilder {
+    self.settings.moderators = moderators;
+    self
+  }
+
+  // what owners, moderators and admins log in with, they can't log in without
+  pub fn owner_token(mut self, token: &str) -> ChatServerBuilder {
+    self.settings.owner_token = Some(token.to_owned());
+    self
+  }
+
+  pub fn moderator_token(mut self, token: &str) -> ChatServerBuilder {
+    self.settings.moderator_token = Some(token.to_owned());
+    self
+  }
+
+  pub fn admin_token(mut self, token: &str) -> ChatServerBuilder {
+    self.settings.admin_token = Some(token.to_owned());
+    self
+  }
+
+  pub fn history(mut self, history: usize) -> ChatServerBuilder {
+    self.settings.history = history;
+    self
+  }
+
+  pub fn mailbox_size(mut self, mailbox_size: u16) -> ChatServerBuilder {
+    self.settings.mailbox_size = mailbox_size;
+    self
+  }
+
+  pub fn rate_limits(mut self, rate_limits: RateLimits) -> ChatServerBuilder {
+    self.settings.rate_limits = rate_limits;
+    self
+  }
+
+  pub fn frame_limits(mut self, frame_limits: FrameLimits) -> ChatServerBuilder {
+    self.settings.frame_limits = frame_limits;
+    self
+  }
+
+  pub fn metrics_port(mut self, port: u16) -> ChatServerBuilder {
+    self.settings.metrics_port = Some(port);
+    self
+  }
+
+  // 0 - any free port
+  pub fn irc_port(mut self, port: u16) -> ChatServerBuilder {
+    self.settings.irc_port = Some(port);
+    self
+  }
+
+  // off by default, links are neither made nor accepted then
+  pub fn federation(mut self, enabled: bool) -> ChatServerBuilder {
+    self.settings.federation = enabled;
+    self
+  }
+
+  pub fn server_id(mut self, server_id: &str) -> ChatServerBuilder {
+    self.settings.server_id = server_id.to_owned();
+    self
+  }
+
+  // servers to link to, host:port
+  pub fn links(mut self, links: Vec<String>) -> ChatServerBuilder {
+    self.settings.links = links;
+    self
+  }
+
+  pub fn link_secret(mut self, secret: &str) -> ChatServerBuilder {
+    self.settings.link_secret = Some(secret.to_owned());
+    self
+  }
+
+  // ids or IP addresses of servers allowed to link to this one
+  pub fn link_allow(mut self, allowed: Vec<String>) -> ChatServerBuilder {
+    self.settings.link_allow = allowed;
+    self
+  }
+
+  pub fn start(self) -> Result<ServerHandle> {
+    Service::start(State::new(self.settings))
+  }
+}
+
+// ----- Running server -----
+pub struct ServerHandle {
+  state: State,
+  addresses: Vec<SocketAddr>,
+  irc_addresses: Vec<SocketAddr>,
+  metrics_address: Option<SocketAddr>,
+  threads: Vec<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+  pub(crate) fn new(
+    state: State,
+    addresses: Vec<SocketAddr>,
+    irc_addresses: Vec<SocketAddr>,
+    metrics_address: Option<SocketAddr>,
+    threads: Vec<JoinHandle<()>>
+  ) -> ServerHandle {
+    ServerHandle { state, addresses, irc_addresses, metrics_address, threads }
+  }
+
+  // the first bound address, with the real port if 0 was asked for
+  pub fn local_addr(&self) -> SocketAddr {
+    self.addresses[0]
+  }
+
+  pub fn addresses(&self) -> &[SocketAddr] {
+    &self.addresses
+  }
+
+  // empty without the IRC gateway
+  pub fn irc_addresses(&self) -> &[SocketAddr] {
+    &self.irc_addresses
+  }
+
+  pub fn metrics_address(&self) -> Option<SocketAddr> {
+    self.metrics_address
+  }
+
+  // usernames of the users connected right now, sorted
+  pub fn users(&self) -> Vec<String> {
+    let mut users: Vec<String> = self.state.get().users.keys().cloned().collect();
+    users.sort();
+    users
+  }
+
+  // users of linked servers as 'user@server', sorted
+  pub fn remote_users(&self) -> Vec<String> {
+    self.state.get().federation.remote_users()
+  }
+
+  // blocks as long as the server runs
+  pub fn wait(self) {
+    for thread in self.threads {
+      let _ = thread.join();
+    }
+  }
+
+  // stops accepting, closes every connection and waits for them to finish
+  pub fn shutdown(self) {
+    let connections: Vec<TcpStream> = {
+      let mut state = self.state.get();
+      state.stopping = true;
+      state.connections.drain().map(|(_, v)| v).collect()
+    };
+    for connection in connections {
+      let _ = connection.shutdown(Shutdown::Both);
+    }
+
+    // listeners block in accept, a connection wakes them up to see the flag
+    for address in self.addresses.iter().chain(self.irc_addresses.iter()).chain(self.metrics_address.iter()) {
+      let _ = TcpStream::connect_timeout(&Self::reachable(*address), SHUTDOWN_TIMEOUT);
+    }
+
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while !self.state.get().users.is_empty() && Instant::now() < deadline {
+      thread::sleep(Duration::from_millis(10));
+    }
+    self.wait();
+  }
+
+  // addresses like 0.0.0.0 can't be connected to everywhere
+  fn reachable(address: SocketAddr) -> SocketAddr {
+    match address.ip() {
+      IpAddr::V4(v) if v.is_unspecified() => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), address.port()),
+      IpAddr::V6(v) if v.is_unspecified() => SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), address.port()),
+      _ => address,
+    }
+  }
+}
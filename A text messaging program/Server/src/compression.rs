@@ -0,0 +1,85 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{Read, Write}
+  };
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+// ----- Body compression -----
+// Bodies of frames are deflated and written in base64, so they never
+// contain the frame end, and the frame gets the COMPRESSION header.
+// It's used only with peers that announced DEFLATE in the handshake,
+// the same file is in the client
+
+// smaller bodies wouldn't get much smaller
+pub const COMPRESSION_THRESHOLD: usize = 512;
+const COMPRESSION_HEADER: &str = "COMPRESSION: DEFLATE";
+const HEADERS_END: &str = "\r\n\r\n";
+
+// ----- Error type -----
+#[derive(Debug)]
+pub enum DecompressError {
+  TooLarge(usize),
+  Invalid,
+}
+
+impl Error for DecompressError {}
+impl fmt::Display for DecompressError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      DecompressError::TooLarge(v) => write!(f, "decompressed message is larger than {v} bytes"),
+      DecompressError::Invalid => write!(f, "compressed message can't be decoded"),
+    }
+  }
+}
+
+// the frame with its body compressed, None if it has no body worth it
+pub fn compress_frame(frame: &str) -> Option<String> {
+  let (headers, rest) = frame.split_once(HEADERS_END)?;
+  let body = rest.strip_suffix(HEADERS_END).unwrap_or(rest);
+  if body.len() < COMPRESSION_THRESHOLD || is_compressed(headers) {
+    return None;
+  }
+
+  let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+  encoder.write_all(body.as_bytes()).ok()?;
+  let encoded = STANDARD.encode(encoder.finish().ok()?);
+  // the header has to be paid for too
+  if encoded.len() + COMPRESSION_HEADER.len() + 2 >= body.len() {
+    return None;
+  }
+  Some(format!("{COMPRESSION_HEADER}\r\n{headers}{HEADERS_END}{encoded}{HEADERS_END}"))
+}
+
+// the frame as it was before compression, other frames as they are;
+// 'max_body_bytes' keeps small frames from unpacking into huge ones
+pub fn decompress_frame(frame: String, max_body_bytes: usize) -> Result<String, DecompressError> {
+  let Some((headers, rest)) = frame.split_once(HEADERS_END) else { return Ok(frame) };
+  if !is_compressed(headers) {
+    return Ok(frame);
+  }
+
+  let encoded = rest.strip_suffix(HEADERS_END).unwrap_or(rest).trim();
+  let compressed = STANDARD.decode(encoded).map_err(|_| DecompressError::Invalid)?;
+  let mut body = Vec::new();
+  DeflateDecoder::new(compressed.as_slice())
+    .take(max_body_bytes as u64 + 1)
+    .read_to_end(&mut body)
+    .map_err(|_| DecompressError::Invalid)?;
+  if body.len() > max_body_bytes {
+    return Err(DecompressError::TooLarge(max_body_bytes));
+  }
+  let body = String::from_utf8(body).map_err(|_| DecompressError::Invalid)?;
+  // the frame end in the body would make the rest of it frames of their own
+  if body.contains(HEADERS_END) {
+    return Err(DecompressError::Invalid);
+  }
+
+  let headers: Vec<&str> = headers.split("\r\n").filter(|v| *v != COMPRESSION_HEADER).collect();
+  Ok(format!("{}{HEADERS_END}{body}{HEADERS_END}", headers.join("\r\n")))
+}
+
+fn is_compressed(headers: &str) -> bool {
+  headers.split("\r\n").any(|v| v == COMPRESSION_HEADER)
+}
@@ -0,0 +1,300 @@
+use std::{fs, net::IpAddr, path::Path, str::FromStr};
+use anyhow::{anyhow, bail, Context, Result};
+use log::LevelFilter;
+use serde::Deserialize;
+
+use crate::{federation::is_valid_server_id, messagesPool::MAX_CAPACITY, settings::parse_bind_address};
+
+// ----- Config file -----
+// every key is optional, missing ones fall back to the defaults
+// in 'Settings', command line args win over the file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  pub port: Option<u16>,
+  pub bind: Vec<String>,
+  pub max_users: Option<u16>,
+  pub name: Option<String>,
+  pub motd: Option<String>,
+  pub banned: Vec<String>,
+  // users allowed to request server stats
+  pub admins: Vec<String>,
+  // users who can kick, mute and ban, owners also moderators
+  pub owners: Vec<String>,
+  pub moderators: Vec<String>,
+  // what users of the lists above log in with
+  pub tokens: TokensConfig,
+  // messages kept in the history, see 'messagesPool.rs'
+  pub history: Option<usize>,
+  // direct messages kept for one offline user, 0 - none
+  pub mailbox_size: Option<u16>,
+  pub rate_limit: RateLimitConfig,
+  pub limits: LimitsConfig,
+  pub log: LogConfig,
+  pub metrics: MetricsConfig,
+  pub irc: IrcConfig,
+  pub federation: FederationConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokensConfig {
+  pub owner: Option<String>,
+  pub moderator: Option<String>,
+  pub admin: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+  pub messages_per_sec: Option<u32>,
+  pub bytes_per_sec: Option<u32>,
+  pub ip_messages_per_sec: Option<u32>,
+  pub ip_bytes_per_sec: Option<u32>,
+  pub burst_secs: Option<u32>,
+  pub warnings_before_mute: Option<u32>,
+  pub mute_secs: Option<u32>,
+  pub mutes_before_disconnect: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+  pub max_header_bytes: Option<usize>,
+  pub max_body_bytes: Option<usize>,
+  pub frame_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+  // plain text metrics on 127.0.0.1, off if not set
+  pub port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IrcConfig {
+  // IRC clients are served on this port of the bound addresses, off if not set
+  pub port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FederationConfig {
+  // off by default, no links are made or accepted then
+  pub enabled: bool,
+  pub server_id: Option<String>,
+  // servers this one links to, links from others are accepted too
+  pub links: Vec<String>,
+  // has to be the same on both sides of a link
+  pub secret: Option<String>,
+  // ids or IP addresses of servers allowed to link, links from other
+  // servers are accepted only with 'secret' or from this list
+  pub allow: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+  pub level: Option<String>,
+}
+
+impl Config {
+  pub fn read(path: &Path) -> Result<Config> {
+    let text = fs::read_to_string(path)
+      .with_context(|| format!("cannot read config file {}", path.display()))?;
+
+    let config: Config = toml::from_str(&text)
+      .map_err(|e| anyhow!("{}: {e}", path.display()))?;
+    config.validate()
+      .map_err(|e| anyhow!("{}: {e}", path.display()))?;
+
+    Ok(config)
+  }
+
+  fn validate(&self) -> Result<()> {
+    self.bind_addresses()?;
+    self.log_level()?;
+
+    if self.name.as_ref().is_some_and(|v| v.trim().is_empty() || v.contains('\n')) {
+      bail!("invalid `name`: must be a non-empty single line");
+    }
+    if self.max_users == Some(0) {
+      bail!("invalid `max_users`: must be greater than 0");
+    }
+    if let Some(v) = self.history {
+      if v == 0 || v > MAX_CAPACITY {
+        bail!("invalid `history`: must be between 1 and {MAX_CAPACITY}, got {v}");
+      }
+    }
+    if self.rate_limit.burst_secs == Some(0) {
+      bail!("invalid `rate_limit.burst_secs`: must be greater than 0");
+    }
+    if self.rate_limit.mute_secs == Some(0) {
+      bail!("invalid `rate_limit.mute_secs`: must be greater than 0");
+    }
+    if self.limits.max_header_bytes.is_some_and(|v| v < 64) {
+      bail!("invalid `limits.max_header_bytes`: must be at least 64");
+    }
+    if self.limits.max_body_bytes == Some(0) {
+      bail!("invalid `limits.max_body_bytes`: must be greater than 0");
+    }
+    if self.limits.frame_timeout_secs == Some(0) {
+      bail!("invalid `limits.frame_timeout_secs`: must be greater than 0");
+    }
+    for (index, v) in self.banned.iter().enumerate() {
+      if v.trim().is_empty() {
+        bail!("invalid `banned[{index}]`: must not be empty");
+      }
+    }
+    for (index, v) in self.admins.iter().enumerate() {
+      if v.trim().is_empty() {
+        bail!("invalid `admins[{index}]`: must not be empty");
+      }
+    }
+    for (index, v) in self.owners.iter().enumerate() {
+      if v.trim().is_empty() {
+        bail!("invalid `owners[{index}]`: must not be empty");
+      }
+    }
+    for (index, v) in self.moderators.iter().enumerate() {
+      if v.trim().is_empty() {
+        bail!("invalid `moderators[{index}]`: must not be empty");
+      }
+    }
+    // a privileged name without a token could be taken by anyone
+    let tokens = [
+      ("owner", &self.tokens.owner, &self.owners),
+      ("moderator", &self.tokens.moderator, &self.moderators),
+      ("admin", &self.tokens.admin, &self.admins),
+    ];
+    for (role, token, users) in tokens {
+      if token.as_ref().is_some_and(|v| v.trim().is_empty() || v.contains('\n')) {
+        bail!("invalid `tokens.{role}`: must be a non-empty single line");
+      }
+      if token.is_none() && !users.is_empty() {
+        bail!("missing `tokens.{role}`: required when {role}s are set");
+      }
+    }
+    if let Some(v) = self.federation.server_id.as_ref().filter(|v| !is_valid_server_id(v)) {
+      bail!("invalid `federation.server_id`: '{v}', use letters, digits, '-', '_' and '.' only");
+    }
+    for (index, v) in self.federation.links.iter().enumerate() {
+      if !v.contains(':') {
+        bail!("invalid `federation.links[{index}]`: '{v}' has no port, expected host:port");
+      }
+    }
+    if self.federation.secret.as_ref().is_some_and(|v| v.trim().is_empty() || v.contains('\n')) {
+      bail!("invalid `federation.secret`: must be a non-empty single line");
+    }
+    if let Some((index, v)) = self.federation.allow.iter().enumerate().find(|(_, v)| v.trim().is_empty()) {
+      bail!("invalid `federation.allow[{index}]`: '{v}', expected a server id or an IP address");
+    }
+
+    Ok(())
+  }
+
+  pub fn bind_addresses(&self) -> Result<Vec<IpAddr>> {
+    self.bind.iter()
+      .enumerate()
+      .map(|(index, v)| parse_bind_address(v).map_err(|e| anyhow!("invalid `bind[{index}]`: {e}")))
+      .collect()
+  }
+
+  pub fn log_level(&self) -> Result<Option<LevelFilter>> {
+    match &self.log.level {
+      Some(v) => LevelFilter::from_str(v)
+        .map(Some)
+        .map_err(|_| anyhow!("invalid `log.level`: '{v}', expected off, error, warn, info, debug or trace")),
+      None => Ok(None),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(text: &str) -> Result<Config> {
+    let config: Config = toml::from_str(text)?;
+    config.validate()?;
+    Ok(config)
+  }
+
+  #[test]
+  fn missing_keys_fall_back_to_defaults() {
+    let config = parse("").unwrap();
+    assert_eq!(config.port, None);
+    assert!(config.bind.is_empty());
+    assert_eq!(config.log_level().unwrap(), None);
+  }
+
+  #[test]
+  fn nested_tables_are_read() {
+    let config = parse(r#"
+      port = 7000
+      bind = ["127.0.0.1", "::1"]
+      banned = ["mallory", "10.0.0.1"]
+
+      [rate_limit]
+      messages_per_sec = 5
+
+      [log]
+      level = "debug"
+    "#).unwrap();
+    assert_eq!(config.port, Some(7000));
+    assert_eq!(config.bind_addresses().unwrap().len(), 2);
+    assert_eq!(config.banned, ["mallory", "10.0.0.1"]);
+    assert_eq!(config.rate_limit.messages_per_sec, Some(5));
+    assert_eq!(config.log_level().unwrap(), Some(LevelFilter::Debug));
+  }
+
+  #[test]
+  fn unknown_keys_are_refused() {
+    assert!(parse("prot = 7000").is_err());
+    assert!(parse("[limits]\nmax_body = 10").is_err());
+  }
+
+  #[test]
+  fn invalid_values_are_refused() {
+    for text in [
+      "bind = [\"localhost:1\"]",
+      "max_users = 0",
+      "name = \"  \"",
+      "history = 0",
+      "banned = [\"\"]",
+      "[rate_limit]\nburst_secs = 0",
+      "[limits]\nmax_header_bytes = 10",
+      "[log]\nlevel = \"loud\"",
+      "[federation]\nlinks = [\"example.com\"]",
+      "[federation]\nallow = [\" \"]",
+      "[tokens]\nowner = \" \"",
+    ] {
+      let error = parse(text).unwrap_err().to_string();
+      assert!(error.starts_with("invalid `"), "{text}: {error}");
+    }
+  }
+
+  #[test]
+  fn privileged_users_need_a_token() {
+    let error = parse("owners = [\"alice\"]").unwrap_err().to_string();
+    assert_eq!(error, "missing `tokens.owner`: required when owners are set");
+    assert!(parse("admins = [\"alice\"]\n[tokens]\nowner = \"secret\"").is_err());
+
+    let config = parse("owners = [\"alice\"]\nadmins = [\"alice\"]\n[tokens]\nowner = \"a\"\nadmin = \"b\"").unwrap();
+    assert_eq!(config.tokens.owner.as_deref(), Some("a"));
+    assert_eq!(config.tokens.admin.as_deref(), Some("b"));
+  }
+
+  #[test]
+  fn read_names_the_file() {
+    let path = std::env::temp_dir().join(format!("chat-server-config-{}.toml", std::process::id()));
+    fs::write(&path, "max_users = 0").unwrap();
+    let error = Config::read(&path).unwrap_err().to_string();
+    fs::remove_file(&path).unwrap();
+    assert!(error.contains(&path.display().to_string()), "{error}");
+
+    assert!(Config::read(Path::new("/nonexistent/server.toml")).is_err());
+  }
+}
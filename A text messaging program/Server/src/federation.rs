@@ -0,0 +1,489 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{BufReader, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant}
+  };
+use anyhow::{anyhow, bail, Result};
+use log::{info, warn};
+use parking_lot::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    messagesPool::{now_millis, MessagesPool, PoolMessage, Since},
+    metrics::Metrics,
+    reader::StreamReader,
+    state::State,
+    types::{Authoritation, Presence, Signal, SignalError, SignalsData, SignalsHeader, PROTOCOL_VERSION}
+  };
+
+// ----- Server links -----
+// Two servers connect over the usual port, the one with the link in
+// its config opens it with a LINK frame. After that both sides send
+// each other every chat message and presence notice of their pool as
+// MESSAGE frames with ORIGIN, the server the message was sent on, and
+// VIA, every server it passed through. A server never sends a message
+// back to a server in VIA and drops ids it has seen already, so
+// messages go around rings of links once. Users of other servers are
+// named 'user@server', local usernames can't contain '@'. Federation
+// is off unless enabled, a server linking in needs the link secret or
+// a place on the allowlist (see 'Settings::accepts_link').
+
+// ids of relayed messages kept to drop copies coming from another side
+const SEEN_CAPACITY: usize = 4096;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+pub fn is_valid_server_id(id: &str) -> bool {
+  !id.is_empty()
+    && id.len() <= 64
+    && id.chars().all(|v| v.is_ascii_alphanumeric() || matches!(v, '-' | '_' | '.'))
+}
+
+// 'Office chat' becomes 'office-chat'
+pub fn default_server_id(name: &str) -> String {
+  let id: String = name.trim()
+    .chars()
+    .map(|v| if v.is_ascii_alphanumeric() || matches!(v, '_' | '.') { v.to_ascii_lowercase() } else { '-' })
+    .take(64)
+    .collect();
+  if is_valid_server_id(&id) { id } else { "chat-server".to_owned() }
+}
+
+// ----- Federation state -----
+// kept in 'StateData', shared by all links
+pub struct Federation {
+  links: HashSet<String>,
+  // every remote user and the server linked to this one that told about them
+  remote_users: HashMap<String, String>,
+  seen: VecDeque<String>,
+  seen_ids: HashSet<String>,
+}
+
+impl Federation {
+  pub fn new() -> Federation {
+    Federation {
+      links: HashSet::new(),
+      remote_users: HashMap::new(),
+      seen: VecDeque::new(),
+      seen_ids: HashSet::new(),
+    }
+  }
+
+  // sorted 'user@server' names
+  pub fn remote_users(&self) -> Vec<String> {
+    let mut users: Vec<String> = self.remote_users.keys().cloned().collect();
+    users.sort();
+    users
+  }
+
+  // false if the server is linked already
+  fn add_link(&mut self, server_id: &str) -> bool {
+    self.links.insert(server_id.to_owned())
+  }
+
+  // users known through the link, they are gone with it
+  fn remove_link(&mut self, server_id: &str) -> Vec<String> {
+    self.links.remove(server_id);
+    let mut gone: Vec<String> = self.remote_users.iter()
+      .filter(|(_, link)| *link == server_id)
+      .map(|(user, _)| user.clone())
+      .collect();
+    gone.sort();
+    for user in gone.iter() {
+      self.remote_users.remove(user);
+    }
+    gone
+  }
+
+  // false for ids that were relayed already
+  fn first_seen(&mut self, id: &str) -> bool {
+    if !self.seen_ids.insert(id.to_owned()) {
+      return false;
+    }
+    self.seen.push_back(id.to_owned());
+    if self.seen.len() > SEEN_CAPACITY {
+      if let Some(v) = self.seen.pop_front() {
+        self.seen_ids.remove(&v);
+      }
+    }
+    true
+  }
+}
+
+// ----- Link to another server -----
+pub struct Link {
+  stream: TcpStream,
+  state: State,
+  messages_pool: Arc<Mutex<MessagesPool>>,
+  metrics: Arc<Metrics>,
+  server_id: String,
+  peer_id: String,
+  peer_addr: String,
+}
+
+impl Link {
+  // keeps a link from the config up as long as the server runs
+  pub fn maintain(address: String, state: State, messages_pool: Arc<Mutex<MessagesPool>>) {
+    let mut delay = RECONNECT_MIN;
+    while !state.get().stopping {
+      match Self::dial(&address, &state, &messages_pool) {
+        Ok((link, reader)) => {
+          delay = RECONNECT_MIN;
+          if let Err(e) = link.run(reader) {
+            warn!(address = address.as_str(), error = format!("{e:#}").as_str(); "server link broken");
+          }
+        },
+        Err(e) => warn!(address = address.as_str(), error = format!("{e:#}").as_str(); "server link failed"),
+      }
+
+      // short naps, so a stopping server doesn't wait for the whole delay
+      let retry_at = Instant::now() + delay;
+      while Instant::now() < retry_at && !state.get().stopping {
+        thread::sleep(Duration::from_millis(50));
+      }
+      delay = (delay * 2).min(RECONNECT_MAX);
+    }
+  }
+
+  // the other side of 'maintain', 'signal' is the LINK frame that came first
+  pub fn accept(
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    signal: &str,
+    state: State,
+    messages_pool: Arc<Mutex<MessagesPool>>
+  ) -> Result<()> {
+    let data = SignalsData::from_str(signal)?;
+    let peer_ip = stream.peer_addr()?.ip();
+    let (server_id, accepted) = {
+      let state = state.get();
+      let accepted = match data.serverId.as_deref() {
+        Some(v) if is_valid_server_id(v) => state.settings.accepts_link(v, peer_ip, data.secret.as_deref()),
+        _ => Err(SignalError.into()),
+      };
+      (state.settings.server_id.clone(), accepted)
+    };
+
+    let opened = accepted.and_then(|_| {
+      let peer_id = data.serverId.clone().unwrap_or_default();
+      Self::open(stream.try_clone()?, state, messages_pool, server_id.clone(), peer_id)
+    });
+    let link = match opened {
+      Ok(v) => v,
+      Err(e) => {
+        warn!(
+          peer = stream.peer_addr().map(|v| v.to_string()).unwrap_or_default().as_str(),
+          server = data.serverId.as_deref().unwrap_or_default(),
+          error = e.to_string().as_str();
+          "server link refused"
+        );
+        let answer = Self::answer(Authoritation::Denied, &server_id);
+        (&stream).write_all(answer.as_bytes())?;
+        return Err(e);
+      }
+    };
+
+    link.send(&Self::answer(Authoritation::Accepted, &server_id))?;
+    link.run(reader)
+  }
+
+  fn answer(auth: Authoritation, server_id: &str) -> String {
+    SignalsData::new(
+      vec![
+        SignalsHeader::signalType(Signal::Link),
+        SignalsHeader::auth(auth),
+        SignalsHeader::protocolVersion(PROTOCOL_VERSION),
+        SignalsHeader::serverId(server_id.to_owned())
+      ],
+      None
+    ).to_string()
+  }
+
+  fn dial(address: &str, state: &State, messages_pool: &Arc<Mutex<MessagesPool>>) -> Result<(Link, BufReader<TcpStream>)> {
+    let (server_id, secret, limits) = {
+      let state = state.get();
+      (state.settings.server_id.clone(), state.settings.link_secret.clone(), state.settings.frame_limits.clone())
+    };
+
+    let mut last_error = anyhow!("'{address}' has no addresses");
+    let mut stream = None;
+    for addr in address.to_socket_addrs()? {
+      match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+        Ok(v) => {
+          stream = Some(v);
+          break;
+        },
+        Err(e) => last_error = anyhow!("{addr}: {e}"),
+      }
+    }
+    let stream = stream.ok_or(last_error)?;
+
+    let mut headers = vec![
+      SignalsHeader::signalType(Signal::Link),
+      SignalsHeader::protocolVersion(PROTOCOL_VERSION),
+      SignalsHeader::serverId(server_id.clone())
+    ];
+    if let Some(v) = secret {
+      headers.push(SignalsHeader::secret(v));
+    }
+    (&stream).write_all(SignalsData::new(headers, None).to_string().as_bytes())?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let answer = SignalsData::from_str(&reader.read_first_signal(&limits)?)?;
+    let peer_id = match (answer.signalType, answer.auth, answer.serverId) {
+      (Some(Signal::Link), Some(Authoritation::Accepted), Some(v)) if is_valid_server_id(&v) => v,
+      _ => bail!("{address} refused the link"),
+    };
+
+    let link = Self::open(stream, state.clone(), messages_pool.clone(), server_id, peer_id)?;
+    Ok((link, reader))
+  }
+
+  // registers the link, one per pair of servers
+  fn open(
+    stream: TcpStream,
+    state: State,
+    messages_pool: Arc<Mutex<MessagesPool>>,
+    server_id: String,
+    peer_id: String
+  ) -> Result<Link> {
+    if peer_id == server_id {
+      bail!("{peer_id} is the id of this server");
+    }
+    let peer_addr = stream.peer_addr()?.to_string();
+    let metrics = {
+      let mut state = state.get();
+      if !state.federation.add_link(&peer_id) {
+        bail!("{peer_id} is linked already");
+      }
+      state.metrics.clone()
+    };
+
+    Ok(Link { stream, state, messages_pool, metrics, server_id, peer_id, peer_addr })
+  }
+
+  // relays the pool to the peer and the peer's frames to the pool
+  // until either side closes the link
+  fn run(self, reader: BufReader<TcpStream>) -> Result<()> {
+    info!(peer = self.peer_addr.as_str(), server = self.peer_id.as_str(); "server linked");
+    self.state.get().connections.insert(self.peer_addr.clone(), self.stream.try_clone()?);
+
+    let mut last_read_sequence = self.messages_pool.lock().last_sequence();
+    let (done_sender, done_receiver) = mpsc::channel::<()>();
+    let receiver_thread = {
+      let state = self.state.clone();
+      let messages_pool = self.messages_pool.clone();
+      let metrics = self.metrics.clone();
+      let server_id = self.server_id.clone();
+      let peer_id = self.peer_id.clone();
+      thread::spawn(move || {
+        Self::receive_all(reader, &state, &messages_pool, &metrics, &server_id, &peer_id);
+        let _ = done_sender.send(());
+      })
+    };
+
+    let mut result = self.snapshot().iter()
+      .filter_map(|v| self.relay_frame(v))
+      .try_for_each(|v| self.send(&v));
+    while result.is_ok() && done_receiver.try_recv().is_err() {
+      let (since, last) = {
+        let messages_pool = self.messages_pool.lock();
+        (messages_pool.read_since(last_read_sequence), messages_pool.last_sequence())
+      };
+      last_read_sequence = last;
+      let messages = match since {
+        Since::Messages(v) => v,
+        Since::Gap { missed, messages } => {
+          warn!(server = self.peer_id.as_str(), missed = missed; "link fell behind the history, messages not relayed");
+          messages
+        },
+      };
+      result = messages.iter()
+        .filter_map(|v| self.relay_frame(v))
+        .try_for_each(|v| self.send(&v));
+      thread::sleep(Duration::from_millis(10));
+    }
+
+    let _ = self.stream.shutdown(Shutdown::Both);
+    let _ = receiver_thread.join();
+    self.state.get().connections.remove(&self.peer_addr);
+    info!(peer = self.peer_addr.as_str(), server = self.peer_id.as_str(); "server link closed");
+    result
+  }
+
+  fn send(&self, frame: &str) -> Result<()> {
+    (&self.stream).write_all(frame.as_bytes())?;
+    Metrics::count(&self.metrics.bytes_out, frame.len() as u64);
+    Ok(())
+  }
+
+  // users the peer has to know about when the link comes up: local
+  // ones and those of other links
+  fn snapshot(&self) -> Vec<PoolMessage> {
+    let state = self.state.get();
+    let local = state.users.keys()
+      .map(|v| (format!("{v}@{}", self.server_id), self.server_id.clone(), Vec::new()));
+    let remote = state.federation.remote_users.iter()
+      .filter(|(_, link)| **link != self.peer_id)
+      .filter_map(|(user, _)| user.rsplit_once('@').map(|(_, origin)| (user.clone(), origin.to_owned())))
+      .map(|(user, origin)| (user, origin.clone(), vec![origin]));
+
+    local.chain(remote)
+      .map(|(username, origin, via)| PoolMessage {
+        id: Uuid::new_v4().to_string(),
+        message: format!("{username} joined the chat!"),
+        username,
+        from_server: true,
+        presence: Some(Presence::Joined),
+        timestamp: now_millis(),
+        origin: Some(origin),
+        via,
+        ..PoolMessage::new()
+      })
+      .collect()
+  }
+
+  // chat messages and presence notices go to other servers, other
+  // notices of this server and messages the peer has seen don't
+  fn relay_frame(&self, message: &PoolMessage) -> Option<String> {
+    if message.from_server && message.presence.is_none() {
+      return None;
+    }
+    if message.origin.as_ref() == Some(&self.peer_id) || message.via.contains(&self.peer_id) {
+      return None;
+    }
+
+    let (username, origin) = match &message.origin {
+      Some(v) => (message.username.clone(), v.clone()),
+      None => (format!("{}@{}", message.username, self.server_id), self.server_id.clone()),
+    };
+    let mut via = message.via.clone();
+    via.push(self.server_id.clone());
+
+    let mut headers = vec![
+      SignalsHeader::signalType(Signal::Message),
+      SignalsHeader::id(message.id.clone()),
+      SignalsHeader::username(username),
+      SignalsHeader::timestamp(message.timestamp),
+      SignalsHeader::origin(origin),
+      SignalsHeader::via(via),
+      SignalsHeader::withMess
+    ];
+    if let Some(v) = message.presence {
+      headers.push(SignalsHeader::presence(v));
+      headers.push(SignalsHeader::serverMess);
+    }
+    if let Some(v) = &message.reply_to {
+      headers.push(SignalsHeader::replyTo(v.clone()));
+    }
+    Some(SignalsData::new(headers, Some(&message.message)).to_string())
+  }
+
+  fn receive_all(
+    mut reader: BufReader<TcpStream>,
+    state: &State,
+    messages_pool: &Mutex<MessagesPool>,
+    metrics: &Metrics,
+    server_id: &str,
+    peer_id: &str
+  ) {
+    loop {
+      let limits = state.get().settings.frame_limits.clone();
+      let frame = match reader.read_signal(&limits) {
+        Ok(v) => v,
+        Err(e) if e.is_disconnect() => break,
+        Err(e) => {
+          Metrics::count(&metrics.dropped_frames, 1);
+          warn!(server = peer_id, error = e.to_string().as_str(); "dropping server link");
+          break;
+        }
+      };
+      Metrics::count(&metrics.bytes_in, frame.len() as u64);
+
+      // a server that sends nonsense can't be trusted with the rest either
+      if let Err(e) = Self::receive(state, messages_pool, server_id, peer_id, &frame) {
+        Metrics::count(&metrics.dropped_frames, 1);
+        warn!(server = peer_id, error = e.to_string().as_str(); "dropping server link");
+        break;
+      }
+    }
+  }
+
+  // puts a message of the peer into the pool, copies that came around
+  // a ring of links are dropped
+  fn receive(state: &State, messages_pool: &Mutex<MessagesPool>, server_id: &str, peer_id: &str, frame: &str) -> Result<()> {
+    let data = SignalsData::from_str(frame)?;
+    let (Some(Signal::Message), Some(id), Some(username), Some(origin), Some(via)) =
+      (data.signalType, data.id, data.username, data.origin, data.via) else {
+      return Err(SignalError.into());
+    };
+    let (_, user_server) = username.rsplit_once('@').ok_or(SignalError)?;
+    if user_server != origin || via.first() != Some(&origin) || via.last().map(String::as_str) != Some(peer_id) {
+      bail!("{username} came with a forged origin");
+    }
+    if origin == server_id || via.iter().any(|v| v == server_id) {
+      return Ok(());
+    }
+
+    let mut state = state.get();
+    if !state.federation.first_seen(&id) {
+      return Ok(());
+    }
+    let text = match data.presence {
+      // repeated notices about the same user come from other links
+      Some(Presence::Joined) => {
+        if state.federation.remote_users.insert(username.clone(), peer_id.to_owned()).is_some() {
+          return Ok(());
+        }
+        format!("{username} joined the chat!")
+      },
+      Some(Presence::Left) => {
+        if state.federation.remote_users.remove(&username).is_none() {
+          return Ok(());
+        }
+        format!("{username} left the chat!")
+      },
+      None => data.message.as_deref().map(str::trim).filter(|v| !v.is_empty()).ok_or(SignalError)?.to_owned(),
+    };
+    drop(state);
+
+    messages_pool.lock().push(PoolMessage {
+      id,
+      username,
+      message: text,
+      from_server: data.presence.is_some(),
+      presence: data.presence,
+      origin: Some(origin),
+      via,
+      reply_to: data.replyTo,
+      ..PoolMessage::new()
+    });
+    Ok(())
+  }
+}
+
+// users of the peer and of servers behind it leave with the link
+impl Drop for Link {
+  fn drop(&mut self) {
+    let gone = self.state.get().federation.remove_link(&self.peer_id);
+    let mut messages_pool = self.messages_pool.lock();
+    for username in gone {
+      let origin = username.rsplit_once('@').map(|v| v.1.to_owned()).unwrap_or_default();
+      messages_pool.push(PoolMessage {
+        id: Uuid::new_v4().to_string(),
+        message: format!("{username} left the chat!"),
+        username,
+        from_server: true,
+        presence: Some(Presence::Left),
+        origin: Some(origin.clone()),
+        via: vec![origin],
+        ..PoolMessage::new()
+      });
+    }
+  }
+}
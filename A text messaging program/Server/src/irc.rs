@@ -0,0 +1,634 @@
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    net::{IpAddr, Shutdown, TcpStream},
+    sync::{
+      atomic::{AtomicBool, Ordering},
+      mpsc::{self, Receiver, Sender},
+      Arc
+    },
+    thread,
+    time::Duration
+  };
+use anyhow::{bail, Result};
+use log::{info, warn};
+use parking_lot::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    messagesPool::{now_millis, MessagesPool, PoolMessage, Since},
+    metrics::Metrics,
+    rateLimiter::{FloodGuard, Verdict},
+    state::{State, UserData},
+    types::Presence
+  };
+
+// ----- IRC gateway -----
+// A second listener for IRC clients. The chat is one room, shown to
+// them as CHANNEL, and PRIVMSG to a nick is a direct message. Only
+// what a client needs to chat is understood: NICK, USER, JOIN, PART,
+// PRIVMSG, NAMES, PING and QUIT.
+
+pub const CHANNEL: &str = "#chat";
+// RFC 1459 allows 512 bytes, newer clients send longer lines
+const MAX_LINE_BYTES: usize = 8192;
+const MAX_NICK_LEN: usize = 30;
+// names per RPL_NAMREPLY line
+const NAMES_PER_LINE: usize = 20;
+
+// what the reader thread tells the thread writing to the client
+enum Event {
+  Reply(String),
+  // sequence number of the last message in the pool before the JOIN notice
+  Joined(u64),
+  Closed,
+}
+
+// ----- Command line -----
+// [:prefix] COMMAND param... [:trailing], the prefix of clients is ignored
+struct Command {
+  name: String,
+  params: Vec<String>,
+}
+
+impl Command {
+  fn parse(line: &str) -> Option<Command> {
+    let mut rest = line.trim_start();
+    if rest.starts_with(':') {
+      rest = rest.split_once(' ')?.1.trim_start();
+    }
+    let (middle, trailing) = match rest.split_once(" :") {
+      Some((middle, trailing)) => (middle, Some(trailing)),
+      None => (rest, None),
+    };
+
+    let mut words = middle.split(' ').filter(|v| !v.is_empty());
+    let name = words.next()?.to_ascii_uppercase();
+    let mut params: Vec<String> = words.map(str::to_owned).collect();
+    if let Some(v) = trailing {
+      params.push(v.to_owned());
+    }
+    Some(Command { name, params })
+  }
+}
+
+fn is_valid_nick(nick: &str) -> bool {
+  let special = |v: char| matches!(v, '[' | ']' | '\\' | '`' | '_' | '^' | '{' | '|' | '}');
+  let mut chars = nick.chars();
+  nick.len() <= MAX_NICK_LEN
+    && chars.next().is_some_and(|v| v.is_ascii_alphabetic() || special(v))
+    && chars.all(|v| v.is_ascii_alphanumeric() || special(v) || v == '-')
+}
+
+// CR, LF and NUL can't be part of an IRC line
+fn strip_line_breaks(line: &str) -> String {
+  line.chars().filter(|v| !matches!(v, '\r' | '\n' | '\0')).collect()
+}
+
+// usernames of the chat as IRC nicks, 'alice@office' becomes 'alice|office'
+fn irc_nick(username: &str) -> String {
+  username.chars()
+    .map(|v| match v {
+      '@' => '|',
+      v if v.is_ascii_alphanumeric() || "[]\\`_^{|}-".contains(v) => v,
+      _ => '_',
+    })
+    .collect()
+}
+
+// ----- Session of one IRC client -----
+pub struct IrcSession {
+  stream: TcpStream,
+  state: State,
+  messages_pool: Arc<Mutex<MessagesPool>>,
+  metrics: Arc<Metrics>,
+  server_id: String,
+  peer_addr: String,
+  peer_ip: IpAddr,
+  nick: Option<String>,
+  // set by the reader thread, read by the one relaying the pool
+  joined: Arc<AtomicBool>,
+}
+
+impl IrcSession {
+  pub fn serve(stream: TcpStream, state: State, messages_pool: Arc<Mutex<MessagesPool>>) -> Result<()> {
+    let (metrics, server_id) = {
+      let state = state.get();
+      (state.metrics.clone(), state.settings.server_id.clone())
+    };
+    Metrics::count(&metrics.connections_total, 1);
+
+    let peer = stream.peer_addr()?;
+    let mut session = IrcSession {
+      stream,
+      state,
+      messages_pool,
+      metrics,
+      server_id,
+      peer_addr: peer.to_string(),
+      peer_ip: peer.ip(),
+      nick: None,
+      joined: Arc::new(AtomicBool::new(false)),
+    };
+    info!(peer = session.peer_addr.as_str(); "irc connection established");
+
+    let stream = session.stream.try_clone()?;
+    session.state.get().connections.insert(session.peer_addr.clone(), stream);
+    let result = session.process();
+    session.state.get().connections.remove(&session.peer_addr);
+    session.leave();
+    result
+  }
+
+  fn try_clone(&self) -> Result<IrcSession> {
+    Ok(IrcSession {
+      stream: self.stream.try_clone()?,
+      state: self.state.clone(),
+      messages_pool: self.messages_pool.clone(),
+      metrics: self.metrics.clone(),
+      server_id: self.server_id.clone(),
+      peer_addr: self.peer_addr.clone(),
+      peer_ip: self.peer_ip,
+      nick: self.nick.clone(),
+      joined: self.joined.clone(),
+    })
+  }
+
+  fn process(&mut self) -> Result<()> {
+    let mut reader = BufReader::new(self.stream.try_clone()?);
+    // like the handshake of the chat, registration has to be quick
+    let timeout = self.state.get().settings.frame_limits.frame_timeout;
+    self.stream.set_read_timeout(Some(timeout))?;
+    let registered = self.register(&mut reader)?;
+    self.stream.set_read_timeout(None)?;
+    if !registered {
+      return Ok(());
+    }
+
+    let (sender, receiver) = mpsc::channel::<Event>();
+    let commands = self.try_clone()?;
+    let reader_thread = thread::spawn(move || commands.read_commands(reader, sender));
+    let result = self.relay(receiver);
+
+    // the reader is still on the stream if the relay stopped first
+    let _ = self.stream.shutdown(Shutdown::Both);
+    let _ = reader_thread.join();
+    result
+  }
+
+  // ----- Registration -----
+  // NICK and USER in any order, false if the client left before that
+  fn register(&mut self, reader: &mut BufReader<TcpStream>) -> Result<bool> {
+    let mut nick: Option<String> = None;
+    let mut user = false;
+    // the token of owners, moderators and admins
+    let mut password: Option<String> = None;
+    loop {
+      let Some(line) = Self::read_line(reader)? else { return Ok(false) };
+      Metrics::count(&self.metrics.bytes_in, line.len() as u64);
+      let Some(command) = Command::parse(&line) else { continue };
+      match command.name.as_str() {
+        "NICK" => match command.params.first() {
+          None => self.send(&self.numeric("431", &["No nickname given"]))?,
+          Some(v) if !is_valid_nick(v) => self.send(&self.numeric("432", &[v, "Erroneous nickname"]))?,
+          Some(v) => nick = Some(v.clone()),
+        },
+        "USER" if command.params.len() < 4 => self.send(&self.numeric("461", &["USER", "Not enough parameters"]))?,
+        "USER" => user = true,
+        "PING" => self.send(&self.pong(&command))?,
+        "QUIT" => return Ok(false),
+        "PASS" => password = command.params.first().cloned(),
+        // capability negotiation is not supported, clients go on without it
+        "CAP" => (),
+        _ => self.send(&self.numeric("451", &["You have not registered"]))?,
+      }
+
+      let Some(candidate) = nick.clone().filter(|_| user) else { continue };
+      match self.login(&candidate, password.as_deref()) {
+        Ok(true) => break,
+        Ok(false) => {
+          self.send(&self.numeric("433", &[&candidate, "Nickname is already in use"]))?;
+          nick = None;
+        },
+        Err(reason) => {
+          warn!(peer = self.peer_addr.as_str(), user = candidate.as_str(); "irc authorization denied");
+          self.send(&format!("ERROR :Closing link: {reason}"))?;
+          return Ok(false);
+        },
+      }
+    }
+
+    self.welcome()?;
+    Ok(true)
+  }
+
+  // the same checks as the handshake of the chat, false if the nick is taken
+  fn login(&mut self, nick: &str, password: Option<&str>) -> Result<bool, String> {
+    let mut state = self.state.get();
+    if state.users.contains_key(nick) {
+      return Ok(false);
+    }
+    if state.users.len() >= state.settings.max_users.into() {
+      return Err("server is full".to_owned());
+    }
+    if state.settings.is_banned(nick, &self.peer_ip.to_string()) || state.sanctions.is_banned(nick) {
+      return Err("you are banned".to_owned());
+    }
+
+    let (role, admin) = state.settings.authorize(nick, password).map_err(|_| "password incorrect".to_owned())?;
+    state.users.insert(nick.to_owned(), UserData { address: self.peer_addr.clone(), role, admin, capabilities: Vec::new() });
+    state.sanctions.take_kick(nick);
+    state.mailboxes.register(nick);
+    drop(state);
+
+    info!(peer = self.peer_addr.as_str(), user = nick; "irc user authorized");
+    self.nick = Some(nick.to_owned());
+    Ok(true)
+  }
+
+  fn welcome(&self) -> Result<()> {
+    let (name, motd) = {
+      let state = self.state.get();
+      (state.settings.name.clone(), state.settings.motd.clone())
+    };
+    let nick = self.nick.clone().unwrap_or_default();
+
+    self.send(&self.numeric("001", &[&format!("Welcome to {name}, {nick}")]))?;
+    self.send(&self.numeric("002", &[&format!("Your host is {}", self.server_id)]))?;
+    match motd {
+      Some(motd) => {
+        self.send(&self.numeric("375", &[&format!("- {} Message of the day -", self.server_id)]))?;
+        for line in motd.lines() {
+          self.send(&self.numeric("372", &[&format!("- {line}")]))?;
+        }
+        self.send(&self.numeric("376", &["End of /MOTD command"]))?;
+      },
+      None => self.send(&self.numeric("422", &["MOTD File is missing"]))?,
+    }
+    self.send(&format!(
+      ":{} NOTICE {nick} :Join {CHANNEL} to talk with everyone, PRIVMSG to a nick is a direct message",
+      self.server_id
+    ))
+  }
+
+  // ----- Commands -----
+  fn read_commands(mut self, mut reader: BufReader<TcpStream>, events: Sender<Event>) {
+    let mut guard = FloodGuard::new();
+    loop {
+      let line = match Self::read_line(&mut reader) {
+        Ok(Some(v)) => v,
+        Ok(None) => break,
+        Err(e) => {
+          Metrics::count(&self.metrics.dropped_frames, 1);
+          warn!(peer = self.peer_addr.as_str(), error = e.to_string().as_str(); "dropping irc connection");
+          let _ = events.send(Event::Reply(format!("ERROR :Closing link: {e}")));
+          break;
+        }
+      };
+      Metrics::count(&self.metrics.bytes_in, line.len() as u64);
+      let Some(command) = Command::parse(&line) else { continue };
+
+      // only what goes to others is limited, clients chat a lot on their own
+      if command.name == "PRIVMSG" {
+        let verdict = {
+          let mut state = self.state.get();
+          let limits = state.settings.rate_limits.clone();
+          guard.check(line.len(), &limits, state.ip_buckets(self.peer_ip))
+        };
+        if verdict != Verdict::Pass {
+          Metrics::count(&self.metrics.dropped_frames, 1);
+        }
+        let notice = match verdict {
+          Verdict::Pass => None,
+          Verdict::Muted => continue,
+          Verdict::Warn => Some("You are sending messages too fast, slow down".to_owned()),
+          Verdict::Mute(v) => Some(format!("You are muted for {} seconds for flooding", v.as_secs())),
+          Verdict::Disconnect => Some("You are disconnected for flooding".to_owned()),
+        };
+        if let Some(text) = notice {
+          let _ = events.send(Event::Reply(self.notice(&text)));
+          if verdict == Verdict::Disconnect {
+            break;
+          }
+          continue;
+        }
+      }
+
+      match self.handle(command, &events) {
+        Ok(true) => (),
+        Ok(false) => break,
+        Err(e) => {
+          warn!(peer = self.peer_addr.as_str(), error = e.to_string().as_str(); "irc command failed");
+          break;
+        }
+      }
+    }
+
+    let _ = events.send(Event::Closed);
+  }
+
+  // false after QUIT
+  fn handle(&mut self, command: Command, events: &Sender<Event>) -> Result<bool> {
+    let reply = |line: String| events.send(Event::Reply(line));
+    let param = |index: usize| command.params.get(index).map(String::as_str);
+
+    match command.name.as_str() {
+      "PING" => reply(self.pong(&command))?,
+      "PONG" | "CAP" => (),
+      "JOIN" => {
+        let Some(channels) = param(0) else {
+          reply(self.numeric("461", &["JOIN", "Not enough parameters"]))?;
+          return Ok(true);
+        };
+        // 'JOIN 0' leaves every channel
+        if channels == "0" {
+          self.part(events)?;
+        }
+        for channel in channels.split(',').filter(|v| *v != "0") {
+          if !channel.eq_ignore_ascii_case(CHANNEL) {
+            reply(self.numeric("403", &[channel, "No such channel"]))?;
+            continue;
+          }
+          if self.joined.load(Ordering::SeqCst) {
+            continue;
+          }
+          let last_read_sequence = self.announce(Presence::Joined);
+          self.joined.store(true, Ordering::SeqCst);
+          events.send(Event::Joined(last_read_sequence))?;
+          reply(format!(":{} JOIN {CHANNEL}", self.prefix(self.nick.as_deref().unwrap_or_default(), None)))?;
+          for line in self.names() {
+            reply(line)?;
+          }
+        }
+      },
+      "PART" => {
+        let Some(channels) = param(0) else {
+          reply(self.numeric("461", &["PART", "Not enough parameters"]))?;
+          return Ok(true);
+        };
+        for channel in channels.split(',') {
+          if !channel.eq_ignore_ascii_case(CHANNEL) {
+            reply(self.numeric("403", &[channel, "No such channel"]))?;
+          } else if !self.joined.load(Ordering::SeqCst) {
+            reply(self.numeric("442", &[CHANNEL, "You're not on that channel"]))?;
+          } else {
+            self.part(events)?;
+          }
+        }
+      },
+      "NAMES" => {
+        for line in self.names() {
+          reply(line)?;
+        }
+      },
+      "PRIVMSG" => match (param(0), param(1)) {
+        (None, _) => reply(self.numeric("411", &["No recipient given (PRIVMSG)"]))?,
+        (Some(_), None) => reply(self.numeric("412", &["No text to send"]))?,
+        (Some(_), Some(text)) if text.trim().is_empty() => reply(self.numeric("412", &["No text to send"]))?,
+        (Some(target), Some(text)) => {
+          if let Some(line) = self.privmsg(target, text) {
+            reply(line)?;
+          }
+        },
+      },
+      "NICK" => {
+        if param(0) != self.nick.as_deref() {
+          reply(self.numeric("400", &["NICK", "Nick changes are not supported, reconnect with the new nick"]))?;
+        }
+      },
+      "USER" | "PASS" => reply(self.numeric("462", &["You may not reregister"]))?,
+      "QUIT" => {
+        reply("ERROR :Closing link: quit".to_owned())?;
+        return Ok(false);
+      },
+      name => reply(self.numeric("421", &[name, "Unknown command"]))?,
+    }
+    Ok(true)
+  }
+
+  fn part(&self, events: &Sender<Event>) -> Result<()> {
+    if !self.joined.swap(false, Ordering::SeqCst) {
+      return Ok(());
+    }
+    self.announce(Presence::Left);
+    let prefix = self.prefix(self.nick.as_deref().unwrap_or_default(), None);
+    events.send(Event::Reply(format!(":{prefix} PART {CHANNEL}")))?;
+    Ok(())
+  }
+
+  // the reply for the sender, if there is one
+  fn privmsg(&self, target: &str, text: &str) -> Option<String> {
+    let nick = self.nick.clone().unwrap_or_default();
+    let mut state = self.state.get();
+    if let Some(left) = state.sanctions.muted_for(&nick) {
+      return Some(self.notice(&format!("You are muted by a moderator for {} more seconds", left.as_secs() + 1)));
+    }
+
+    if target.eq_ignore_ascii_case(CHANNEL) {
+      drop(state);
+      if !self.joined.load(Ordering::SeqCst) {
+        return Some(self.numeric("404", &[CHANNEL, "Cannot send to channel"]));
+      }
+      self.messages_pool.lock().push(PoolMessage {
+        id: Uuid::new_v4().to_string(),
+        username: nick,
+        message: text.trim().to_owned(),
+        ..PoolMessage::new()
+      });
+      self.metrics.message_received();
+      return None;
+    }
+    if target.starts_with('#') {
+      return Some(self.numeric("403", &[target, "No such channel"]));
+    }
+
+    // users who are away get it on their next login
+    let message = PoolMessage {
+      id: Uuid::new_v4().to_string(),
+      username: nick,
+      message: text.trim().to_owned(),
+      timestamp: now_millis(),
+      away: !state.users.contains_key(target),
+      recipient: Some(target.to_owned()),
+      ..PoolMessage::new()
+    };
+    let limit = state.settings.mailbox_size.into();
+    match state.mailboxes.post(message, limit) {
+      Ok(()) => {
+        self.metrics.message_received();
+        None
+      },
+      Err(e) => Some(self.numeric("401", &[target, &e.to_string()])),
+    }
+  }
+
+  // the sequence number of the last message before the notice
+  fn announce(&self, presence: Presence) -> u64 {
+    let nick = self.nick.clone().unwrap_or_default();
+    let message = match presence {
+      Presence::Joined => format!("{nick} joined the chat!"),
+      Presence::Left => format!("{nick} left the chat!"),
+    };
+    let mut messages_pool = self.messages_pool.lock();
+    let last = messages_pool.last_sequence();
+    messages_pool.push(PoolMessage {
+      id: Uuid::new_v4().to_string(),
+      username: nick,
+      message,
+      from_server: true,
+      presence: Some(presence),
+      ..PoolMessage::new()
+    });
+    last
+  }
+
+  fn names(&self) -> Vec<String> {
+    let mut names: Vec<String> = {
+      let state = self.state.get();
+      state.users.keys()
+        .cloned()
+        .chain(state.federation.remote_users())
+        .map(|v| irc_nick(&v))
+        .collect()
+    };
+    names.sort();
+
+    let mut lines: Vec<String> = names.chunks(NAMES_PER_LINE)
+      .map(|v| self.numeric("353", &["=", CHANNEL, &v.join(" ")]))
+      .collect();
+    lines.push(self.numeric("366", &[CHANNEL, "End of /NAMES list"]));
+    lines
+  }
+
+  // ----- Relaying to the client -----
+  fn relay(&mut self, events: Receiver<Event>) -> Result<()> {
+    let nick = self.nick.clone().unwrap_or_default();
+    let mut last_read_sequence = self.messages_pool.lock().last_sequence();
+    loop {
+      while let Ok(event) = events.try_recv() {
+        match event {
+          Event::Reply(line) => self.send(&line)?,
+          Event::Joined(v) => last_read_sequence = v,
+          Event::Closed => return Ok(()),
+        }
+      }
+
+      let messages = self.state.get().mailboxes.take(&nick);
+      for message in messages {
+        let prefix = self.prefix(&message.username, message.origin.as_deref());
+        for line in message.message.lines().filter(|v| !v.trim().is_empty()) {
+          self.send(&format!(":{prefix} PRIVMSG {nick} :{line}"))?;
+        }
+      }
+
+      let kick = self.state.get().sanctions.take_kick(&nick);
+      if let Some(reason) = kick {
+        self.send(&format!(":{} KICK {CHANNEL} {nick} :{reason}", self.server_id))?;
+        self.send(&format!("ERROR :Closing link: {reason}"))?;
+        return Ok(());
+      }
+
+      if self.joined.load(Ordering::SeqCst) {
+        let (since, last) = {
+          let messages_pool = self.messages_pool.lock();
+          (messages_pool.read_since(last_read_sequence), messages_pool.last_sequence())
+        };
+        last_read_sequence = last;
+        let messages = match since {
+          Since::Messages(v) => v,
+          Since::Gap { missed, messages } => {
+            self.send(&format!(":{} NOTICE {CHANNEL} :{missed} messages were missed", self.server_id))?;
+            messages
+          },
+        };
+        for line in messages.iter().flat_map(|v| self.relay_lines(v)) {
+          self.send(&line)?;
+        }
+      }
+      thread::sleep(Duration::from_millis(10));
+    }
+  }
+
+  // IRC clients show their own messages and JOIN themselves
+  fn relay_lines(&self, message: &PoolMessage) -> Vec<String> {
+    let own = message.origin.is_none() && Some(&message.username) == self.nick.as_ref();
+    let prefix = self.prefix(&message.username, message.origin.as_deref());
+    let lines = || message.message.lines().filter(|v| !v.trim().is_empty());
+
+    match message.presence {
+      Some(_) if own => Vec::new(),
+      Some(Presence::Joined) => vec![format!(":{prefix} JOIN {CHANNEL}")],
+      Some(Presence::Left) => vec![format!(":{prefix} PART {CHANNEL}")],
+      None if message.from_server => lines().map(|v| format!(":{} NOTICE {CHANNEL} :{v}", self.server_id)).collect(),
+      None if own => Vec::new(),
+      None => lines().map(|v| format!(":{prefix} PRIVMSG {CHANNEL} :{v}")).collect(),
+    }
+  }
+
+  // ----- Lines -----
+  // None when the client is gone
+  fn read_line(reader: &mut BufReader<TcpStream>) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    match reader.by_ref().take(MAX_LINE_BYTES as u64 + 1).read_until(b'\n', &mut line) {
+      Ok(0) => return Ok(None),
+      Ok(_) => (),
+      Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => bail!("registration timed out"),
+      Err(e) if matches!(
+        e.kind(),
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe | ErrorKind::UnexpectedEof
+      ) => return Ok(None),
+      Err(e) => return Err(e.into()),
+    }
+    if line.len() > MAX_LINE_BYTES {
+      bail!("line is longer than {MAX_LINE_BYTES} bytes");
+    }
+    Ok(Some(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_owned()))
+  }
+
+  // every line goes out through here, so nothing in a chat message
+  // can end it early and smuggle in a command of its own
+  fn send(&self, line: &str) -> Result<()> {
+    let line = strip_line_breaks(line);
+    (&self.stream).write_all(format!("{line}\r\n").as_bytes())?;
+    Metrics::count(&self.metrics.bytes_out, line.len() as u64 + 2);
+    Ok(())
+  }
+
+  // ':server 001 nick param... :last param'
+  fn numeric(&self, code: &str, params: &[&str]) -> String {
+    let target = self.nick.as_deref().unwrap_or("*");
+    let mut line = format!(":{} {code} {target}", self.server_id);
+    if let Some((last, middle)) = params.split_last() {
+      for v in middle {
+        line.push(' ');
+        line.push_str(v);
+      }
+      line.push_str(" :");
+      line.push_str(last);
+    }
+    line
+  }
+
+  fn notice(&self, text: &str) -> String {
+    format!(":{} NOTICE {} :{text}", self.server_id, self.nick.as_deref().unwrap_or("*"))
+  }
+
+  fn pong(&self, command: &Command) -> String {
+    let token = command.params.last().map(String::as_str).unwrap_or(&self.server_id);
+    format!(":{} PONG {} :{token}", self.server_id, self.server_id)
+  }
+
+  // users of this server come from it, those of linked servers from theirs
+  fn prefix(&self, username: &str, origin: Option<&str>) -> String {
+    let nick = irc_nick(username);
+    format!("{nick}!{nick}@{}", origin.unwrap_or(&self.server_id))
+  }
+
+  fn leave(&mut self) {
+    let Some(nick) = self.nick.clone() else { return };
+    self.state.get().users.remove(&nick);
+    if self.joined.swap(false, Ordering::SeqCst) {
+      self.announce(Presence::Left);
+    }
+    info!(peer = self.peer_addr.as_str(), user = nick.as_str(); "irc connection closed");
+  }
+}
@@ -0,0 +1,24 @@
+// Chat server library, the binary in 'main.rs' is a thin wrapper around it
+mod settings;
+mod config;
+mod state;
+mod service;
+mod chatServer;
+mod manageConnection;
+mod messagesPool;
+mod reader;
+mod compression;
+mod rateLimiter;
+mod metrics;
+mod mailbox;
+mod moderation;
+mod federation;
+mod irc;
+mod search;
+mod reactions;
+mod transfers;
+mod types;
+
+pub use chatServer::{ChatServer, ChatServerBuilder, ServerHandle};
+pub use reader::FrameLimits;
+pub use settings::{Args, RateLimits, Settings};
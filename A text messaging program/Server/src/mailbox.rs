@@ -0,0 +1,51 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use anyhow::{anyhow, Result};
+
+use crate::messagesPool::PoolMessage;
+
+// ----- Direct message mailboxes -----
+// every direct message waits here till the recipient's connection
+// picks it up, which is at once for users online and on the next
+// login for those away; only users who have logged in since the
+// start of the server can get messages
+pub struct Mailboxes {
+  boxes: HashMap<String, VecDeque<PoolMessage>>,
+  registered: HashSet<String>,
+}
+
+impl Mailboxes {
+  pub fn new() -> Mailboxes {
+    Mailboxes {
+      boxes: HashMap::new(),
+      registered: HashSet::new(),
+    }
+  }
+
+  pub fn register(&mut self, username: &str) {
+    self.registered.insert(username.to_owned());
+  }
+
+  pub fn is_registered(&self, username: &str) -> bool {
+    self.registered.contains(username)
+  }
+
+  // 'limit' is how many messages may wait for a user who is away,
+  // messages for users online are taken within moments
+  pub fn post(&mut self, message: PoolMessage, limit: usize) -> Result<()> {
+    let recipient = message.recipient.clone().ok_or_else(|| anyhow!("direct message without a recipient"))?;
+    if !self.is_registered(&recipient) {
+      return Err(anyhow!("{recipient} is not a known user"));
+    }
+
+    let mailbox = self.boxes.entry(recipient.clone()).or_default();
+    if message.away && mailbox.iter().filter(|v| v.away).count() >= limit {
+      return Err(anyhow!("mailbox of {recipient} is full"));
+    }
+    mailbox.push_back(message);
+    Ok(())
+  }
+
+  pub fn take(&mut self, username: &str) -> Vec<PoolMessage> {
+    self.boxes.remove(username).map(Vec::from).unwrap_or_default()
+  }
+}
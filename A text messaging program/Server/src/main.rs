@@ -1,22 +1,17 @@
 use anyhow::Result;
+use log::LevelFilter;
 
-use service::Service;
-use settings::Settings;
-use state::State;
-
-mod settings;
-mod state;
-mod service;
-mod manageConnection;
-mod messagesPool;
-mod reader;
-mod types;
+use server::{ChatServer, Settings};
 
 fn main() -> Result<()> {
-  let settings = Settings::new();
-  let state = State::new(settings);
+  let settings = Settings::new()?;
+
+  // the level itself is switched through 'log::set_max_level',
+  // so it can be changed on config reload
+  env_logger::Builder::new().filter_level(LevelFilter::Trace).init();
+  log::set_max_level(settings.log_level);
 
-  Service::run(state)?;
+  ChatServer::run(settings)?;
   
   Ok(())
 }
@@ -1,37 +1,77 @@
+use std::net::{IpAddr, Shutdown, SocketAddr};
 use std::sync::Arc;
 use std::sync::mpsc::Receiver;
 use std::thread;
 use std::time::Duration;
 use std::str::FromStr;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use log::{info, warn};
 use parking_lot::Mutex;
 use uuid::Uuid;
 
-use crate::messagesPool::{PoolMessage, MessagesPool};
-use crate::state::UserData;
+use crate::messagesPool::{now_millis, MessagesPool, PoolMessage, Since};
+use crate::moderation::{Role, MAX_MUTE};
+use crate::reactions;
+use crate::search::SearchQuery;
+use crate::state::{State, UserData};
+use crate::transfers::{is_valid_file_name, is_valid_sha256};
 use crate::types::{
   Authoritation, 
+  Capability,
   SignalsData, 
   SignalsHeader, 
   SignalError,
-  Signal
+  Signal,
+  Presence,
+  PROTOCOL_VERSION
 };
 
 use super::manager::Manager;
 use super::streamManager::StreamManager;
 
+// what this server can do, announced in the ACCEPTED response
+const SERVER_CAPABILITIES: [Capability; 12] = [
+  Capability::MessageIds,
+  Capability::Motd,
+  Capability::Warnings,
+  Capability::ServerIdentity,
+  Capability::Stats,
+  Capability::Search,
+  Capability::DirectMessages,
+  Capability::Moderation,
+  Capability::Deflate,
+  Capability::Reactions,
+  Capability::Threads,
+  Capability::FileTransfer
+];
+
 pub trait DataManager {
   fn deny_auth(&mut self) -> Result<()>;
   fn auth(&mut self, signal: String) -> Result<()>;
   fn remove_user(&mut self, username: String) -> Result<()>;
-  fn process_messages_pool(&mut self, receiver: Receiver<()>) -> Result<()>;
-  fn process_incoming_message(messages_pool: Arc<Mutex<MessagesPool>>, signal: String) -> Result<()>;
+  fn process_messages_pool(&mut self, receiver: Receiver<()>, direct_receiver: Receiver<String>) -> Result<()>;
+  fn process_incoming_message(messages_pool: Arc<Mutex<MessagesPool>>, username: &str, signal: String) -> Result<()>;
+  fn process_direct_message(state: &State, username: &str, signal: String) -> Result<String>;
+  fn process_reaction(messages_pool: &Mutex<MessagesPool>, username: &str, signal: &str) -> Result<()>;
+  fn reaction_signal(&self, message: &PoolMessage) -> String;
+  fn deliver_direct_messages(&mut self, on_login: bool) -> Result<()>;
+  fn broker_file(state: &State, username: &str, peer_ip: IpAddr, signal: &str) -> Result<()>;
+  fn direct_signal(message: &PoolMessage) -> String;
+  fn moderate(state: &State, messages_pool: &Mutex<MessagesPool>, username: &str, signal: &str) -> Result<()>;
+  fn warning_signal(text: &str, supports_warnings: bool) -> String;
+  fn stats_signal(state: &State, username: &str, supports_warnings: bool) -> String;
+  fn search_signals(messages_pool: &Mutex<MessagesPool>, signal: &str, supports_warnings: bool) -> Vec<String>;
+  fn history_signals(messages_pool: &Mutex<MessagesPool>, signal: &str, supports_warnings: bool) -> Vec<String>;
 }
 
 impl DataManager for Manager {
   fn deny_auth(&mut self) -> Result<()> {
     let response = SignalsData::new(
-      vec![SignalsHeader::auth(Authoritation::Denied)],
+      vec![
+        SignalsHeader::signalType(Signal::Connection),
+        SignalsHeader::auth(Authoritation::Denied),
+        SignalsHeader::protocolVersion(PROTOCOL_VERSION)
+      ],
       None
     );
 
@@ -47,31 +87,95 @@ impl DataManager for Manager {
           if let None = data.username {
             return Err(SignalError.into());
           }
+          // 'user@server' are users of linked servers
+          if data.username.as_ref().is_some_and(|v| v.contains('@')) {
+            return Err(SignalError.into());
+          }
           let mut state = self.state.get();
           if state.users.contains_key(&data.username.clone().unwrap()) {
             return Err(SignalError.into())
           }
+          if state.users.len() >= state.settings.max_users.into() {
+            return Err(SignalError.into())
+          }
+          let peer_ip = self.stream.peer_addr()?.ip().to_string();
+          if state.settings.is_banned(&data.username.clone().unwrap(), &peer_ip)
+            || state.sanctions.is_banned(&data.username.clone().unwrap()) {
+            return Err(SignalError.into())
+          }
+          // owners, moderators and admins prove it with the SECRET header
+          let (role, admin) = state.settings.authorize(&data.username.clone().unwrap(), data.secret.as_deref())?;
           state.users.insert(data.username.clone().unwrap().to_owned(), UserData {
             address: self.stream.peer_addr()?.to_string(),
+            role,
+            admin,
+            capabilities: data.capabilities.clone().unwrap_or_default(),
           });
-          self.messages_pool.lock().push(PoolMessage {
+          // a kick of the last session that wasn't picked up doesn't count
+          state.sanctions.take_kick(&data.username.clone().unwrap());
+          state.mailboxes.register(&data.username.clone().unwrap());
+          let mut pool = self.messages_pool.lock();
+          // reactions before this are in the history replay
+          self.last_reaction_update = pool.last_reaction_update();
+          pool.push(PoolMessage {
             id: Uuid::new_v4().to_string(),
-            username: String::new(),
+            username: data.username.clone().unwrap(),
             message: format!("{} joined the chat!", data.username.clone().unwrap()),
-            from_server: true
+            from_server: true,
+            presence: Some(Presence::Joined),
+            ..PoolMessage::new()
           });
+          // the whole history is replayed, the join notice included
+          self.last_read_sequence = pool.history_start();
         }
         _ => return Err(SignalError.into()),
     }
 
-    self.connected_user_username = Some(data.username.unwrap());
-
-    let response = SignalsData::new(
-      vec![SignalsHeader::auth(Authoritation::Accepted)],
-      None
+    self.connected_user_username = Some(data.username.clone().unwrap());
+    self.peer_version = data.peer_version();
+    self.peer_capabilities = data.capabilities.clone().unwrap_or_default();
+    info!(
+      peer = self.connected_peer_addr.as_str(), 
+      user = data.username.clone().unwrap().as_str(), 
+      protocol = self.peer_version; 
+      "user authorized"
     );
 
+    let (server_name, motd) = {
+      let state = self.state.get();
+      (state.settings.name.clone(), state.settings.motd.clone())
+    };
+
+    let mut headers = vec![
+      SignalsHeader::signalType(Signal::Connection),
+      SignalsHeader::auth(Authoritation::Accepted),
+      SignalsHeader::protocolVersion(PROTOCOL_VERSION),
+      SignalsHeader::capabilities(SERVER_CAPABILITIES.to_vec()),
+      SignalsHeader::serverName(server_name)
+    ];
+    // new clients get MOTD within the response, old ones as a server message
+    let motd_in_response = motd.is_some() && data.supports(Capability::Motd);
+    if motd_in_response {
+      headers.push(SignalsHeader::withMess);
+    }
+    let response = SignalsData::new(headers, motd.as_deref());
+
     self.send_data(&response.to_string())?;
+
+    if let Some(motd) = motd.filter(|_| !motd_in_response) {
+      let greeting = SignalsData::new(
+        vec![
+          SignalsHeader::signalType(Signal::Message),
+          SignalsHeader::id(Uuid::new_v4().to_string()),
+          SignalsHeader::withMess,
+          SignalsHeader::serverMess
+        ],
+        Some(&motd)
+      );
+      self.send_data(&greeting.to_string())?;
+    }
+
+    self.deliver_direct_messages(true)?;
     Ok(())
   }
 
@@ -80,41 +184,99 @@ impl DataManager for Manager {
 
     if state.users.contains_key(&username) {
       state.users.remove(&username);
+      state.transfers.forget(&username);
       self.messages_pool.lock().push(PoolMessage {
         id: Uuid::new_v4().to_string(),
-        username: String::new(),
         message: format!("{username} left the chat!"),
-        from_server: true
+        username,
+        from_server: true,
+        presence: Some(Presence::Left),
+        ..PoolMessage::new()
       });
     }
     Ok(())
   }
 
-  fn process_messages_pool(&mut self, receiver: Receiver<()>) -> Result<()> {
+  fn process_messages_pool(&mut self, receiver: Receiver<()>, direct_receiver: Receiver<String>) -> Result<()> {
     loop {
+      // frames for this user only, sent before the stop is handled,
+      // so a reason of disconnection still reaches the user
+      while let Ok(frame) = direct_receiver.try_recv() {
+        self.send_data(&frame)?;
+      }
       if let Ok(()) = receiver.try_recv() {
         break;
       };
 
-      let lock_ref = self.messages_pool.clone();
-      let pool_lock = lock_ref.lock();
+      self.deliver_direct_messages(false)?;
+
+      let file_signals = self.connected_user_username.as_ref().map(|v| self.state.get().transfers.take(v)).unwrap_or_default();
+      for frame in file_signals {
+        self.send_data(&frame)?;
+      }
+
+      let kick = self.connected_user_username.as_ref().and_then(|v| self.state.get().sanctions.take_kick(v));
+      if let Some(reason) = kick {
+        let supports_warnings = self.peer_capabilities.contains(&Capability::Warnings);
+        self.send_data(&Self::warning_signal(&reason, supports_warnings))?;
+        // the reader thread is still on the stream, closing it ends that too
+        let _ = self.stream.shutdown(Shutdown::Both);
+        break;
+      }
+
+      // the pool is let go before sending, a slow connection doesn't hold up the others
+      let supports_reactions = self.peer_capabilities.contains(&Capability::Reactions);
+      let (since, updated) = {
+        let pool = self.messages_pool.lock();
+        let since = pool.read_since(self.last_read_sequence);
+        self.last_read_sequence = pool.last_sequence();
+        let (updated, last) = pool.reactions_since(self.last_reaction_update);
+        self.last_reaction_update = last;
+        (since, updated)
+      };
 
-      let messages = pool_lock.has_new(&self.last_read_message_id);
-      if let Some(v) = messages {
-        if let Some(last) = v.1 {
-          self.last_read_message_id = last;
+      let messages = match since {
+        Since::Messages(v) => v,
+        // messages pushed out before this connection got them are gone,
+        // the user is told instead of getting the history again
+        Since::Gap { missed, messages } => {
+          warn!(peer = self.connected_peer_addr.as_str(), missed = missed; "connection fell behind the history");
+          let supports_warnings = self.peer_capabilities.contains(&Capability::Warnings);
+          self.send_data(&Self::warning_signal(&format!("{missed} messages were missed"), supports_warnings))?;
+          messages
+        },
+      };
+      for message in messages {
+        let mut syg_vec = vec![
+          SignalsHeader::signalType(Signal::Message),
+          SignalsHeader::id(message.id.clone()),
+          SignalsHeader::username(message.username.clone()),
+          SignalsHeader::withMess
+        ];
+        if message.from_server {
+          syg_vec.push(SignalsHeader::serverMess);
         }
-        for message in v.0 {
-          let mut syg_vec = vec![
-            SignalsHeader::signalType(Signal::Message),
-            SignalsHeader::username(message.username.clone()),
-            SignalsHeader::withMess
-          ];
-          if message.from_server {
-            syg_vec.push(SignalsHeader::serverMess);
-          }
-          let response = SignalsData::new(syg_vec, Some(&message.message));
-          self.send_data(&response.to_string())?;
+        if let Some(v) = message.presence {
+          syg_vec.push(SignalsHeader::presence(v));
+        }
+        if let Some(v) = &message.reply_to {
+          syg_vec.push(SignalsHeader::replyTo(v.clone()));
+        }
+        syg_vec.push(SignalsHeader::timestamp(message.timestamp));
+        syg_vec.push(SignalsHeader::sequence(message.sequence));
+        if supports_reactions && !message.reactions.is_empty() {
+          let (counts, own) = reactions::summary(&message.reactions, self.connected_user_username.as_deref().unwrap_or_default());
+          syg_vec.push(SignalsHeader::reactions(counts));
+          syg_vec.push(SignalsHeader::reacted(own));
+        }
+        let response = SignalsData::new(syg_vec, Some(&message.message));
+        self.send_data(&response.to_string())?;
+      }
+
+      // changes of reactions to messages the user has already got
+      if supports_reactions {
+        for message in updated {
+          self.send_data(&self.reaction_signal(&message))?;
         }
       }
       thread::sleep(Duration::from_millis(10));
@@ -123,20 +285,345 @@ impl DataManager for Manager {
     Ok(())
   }
 
-  fn process_incoming_message(messages_pool: Arc<Mutex<MessagesPool>>, signal: String) -> Result<()> {
+  // messages are always sent as the user authorized on this connection,
+  // USERNAME header is optional and has to match if present;
+  // REPLY_TO has to be a chat message still in the history
+  fn process_incoming_message(messages_pool: Arc<Mutex<MessagesPool>>, username: &str, signal: String) -> Result<()> {
     let data = SignalsData::from_str(&signal)?;
   
-    if !data.withMess || data.username.is_none() {
+    if !data.withMess {
       return Err(SignalError.into())
     }
+    if data.username.as_ref().is_some_and(|v| v != username) {
+      return Err(anyhow!("messages can be sent only as {username}"))
+    }
   
-    messages_pool.lock().push(PoolMessage {
+    let mut messages_pool = messages_pool.lock();
+    if data.replyTo.as_ref().is_some_and(|v| !messages_pool.can_reply_to(v)) {
+      return Err(anyhow!("the message replied to is not in the history"))
+    }
+    messages_pool.push(PoolMessage {
       id: Uuid::new_v4().to_string(),
-      username: data.username.clone().unwrap(),
+      username: username.to_owned(),
       message: data.message.clone().unwrap().trim().to_owned(),
-      from_server: false
+      from_server: false,
+      presence: None,
+      reply_to: data.replyTo.clone(),
+      ..PoolMessage::new()
     });
   
     Ok(())
   }
-}
\ No newline at end of file
+
+  // the recipient has to have logged in at least once, an echo of
+  // the message is returned for the sender's own view
+  fn process_direct_message(state: &State, username: &str, signal: String) -> Result<String> {
+    let data = SignalsData::from_str(&signal)?;
+    let recipient = data.recipient.clone().ok_or(SignalError)?;
+    let text = data.message.as_deref().map(str::trim).unwrap_or_default();
+    if text.is_empty() {
+      return Err(SignalError.into());
+    }
+
+    let mut state = state.get();
+    let message = PoolMessage {
+      id: Uuid::new_v4().to_string(),
+      username: username.to_owned(),
+      message: text.to_owned(),
+      timestamp: now_millis(),
+      away: !state.users.contains_key(&recipient),
+      recipient: Some(recipient.clone()),
+      ..PoolMessage::new()
+    };
+    let limit = state.settings.mailbox_size.into();
+    let echo = Self::direct_signal(&PoolMessage { away: false, ..message.clone() });
+    if message.away {
+      info!(user = username, recipient = recipient.as_str(); "direct message queued for an offline user");
+    }
+    state.mailboxes.post(message, limit)?;
+    Ok(echo)
+  }
+
+  // REACT with REMOVE takes back a reaction of the same user
+  fn process_reaction(messages_pool: &Mutex<MessagesPool>, username: &str, signal: &str) -> Result<()> {
+    let data = SignalsData::from_str(signal)?;
+    let id = data.id.filter(|v| !v.is_empty()).ok_or_else(|| anyhow!("no message given"))?;
+    let reaction = data.reaction.unwrap_or_default();
+    messages_pool.lock().react(&id, username, &reaction, data.remove)
+  }
+
+  // every reaction to the message, an empty REACTIONS is when none are left
+  fn reaction_signal(&self, message: &PoolMessage) -> String {
+    let (counts, own) = reactions::summary(&message.reactions, self.connected_user_username.as_deref().unwrap_or_default());
+    SignalsData::new(
+      vec![
+        SignalsHeader::signalType(Signal::React),
+        SignalsHeader::id(message.id.clone()),
+        SignalsHeader::reactions(counts),
+        SignalsHeader::reacted(own)
+      ],
+      None
+    ).to_string()
+  }
+
+  // messages which came while the user was away are flagged so on login
+  fn deliver_direct_messages(&mut self, on_login: bool) -> Result<()> {
+    let Some(username) = self.connected_user_username.clone() else { return Ok(()) };
+    let messages = self.state.get().mailboxes.take(&username);
+    for message in messages {
+      let away = message.away || on_login;
+      self.send_data(&Self::direct_signal(&PoolMessage { away, ..message }))?;
+    }
+    Ok(())
+  }
+
+  // FILE_OFFER goes to the recipient with the address of the sender,
+  // FILE_ACCEPT back to the sender; no part of the file passes the server;
+  // IPv4 peers of a dual-stack listener get their plain IPv4 address
+  fn broker_file(state: &State, username: &str, peer_ip: IpAddr, signal: &str) -> Result<()> {
+    let data = SignalsData::from_str(signal)?;
+    let transfer = data.transfer.clone().filter(|v| !v.is_empty()).ok_or_else(|| anyhow!("no transfer given"))?;
+    let mut state = state.get();
+
+    match data.signalType {
+      Some(Signal::FileOffer) => {
+        let to = data.recipient.clone().ok_or_else(|| anyhow!("no recipient given"))?;
+        let name = data.fileName.clone().filter(|v| is_valid_file_name(v)).ok_or_else(|| anyhow!("invalid file name"))?;
+        let sha256 = data.sha256.clone().filter(|v| is_valid_sha256(v)).ok_or_else(|| anyhow!("invalid SHA256"))?;
+        let (Some(size), Some(port)) = (data.fileSize, data.port.filter(|v| *v != 0)) else {
+          bail!("an offer needs FILE_SIZE and PORT");
+        };
+        if to == username {
+          bail!("you can't send files to yourself");
+        }
+        match state.users.get(&to) {
+          None => bail!("{to} is not online"),
+          Some(v) if !v.capabilities.contains(&Capability::FileTransfer) => bail!("{to} can't take files"),
+          Some(_) => {},
+        }
+
+        let frame = SignalsData::new(
+          vec![
+            SignalsHeader::signalType(Signal::FileOffer),
+            SignalsHeader::username(username.to_owned()),
+            SignalsHeader::recipient(to.clone()),
+            SignalsHeader::transfer(transfer.clone()),
+            SignalsHeader::fileName(name),
+            SignalsHeader::fileSize(size),
+            SignalsHeader::sha256(sha256.to_lowercase()),
+            SignalsHeader::address(SocketAddr::new(peer_ip.to_canonical(), port).to_string())
+          ],
+          None
+        ).to_string();
+        info!(user = username, recipient = to.as_str(), transfer = transfer.as_str(), size = size; "file offered");
+        state.transfers.offer(&transfer, username, &to, frame)
+      },
+      Some(Signal::FileAccept) => {
+        let sender = state.transfers.answer(&transfer, username)?;
+        let mut headers = vec![
+          SignalsHeader::signalType(Signal::FileAccept),
+          SignalsHeader::username(username.to_owned()),
+          SignalsHeader::transfer(transfer)
+        ];
+        if data.rejected {
+          headers.push(SignalsHeader::rejected);
+        }
+        state.transfers.post(&sender, SignalsData::new(headers, None).to_string());
+        Ok(())
+      },
+      _ => Err(SignalError.into()),
+    }
+  }
+
+  fn direct_signal(message: &PoolMessage) -> String {
+    let mut headers = vec![
+      SignalsHeader::signalType(Signal::Message),
+      SignalsHeader::id(message.id.clone()),
+      SignalsHeader::username(message.username.clone()),
+      SignalsHeader::timestamp(message.timestamp),
+      SignalsHeader::withMess
+    ];
+    if let Some(v) = &message.recipient {
+      headers.push(SignalsHeader::recipient(v.clone()));
+    }
+    if message.away {
+      headers.push(SignalsHeader::away);
+    }
+    SignalsData::new(headers, Some(&message.message)).to_string()
+  }
+
+  // KICK, MUTE, BAN and UNBAN; every action is announced to the room,
+  // errors go back to the moderator only
+  fn moderate(state: &State, messages_pool: &Mutex<MessagesPool>, username: &str, signal: &str) -> Result<()> {
+    let data = SignalsData::from_str(signal)?;
+    let action = data.signalType.ok_or(SignalError)?;
+    let target = data.target.clone().filter(|v| !v.is_empty()).ok_or_else(|| anyhow!("no user given"))?;
+    let reason = data.message.as_deref().map(str::trim).filter(|v| !v.is_empty());
+    let reason_suffix = reason.map(|v| format!(": {v}")).unwrap_or_default();
+
+    let mut state = state.get();
+    let actor_role = state.users.get(username).map(|v| v.role).unwrap_or_else(|| state.settings.role_of(username));
+    let target_role = state.users.get(&target).map(|v| v.role).unwrap_or_else(|| state.settings.role_of(&target));
+    if target == username {
+      bail!("you can't moderate yourself");
+    }
+    if actor_role == Role::User {
+      bail!("only moderators and owners can do that");
+    }
+    if !actor_role.can_moderate(target_role) {
+      bail!("a {actor_role} can't moderate {target}, who is a {target_role}");
+    }
+
+    let announcement = match action {
+      Signal::Kick => {
+        if !state.users.contains_key(&target) {
+          bail!("{target} is not online");
+        }
+        state.sanctions.kick(&target, format!("You were kicked by {username}{reason_suffix}"));
+        format!("{target} was kicked by {username}{reason_suffix}")
+      },
+      Signal::Mute => {
+        let duration = data.duration
+          .filter(|v| *v > 0)
+          .map(Duration::from_secs)
+          .ok_or_else(|| anyhow!("mute needs a DURATION in seconds"))?;
+        if duration > MAX_MUTE {
+          bail!("mute can't be longer than {} days", MAX_MUTE.as_secs() / 86400);
+        }
+        state.sanctions.mute(&target, duration);
+        format!("{target} was muted for {} seconds by {username}{reason_suffix}", duration.as_secs())
+      },
+      Signal::Ban => {
+        state.sanctions.ban(&target);
+        if state.users.contains_key(&target) {
+          state.sanctions.kick(&target, format!("You were banned by {username}{reason_suffix}"));
+        }
+        format!("{target} was banned by {username}{reason_suffix}")
+      },
+      Signal::Unban => {
+        if state.settings.banned.contains(&target) {
+          bail!("{target} is banned in the server config");
+        }
+        if !state.sanctions.unban(&target) {
+          bail!("{target} is not banned");
+        }
+        format!("{target} was unbanned by {username}{reason_suffix}")
+      },
+      _ => return Err(SignalError.into()),
+    };
+    drop(state);
+
+    info!(user = username, target = target.as_str(), action = action.to_string().as_str(); "moderation");
+    messages_pool.lock().push(PoolMessage {
+      id: Uuid::new_v4().to_string(),
+      message: announcement,
+      from_server: true,
+      ..PoolMessage::new()
+    });
+    Ok(())
+  }
+
+  // old clients don't know WARNING, so they get a server message instead
+  fn warning_signal(text: &str, supports_warnings: bool) -> String {
+    let signal_type = if supports_warnings { Signal::Warning } else { Signal::Message };
+    SignalsData::new(
+      vec![
+        SignalsHeader::signalType(signal_type),
+        SignalsHeader::id(Uuid::new_v4().to_string()),
+        SignalsHeader::withMess,
+        SignalsHeader::serverMess
+      ],
+      Some(text)
+    ).to_string()
+  }
+
+  // counters are shown only to admins from the config
+  fn stats_signal(state: &State, username: &str, supports_warnings: bool) -> String {
+    let state = state.get();
+    if !state.users.get(username).is_some_and(|v| v.admin) {
+      return Self::warning_signal("Stats are available only to admins", supports_warnings);
+    }
+
+    SignalsData::new(
+      vec![
+        SignalsHeader::signalType(Signal::Stats),
+        SignalsHeader::id(Uuid::new_v4().to_string()),
+        SignalsHeader::withMess,
+        SignalsHeader::serverMess
+      ],
+      Some(&state.metrics.render(state.users.len()))
+    ).to_string()
+  }
+
+  // a SEARCH frame per match, then one with RESULTS and no message
+  fn search_signals(messages_pool: &Mutex<MessagesPool>, signal: &str, supports_warnings: bool) -> Vec<String> {
+    let query = SignalsData::from_str(signal)
+      .map_err(anyhow::Error::from)
+      .and_then(|v| SearchQuery::from_signal(&v));
+    let query = match query {
+      Ok(v) => v,
+      Err(e) => return vec![Self::warning_signal(&format!("Search failed: {e}"), supports_warnings)],
+    };
+    let found = messages_pool.lock().search(&query);
+
+    let mut signals: Vec<String> = found.iter()
+      .map(|message| SignalsData::new(
+        vec![
+          SignalsHeader::signalType(Signal::Search),
+          SignalsHeader::id(message.id.clone()),
+          SignalsHeader::username(message.username.clone()),
+          SignalsHeader::timestamp(message.timestamp),
+          SignalsHeader::sequence(message.sequence),
+          SignalsHeader::withMess
+        ],
+        Some(&message.message)
+      ).to_string())
+      .collect();
+    signals.push(
+      SignalsData::new(
+        vec![
+          SignalsHeader::signalType(Signal::Search),
+          SignalsHeader::results(found.len() as u32)
+        ],
+        None
+      ).to_string()
+    );
+    signals
+  }
+
+  // like SEARCH, a HISTORY frame per message and one with RESULTS,
+  // which names the thread if one was asked for
+  fn history_signals(messages_pool: &Mutex<MessagesPool>, signal: &str, supports_warnings: bool) -> Vec<String> {
+    let thread = SignalsData::from_str(signal).ok().and_then(|v| v.thread);
+    let found = match &thread {
+      Some(id) => messages_pool.lock().thread(id),
+      None => Some(messages_pool.lock().messages()),
+    };
+    let Some(found) = found else {
+      return vec![Self::warning_signal("History failed: the message is not in the history", supports_warnings)];
+    };
+
+    let mut signals: Vec<String> = found.iter()
+      .map(|message| {
+        let mut headers = vec![
+          SignalsHeader::signalType(Signal::History),
+          SignalsHeader::id(message.id.clone()),
+          SignalsHeader::username(message.username.clone()),
+          SignalsHeader::timestamp(message.timestamp),
+          SignalsHeader::sequence(message.sequence),
+          SignalsHeader::withMess
+        ];
+        if let Some(v) = &message.reply_to {
+          headers.push(SignalsHeader::replyTo(v.clone()));
+        }
+        SignalsData::new(headers, Some(&message.message)).to_string()
+      })
+      .collect();
+    let mut headers = vec![SignalsHeader::signalType(Signal::History), SignalsHeader::results(found.len() as u32)];
+    if let Some(v) = thread {
+      headers.push(SignalsHeader::thread(v));
+    }
+    signals.push(SignalsData::new(headers, None).to_string());
+    signals
+  }
+}
@@ -6,7 +6,12 @@ use std::{
   use parking_lot::Mutex;
   use anyhow::Result;
   
-  use crate::{state::State, messagesPool::MessagesPool};
+  use crate::{
+    state::State,
+    messagesPool::MessagesPool,
+    metrics::Metrics,
+    types::{Capability, LEGACY_PROTOCOL_VERSION}
+  };
   use super::streamManager::StreamManager;
   
   pub struct Manager {
@@ -14,24 +19,40 @@ use std::{
     pub reader: BufReader<TcpStream>,
     pub state: State,
     pub messages_pool: Arc<Mutex<MessagesPool>>,
-    pub last_read_message_id: String,
+    pub last_read_sequence: u64,
+    pub last_reaction_update: u64,
     pub connected_user_username: Option<String>,
-    pub connected_peer_addr: String
+    pub connected_peer_addr: String,
+    // negotiated in the handshake
+    pub peer_version: u16,
+    pub peer_capabilities: Vec<Capability>,
+    pub metrics: Arc<Metrics>
   }
   
   impl Manager {
     pub fn new(stream: TcpStream, state: State, messages_pool: Arc<Mutex<MessagesPool>>) -> Result<()> {
+      let metrics = state.get().metrics.clone();
+      Metrics::count(&metrics.connections_total, 1);
+
       let mut manager = Manager {
         stream: stream.try_clone()?,
         reader: BufReader::new(stream.try_clone()?),
         state,
         messages_pool,
-        last_read_message_id: String::new(),
+        last_read_sequence: 0,
+        last_reaction_update: 0,
         connected_user_username: None,
-        connected_peer_addr: stream.try_clone()?.peer_addr()?.to_string()
+        connected_peer_addr: stream.try_clone()?.peer_addr()?.to_string(),
+        peer_version: LEGACY_PROTOCOL_VERSION,
+        peer_capabilities: Vec::new(),
+        metrics
       };
   
-      manager.process_connection()?;
-      Ok(())
+      let peer_addr = manager.connected_peer_addr.clone();
+      manager.state.get().connections.insert(peer_addr.clone(), stream.try_clone()?);
+      let result = manager.process_connection();
+      manager.state.get().connections.remove(&peer_addr);
+
+      result
     }
   }
\ No newline at end of file
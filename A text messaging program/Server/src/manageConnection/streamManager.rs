@@ -2,15 +2,32 @@ use std::{
     io::{
       Write, BufReader
     }, 
+    net::IpAddr,
     thread,
-    sync::mpsc::{
-      self, 
-      Sender
+    sync::{
+      mpsc::{
+        self, 
+        Sender
+      },
+      Arc
     }
   };
   use anyhow::Result;
+  use parking_lot::Mutex;
+  use std::str::FromStr;
+  use log::{info, warn};
   
-  use crate::{manageConnection::dataManager::DataManager, reader::StreamReader};
+  use crate::{
+    compression::compress_frame,
+    federation::Link,
+    manageConnection::dataManager::DataManager, 
+    messagesPool::MessagesPool,
+    metrics::Metrics,
+    rateLimiter::{FloodGuard, Verdict}, 
+    reader::StreamReader, 
+    state::State,
+    types::{Capability, Signal, SignalsData}
+  };
   
   use super::manager::Manager;
   
@@ -18,32 +35,44 @@ use std::{
     fn process_connection(&mut self) -> Result<()>;
     fn process_disconnection(&mut self) -> Result<()>;
     fn send_data(&mut self, data: &str) -> Result<()>;
-    fn process_signals(&mut self, sender: Sender<()>) -> Result<()>;
+    fn process_signals(&mut self, sender: Sender<()>, direct_sender: Sender<String>) -> Result<()>;
   }
   
   impl StreamManager for Manager {
     fn process_connection(&mut self) -> Result<()> {
-      println!("Connection established - {}", self.connected_peer_addr);
+      info!(peer = self.connected_peer_addr.as_str(); "connection established");
   
-      let auth_data = match BufReader::new(self.stream.try_clone()?).read_signal() {
+      let limits = self.state.get().settings.frame_limits.clone();
+      let mut reader = BufReader::new(self.stream.try_clone()?);
+      let auth_data = match reader.read_first_signal(&limits) {
         Ok(v) => v,
-        Err(_) => {
+        Err(e) => {
+          if !e.is_disconnect() {
+            Metrics::count(&self.metrics.dropped_frames, 1);
+            warn!(peer = self.connected_peer_addr.as_str(), error = e.to_string().as_str(); "invalid handshake");
+          }
           self.process_disconnection()?;
           return Ok(())
         }
       };
   
+      // other servers link on the same port
+      if SignalsData::from_str(&auth_data).is_ok_and(|v| matches!(v.signalType, Some(Signal::Link))) {
+        return Link::accept(self.stream.try_clone()?, reader, &auth_data, self.state.clone(), self.messages_pool.clone());
+      }
+
       if self.auth(auth_data.clone()).is_err() {
-        print!("Error in connection");
+        warn!(peer = self.connected_peer_addr.as_str(); "authorization denied");
         self.deny_auth()?;
         self.process_disconnection()?;
         return Ok(())
       }
   
       let (channel_sender, channel_receiver) = mpsc::channel::<()>();
-      self.process_signals(channel_sender)?;
+      let (direct_sender, direct_receiver) = mpsc::channel::<String>();
+      self.process_signals(channel_sender, direct_sender)?;
       
-      self.process_messages_pool(channel_receiver)?;
+      self.process_messages_pool(channel_receiver, direct_receiver)?;
   
       self.process_disconnection()?;
       Ok(())
@@ -53,33 +82,86 @@ use std::{
       if self.connected_user_username.is_some() {
         self.remove_user(self.connected_user_username.clone().unwrap())?;
       }
-      println!("Connection closed - {}", self.connected_peer_addr);
+      info!(
+        peer = self.connected_peer_addr.as_str(), 
+        user = self.connected_user_username.as_deref().unwrap_or_default(); 
+        "connection closed"
+      );
       Ok(())
     }
   
     fn send_data(&mut self, data: &str) -> Result<()> {
-      self.stream.write(data.as_bytes())?;
+      let compressed = self.peer_capabilities.contains(&Capability::Deflate)
+        .then(|| compress_frame(data))
+        .flatten();
+      if let Some(v) = &compressed {
+        Metrics::count(&self.metrics.bytes_saved, (data.len() - v.len()) as u64);
+      }
+      let data = compressed.as_deref().unwrap_or(data);
+
+      self.stream.write_all(data.as_bytes())?;
+      Metrics::count(&self.metrics.bytes_out, data.len() as u64);
       Ok(())
     }
   
-    fn process_signals(&mut self, sender: Sender<()>) -> Result<()> {
+    fn process_signals(&mut self, sender: Sender<()>, direct_sender: Sender<String>) -> Result<()> {
       let cloned_stream = self.stream.try_clone()?;
-      let cloned_messages_pool = self.messages_pool.clone();
+      let handler = SignalHandler {
+        state: self.state.clone(),
+        messages_pool: self.messages_pool.clone(),
+        username: self.connected_user_username.clone().unwrap_or_default(),
+        peer_addr: self.connected_peer_addr.clone(),
+        peer_ip: self.stream.peer_addr()?.ip(),
+        supports_warnings: self.peer_capabilities.contains(&Capability::Warnings),
+        metrics: self.metrics.clone(),
+        replies: direct_sender,
+      };
   
       thread::spawn(move || -> Result<()> {
+        let (peer_addr, username) = (handler.peer_addr.as_str(), handler.username.as_str());
         let mut reader = BufReader::new(cloned_stream.try_clone()?);
+        let mut guard = FloodGuard::new();
         loop {
-          let data_from_socket = match reader.read_signal() {
+          let limits = handler.state.get().settings.frame_limits.clone();
+          let data_from_socket = match reader.read_signal(&limits) {
             Ok(s) => s,
-            Err(_) => {
+            Err(e) if e.is_disconnect() => break,
+            // the stream can't be trusted after a bad frame
+            Err(e) => {
+              Metrics::count(&handler.metrics.dropped_frames, 1);
+              warn!(peer = peer_addr, user = username, error = e.to_string().as_str(); "dropping connection");
+              handler.warn(&format!("Disconnected: {e}"));
               break;
             }
           };
-  
-          match Self::process_incoming_message(cloned_messages_pool.clone(), data_from_socket) {
-            Ok(_) => (),
-            Err(_) => println!("invalid message")
+          Metrics::count(&handler.metrics.bytes_in, data_from_socket.len() as u64);
+
+          // every frame is counted against the limits before it's handled
+          let verdict = {
+            let mut state = handler.state.get();
+            let limits = state.settings.rate_limits.clone();
+            guard.check(data_from_socket.len(), &limits, state.ip_buckets(handler.peer_ip))
           };
+          if verdict != Verdict::Pass {
+            Metrics::count(&handler.metrics.dropped_frames, 1);
+          }
+          let notice = match verdict {
+            Verdict::Pass => None,
+            Verdict::Muted => continue,
+            Verdict::Warn => Some("You are sending messages too fast, slow down".to_owned()),
+            Verdict::Mute(v) => Some(format!("You are muted for {} seconds for flooding", v.as_secs())),
+            Verdict::Disconnect => Some("You are disconnected for flooding".to_owned()),
+          };
+          if let Some(text) = notice {
+            warn!(peer = peer_addr, user = username, verdict = format!("{verdict:?}").as_str(); "rate limit exceeded");
+            handler.warn(&text);
+            if verdict == Verdict::Disconnect {
+              break;
+            }
+            continue;
+          }
+  
+          handler.handle(&data_from_socket);
         }
   
         sender.send(())?;
@@ -89,4 +171,120 @@ use std::{
   
       Ok(())
     }
-  }
\ No newline at end of file
+  }
+
+  // ----- Signals of an authorized user -----
+  // one handler per signal, frames have passed the flood check already;
+  // what they answer goes to the user through 'replies'
+  struct SignalHandler {
+    state: State,
+    messages_pool: Arc<Mutex<MessagesPool>>,
+    username: String,
+    peer_addr: String,
+    peer_ip: IpAddr,
+    supports_warnings: bool,
+    metrics: Arc<Metrics>,
+    replies: Sender<String>,
+  }
+
+  impl SignalHandler {
+    fn handle(&self, signal: &str) {
+      let data = SignalsData::from_str(signal).ok();
+      let signal_type = data.as_ref().and_then(|v| v.signalType);
+
+      // asking and moderating are left to muted users, nothing else is
+      let allowed_muted = matches!(
+        signal_type,
+        Some(Signal::Stats | Signal::Search | Signal::History | Signal::Kick | Signal::Mute | Signal::Ban | Signal::Unban)
+      );
+      if !allowed_muted && self.is_muted() {
+        return;
+      }
+
+      match signal_type {
+        Some(Signal::Stats) => self.stats(),
+        Some(Signal::Search) => self.search(signal),
+        Some(Signal::History) => self.history(signal),
+        Some(Signal::Kick | Signal::Mute | Signal::Ban | Signal::Unban) => self.moderate(signal),
+        Some(Signal::FileOffer | Signal::FileAccept) => self.broker_file(signal),
+        Some(Signal::React) => self.react(signal),
+        _ if data.is_some_and(|v| v.recipient.is_some()) => self.direct_message(signal),
+        _ => self.message(signal),
+      }
+    }
+
+    fn reply(&self, frame: String) {
+      let _ = self.replies.send(frame);
+    }
+
+    fn warn(&self, text: &str) {
+      self.reply(Manager::warning_signal(text, self.supports_warnings));
+    }
+
+    // tells the user if they are muted
+    fn is_muted(&self) -> bool {
+      let muted = self.state.get().sanctions.muted_for(&self.username);
+      if let Some(left) = muted {
+        self.warn(&format!("You are muted by a moderator for {} more seconds", left.as_secs() + 1));
+      }
+      muted.is_some()
+    }
+
+    fn stats(&self) {
+      self.reply(Manager::stats_signal(&self.state, &self.username, self.supports_warnings));
+    }
+
+    fn search(&self, signal: &str) {
+      for frame in Manager::search_signals(&self.messages_pool, signal, self.supports_warnings) {
+        self.reply(frame);
+      }
+    }
+
+    fn history(&self, signal: &str) {
+      for frame in Manager::history_signals(&self.messages_pool, signal, self.supports_warnings) {
+        self.reply(frame);
+      }
+    }
+
+    fn moderate(&self, signal: &str) {
+      if let Err(e) = Manager::moderate(&self.state, &self.messages_pool, &self.username, signal) {
+        self.warn(&format!("Moderation failed: {e}"));
+      }
+    }
+
+    fn broker_file(&self, signal: &str) {
+      if let Err(e) = Manager::broker_file(&self.state, &self.username, self.peer_ip, signal) {
+        self.warn(&format!("File transfer failed: {e}"));
+      }
+    }
+
+    fn react(&self, signal: &str) {
+      if let Err(e) = Manager::process_reaction(&self.messages_pool, &self.username, signal) {
+        self.warn(&format!("Reaction failed: {e}"));
+      }
+    }
+
+    fn direct_message(&self, signal: &str) {
+      match Manager::process_direct_message(&self.state, &self.username, signal.to_owned()) {
+        Ok(echo) => {
+          self.metrics.message_received();
+          self.reply(echo);
+        },
+        Err(e) => {
+          warn!(peer = self.peer_addr.as_str(), user = self.username.as_str(), error = e.to_string().as_str(); "invalid direct message");
+          self.warn(&format!("Message rejected: {e}"));
+        }
+      }
+    }
+
+    fn message(&self, signal: &str) {
+      match Manager::process_incoming_message(self.messages_pool.clone(), &self.username, signal.to_owned()) {
+        Ok(_) => self.metrics.message_received(),
+        Err(e) => {
+          Metrics::count(&self.metrics.dropped_frames, 1);
+          warn!(peer = self.peer_addr.as_str(), user = self.username.as_str(), error = e.to_string().as_str(); "invalid message");
+          self.warn(&format!("Message rejected: {e}"));
+        }
+      }
+    }
+  }
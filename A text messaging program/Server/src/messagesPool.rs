@@ -1,4 +1,18 @@
-use std::{collections::{HashMap, VecDeque}, iter};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{SystemTime, UNIX_EPOCH}
+  };
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    reactions::{self, Reaction, MAX_REACTIONS},
+    search::{SearchQuery, MAX_RESULTS},
+    types::Presence
+  };
+
+// reaction changes kept for connections to catch up with
+const REACTION_UPDATES: usize = 1024;
 
 #[derive(Debug, Clone)]
 pub struct PoolMessage {
@@ -6,106 +20,335 @@ pub struct PoolMessage {
   pub username: String,
   pub message: String,
   pub from_server: bool,
+  // join and leave notices, 'username' is the user they are about
+  pub presence: Option<Presence>,
+  // set by the pool on push
+  pub timestamp: u64,
+  pub sequence: u64,
+  // direct messages only, they never get into the pool
+  pub recipient: Option<String>,
+  pub away: bool,
+  // relayed from a linked server: the server it was sent on and every
+  // server it passed through, starting with that one
+  pub origin: Option<String>,
+  pub via: Vec<String>,
+  // changed in place with 'MessagesPool::react'
+  pub reactions: Vec<Reaction>,
+  // id of the message this one answers
+  pub reply_to: Option<String>,
 }
 
 impl PoolMessage {
-  fn new() -> PoolMessage {
+  pub fn new() -> PoolMessage {
     PoolMessage {
       id: String::new(),
       username: String::new(),
       message: String::new(),
       from_server: false,
+      presence: None,
+      timestamp: 0,
+      sequence: 0,
+      recipient: None,
+      away: false,
+      origin: None,
+      via: Vec::new(),
+      reactions: Vec::new(),
+      reply_to: None,
     }
   }
 }
 
+// UTC milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|v| v.as_millis() as u64)
+    .unwrap_or_default()
+}
+
+// the history kept can't be bigger than that
+pub const MAX_CAPACITY: usize = 100_000;
+
+// ----- Reading since a sequence number -----
+// a reader that fell so far behind that messages it hasn't read were
+// pushed out gets a gap, with the number of them and what's left
+#[derive(Debug)]
+pub enum Since {
+  Messages(Vec<PoolMessage>),
+  Gap { missed: u64, messages: Vec<PoolMessage> },
+}
+
+// ----- Messages pool -----
+// a ring buffer of the last 'capacity' messages; sequence numbers go up
+// by one with every push, so a message is found by its number without
+// a search and the numbers of the messages kept have no holes
 pub struct MessagesPool {
   pool: VecDeque<PoolMessage>,
-  indexes: HashMap<String, u8>,
-  length: u16,
+  // sequence numbers of the messages kept, by id
+  sequences: HashMap<String, u64>,
+  capacity: usize,
+  last_sequence: u64,
+  // (update number, message id) of every change of reactions
+  reaction_updates: VecDeque<(u64, String)>,
+  last_reaction_update: u64,
 }
 
 impl MessagesPool {
-  pub fn new() -> MessagesPool {
-    let arr: VecDeque<PoolMessage> = iter::repeat_with(|| PoolMessage::new())
-      .take(256)
-      .collect();
-    MessagesPool { 
-      pool: arr, 
-      indexes: HashMap::new(),
-      length: 0
+  pub fn new(capacity: usize) -> MessagesPool {
+    let capacity = capacity.clamp(1, MAX_CAPACITY);
+    MessagesPool {
+      pool: VecDeque::with_capacity(capacity),
+      sequences: HashMap::new(),
+      capacity,
+      last_sequence: 0,
+      reaction_updates: VecDeque::new(),
+      last_reaction_update: 0
     }
   }
 
-  pub fn push(&mut self, v: PoolMessage) {
-    if self.length == 256 {
-      self.pool.pop_front();
-      self.pool.push_back(v);
+  // stamps the message with the time and the next sequence number,
+  // the pool is behind a lock, so the numbers follow the order of pushes
+  pub fn push(&mut self, mut v: PoolMessage) {
+    self.last_sequence += 1;
+    v.sequence = self.last_sequence;
+    v.timestamp = now_millis();
 
-      let mut new_indexes: HashMap<String, u8> = HashMap::new();
-      for (index, message) in self.pool.iter().enumerate() {
-        new_indexes.insert(message.id.clone(), index as u8);
+    if self.pool.len() == self.capacity {
+      if let Some(oldest) = self.pool.pop_front() {
+        // an id pushed again belongs to the newer message
+        if self.sequences.get(&oldest.id) == Some(&oldest.sequence) {
+          self.sequences.remove(&oldest.id);
+        }
       }
-      self.indexes = new_indexes;
     }
-    else {
-      let index = self.length as u8;
-      self.pool[index as usize] = v.clone();
-      self.length += 1;
-      self.indexes.insert(v.id.clone(), index);
+    self.sequences.insert(v.id.clone(), v.sequence);
+    self.pool.push_back(v);
+  }
+
+  pub fn last_sequence(&self) -> u64 {
+    self.last_sequence
+  }
+
+  // the number just before the oldest message kept, reading since it
+  // gives the whole history
+  pub fn history_start(&self) -> u64 {
+    self.pool.front().map_or(self.last_sequence, |v| v.sequence - 1)
+  }
+
+  // messages pushed after 'sequence', oldest first
+  pub fn read_since(&self, sequence: u64) -> Since {
+    let start = self.history_start();
+    if sequence < start {
+      return Since::Gap { missed: start - sequence, messages: self.pool.iter().cloned().collect() };
     }
+    let skipped = usize::try_from(sequence - start).unwrap_or(usize::MAX).min(self.pool.len());
+    Since::Messages(self.pool.range(skipped..).cloned().collect())
   }
 
-  fn read_from(&self, id: &str) -> (Vec<PoolMessage>, Option<String>) {
-    let found_index = self.indexes.get(id);
-    match found_index {
-      Some(v) => {
-        let index: u16 = v.to_owned() as u16 + 1;
-        let sliced_pool = &Vec::from(self.pool.clone())[index.into()..self.length.into()];
-        let sliced_pool_last = {
-          if sliced_pool.len() == 0 {
-            None
-          }
-          else {
-            Some(sliced_pool.last().unwrap().clone().id)
-          }
-        };
-        return (sliced_pool.clone().into(), sliced_pool_last)
-      },
-      None => {
-        let last_el = self.last();
-        let index = match last_el {
-          Some(v) => Some(v.id.clone()),
-          None => None
-        };
-        let sliced_pool = &Vec::from(self.pool.clone())[..self.length.into()];
-        return (sliced_pool.into(), index)
+  // the newest matches, oldest first
+  pub fn search(&self, query: &SearchQuery) -> Vec<PoolMessage> {
+    let mut found: Vec<PoolMessage> = self.pool.iter()
+      .rev()
+      .filter(|v| query.matches(v))
+      .take(MAX_RESULTS)
+      .cloned()
+      .collect();
+    found.reverse();
+    found
+  }
+
+  // chat messages of the history, oldest first
+  pub fn messages(&self) -> Vec<PoolMessage> {
+    self.pool.iter()
+      .filter(|v| !v.from_server)
+      .cloned()
+      .collect()
+  }
+
+  // replies are made only to chat messages still in the history
+  pub fn can_reply_to(&self, id: &str) -> bool {
+    self.get(id).is_some_and(|v| !v.from_server)
+  }
+
+  // the thread 'id' is a part of, oldest first: its first message
+  // still in the history and every reply under that
+  pub fn thread(&self, id: &str) -> Option<Vec<PoolMessage>> {
+    let mut root = self.get(id)?;
+    while let Some(parent) = root.reply_to.as_deref().and_then(|v| self.get(v)) {
+      root = parent;
+    }
+
+    let mut ids: HashSet<&str> = HashSet::from([root.id.as_str()]);
+    let mut thread = Vec::new();
+    for message in &self.pool {
+      let is_reply = message.reply_to.as_deref().is_some_and(|v| ids.contains(v));
+      if message.id == root.id || is_reply {
+        ids.insert(&message.id);
+        thread.push(message.clone());
       }
     }
+    Some(thread)
   }
 
-  pub fn has_new(&self, id: &str) -> Option<(Vec<PoolMessage>, Option<String>)> {
-    let last_el = self.last();
-    match last_el {
-      Some(_) => Some(self.read_from(id)),
-      None => None,
-    }
+  fn get(&self, id: &str) -> Option<&PoolMessage> {
+    self.index_of(id).map(|v| &self.pool[v])
+  }
+
+  fn index_of(&self, id: &str) -> Option<usize> {
+    let offset = self.sequences.get(id)?.checked_sub(self.pool.front()?.sequence)?;
+    usize::try_from(offset).ok()
   }
 
-  fn last(&self) -> Option<PoolMessage> {
-    let last_index = {
-      if self.length > 0 {
-        self.length - 1
-      } else {
-        self.length
+  // adding a reaction twice and removing one of another user are errors
+  pub fn react(&mut self, id: &str, username: &str, text: &str, remove: bool) -> Result<()> {
+    reactions::validate(text)?;
+    let index = self.index_of(id).ok_or_else(|| anyhow!("the message is not in the history"))?;
+    let message = &mut self.pool[index];
+    if message.from_server {
+      bail!("server messages can't be reacted to");
+    }
+
+    let position = message.reactions.iter().position(|v| v.text == text);
+    if remove {
+      let reaction = position.map(|v| &mut message.reactions[v]);
+      let Some(reaction) = reaction.filter(|v| v.users.iter().any(|user| user == username)) else {
+        bail!("you haven't reacted with {text}");
+      };
+      reaction.users.retain(|v| v != username);
+      message.reactions.retain(|v| !v.users.is_empty());
+    }
+    else {
+      match position {
+        Some(v) if message.reactions[v].users.iter().any(|user| user == username) => {
+          bail!("you have already reacted with {text}");
+        },
+        Some(v) => message.reactions[v].users.push(username.to_owned()),
+        None if message.reactions.len() >= MAX_REACTIONS => {
+          bail!("a message can't have more than {MAX_REACTIONS} different reactions");
+        },
+        None => message.reactions.push(Reaction { text: text.to_owned(), users: vec![username.to_owned()] }),
       }
-    };
+    }
 
-    let last_el = &self.pool[last_index.into()];
-    if last_el.id == "".to_owned() {
-      None
-    } else {
-      Some(last_el.to_owned())
+    self.last_reaction_update += 1;
+    if self.reaction_updates.len() == REACTION_UPDATES {
+      self.reaction_updates.pop_front();
     }
+    self.reaction_updates.push_back((self.last_reaction_update, id.to_owned()));
+    Ok(())
   }
-}
\ No newline at end of file
+
+  pub fn last_reaction_update(&self) -> u64 {
+    self.last_reaction_update
+  }
+
+  // messages with reactions changed after 'update', in the order of changes,
+  // and the number of the latest change
+  pub fn reactions_since(&self, update: u64) -> (Vec<PoolMessage>, u64) {
+    let mut ids: Vec<&str> = Vec::new();
+    for (_, id) in self.reaction_updates.iter().filter(|(v, _)| *v > update) {
+      ids.retain(|v| v != id);
+      ids.push(id);
+    }
+    let messages = ids.iter()
+      .filter_map(|v| self.get(v))
+      .cloned()
+      .collect();
+    (messages, self.last_reaction_update)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn message(id: &str) -> PoolMessage {
+    PoolMessage { id: id.to_owned(), username: "alice".to_owned(), message: id.to_owned(), ..PoolMessage::new() }
+  }
+
+  fn pool_of(capacity: usize, ids: &[&str]) -> MessagesPool {
+    let mut pool = MessagesPool::new(capacity);
+    for id in ids {
+      pool.push(message(id));
+    }
+    pool
+  }
+
+  fn ids(messages: &[PoolMessage]) -> Vec<&str> {
+    messages.iter().map(|v| v.id.as_str()).collect()
+  }
+
+  #[test]
+  fn oldest_messages_make_room_at_capacity() {
+    let pool = pool_of(3, &["a", "b", "c", "d", "e"]);
+    assert_eq!(ids(&pool.messages()), ["c", "d", "e"]);
+    assert_eq!(pool.last_sequence(), 5);
+    assert_eq!(pool.history_start(), 2);
+    assert_eq!(pool.index_of("a"), None);
+    assert_eq!(pool.index_of("c"), Some(0));
+    assert_eq!(pool.index_of("e"), Some(2));
+  }
+
+  #[test]
+  fn empty_pool_starts_at_the_last_sequence() {
+    let pool = MessagesPool::new(3);
+    assert_eq!(pool.history_start(), 0);
+    assert!(matches!(pool.read_since(0), Since::Messages(v) if v.is_empty()));
+  }
+
+  #[test]
+  fn reads_return_only_new_messages() {
+    let mut pool = pool_of(5, &["a", "b", "c"]);
+    let last = pool.last_sequence();
+    pool.push(message("d"));
+    pool.push(message("e"));
+
+    let Since::Messages(messages) = pool.read_since(last) else { panic!("no gap expected") };
+    assert_eq!(ids(&messages), ["d", "e"]);
+    assert_eq!(messages.iter().map(|v| v.sequence).collect::<Vec<_>>(), [4, 5]);
+    assert!(matches!(pool.read_since(pool.last_sequence()), Since::Messages(v) if v.is_empty()));
+    // after the ring wrapped around too
+    for id in ["f", "g", "h", "i"] {
+      pool.push(message(id));
+    }
+    let Since::Messages(messages) = pool.read_since(7) else { panic!("no gap expected") };
+    assert_eq!(ids(&messages), ["h", "i"]);
+  }
+
+  #[test]
+  fn overflow_gives_the_exact_gap() {
+    let mut pool = pool_of(3, &["a", "b"]);
+    let last = pool.last_sequence();
+    for id in ["c", "d", "e", "f", "g"] {
+      pool.push(message(id));
+    }
+
+    // 'c' and 'd' were pushed out before they were read
+    match pool.read_since(last) {
+      Since::Gap { missed, messages } => {
+        assert_eq!(missed, 2);
+        assert_eq!(ids(&messages), ["e", "f", "g"]);
+      },
+      Since::Messages(v) => panic!("expected a gap, got {:?}", ids(&v)),
+    }
+    // the whole history is no gap
+    assert!(matches!(pool.read_since(pool.history_start()), Since::Messages(v) if v.len() == 3));
+  }
+
+  #[test]
+  fn id_pushed_again_belongs_to_the_newer_message() {
+    let mut pool = pool_of(3, &["a", "b", "a"]);
+    assert_eq!(pool.index_of("a"), Some(2));
+
+    // the old 'a' going away leaves the new one
+    pool.push(message("c"));
+    assert_eq!(ids(&pool.messages()), ["b", "a", "c"]);
+    assert_eq!(pool.index_of("a"), Some(1));
+    assert_eq!(pool.get("a").map(|v| v.sequence), Some(3));
+
+    pool.push(message("d"));
+    pool.push(message("e"));
+    assert_eq!(pool.index_of("a"), None);
+    assert!(!pool.can_reply_to("a"));
+  }
+}
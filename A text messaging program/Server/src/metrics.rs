@@ -0,0 +1,88 @@
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant}
+  };
+use parking_lot::Mutex;
+
+// messages per second are averaged over this window
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+// ----- Runtime counters -----
+// shared by all connections, updated without taking the state lock
+pub struct Metrics {
+  started: Instant,
+  pub connections_total: AtomicU64,
+  pub messages_in: AtomicU64,
+  pub bytes_in: AtomicU64,
+  pub bytes_out: AtomicU64,
+  // what compression saved of 'bytes_out'
+  pub bytes_saved: AtomicU64,
+  pub dropped_frames: AtomicU64,
+  recent_messages: Mutex<VecDeque<Instant>>,
+}
+
+impl Metrics {
+  pub fn new() -> Metrics {
+    Metrics {
+      started: Instant::now(),
+      connections_total: AtomicU64::new(0),
+      messages_in: AtomicU64::new(0),
+      bytes_in: AtomicU64::new(0),
+      bytes_out: AtomicU64::new(0),
+      bytes_saved: AtomicU64::new(0),
+      dropped_frames: AtomicU64::new(0),
+      recent_messages: Mutex::new(VecDeque::new()),
+    }
+  }
+
+  pub fn count(counter: &AtomicU64, amount: u64) {
+    counter.fetch_add(amount, Ordering::Relaxed);
+  }
+
+  pub fn message_received(&self) {
+    Self::count(&self.messages_in, 1);
+
+    let now = Instant::now();
+    let mut recent = self.recent_messages.lock();
+    recent.push_back(now);
+    Self::prune(&mut recent, now);
+  }
+
+  pub fn messages_per_sec(&self) -> f64 {
+    let mut recent = self.recent_messages.lock();
+    Self::prune(&mut recent, Instant::now());
+    recent.len() as f64 / RATE_WINDOW.as_secs_f64()
+  }
+
+  fn prune(recent: &mut VecDeque<Instant>, now: Instant) {
+    while recent.front().is_some_and(|v| now.duration_since(*v) > RATE_WINDOW) {
+      recent.pop_front();
+    }
+  }
+
+  // name, value pairs in the order they are shown
+  pub fn snapshot(&self, connected_users: usize) -> Vec<(&'static str, String)> {
+    let load = |v: &AtomicU64| v.load(Ordering::Relaxed).to_string();
+    vec![
+      ("uptime_seconds", self.started.elapsed().as_secs().to_string()),
+      ("connected_users", connected_users.to_string()),
+      ("connections_total", load(&self.connections_total)),
+      ("messages_total", load(&self.messages_in)),
+      ("messages_per_second", format!("{:.2}", self.messages_per_sec())),
+      ("bytes_in_total", load(&self.bytes_in)),
+      ("bytes_out_total", load(&self.bytes_out)),
+      ("bytes_saved_total", load(&self.bytes_saved)),
+      ("dropped_frames_total", load(&self.dropped_frames)),
+    ]
+  }
+
+  // plain text for the STATS signal and the metrics endpoint
+  pub fn render(&self, connected_users: usize) -> String {
+    self.snapshot(connected_users)
+      .into_iter()
+      .map(|(name, value)| format!("chat_{name} {value}"))
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+}
@@ -0,0 +1,86 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    time::{Duration, Instant}
+  };
+
+// longer mutes are bans in all but name
+pub const MAX_MUTE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+// ----- Role type -----
+// taken from the config on login, every higher role can act on lower ones
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+  User,
+  Moderator,
+  Owner,
+}
+
+impl Role {
+  pub fn can_moderate(self, target: Role) -> bool {
+    self >= Role::Moderator && self > target
+  }
+}
+
+impl fmt::Display for Role {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      Role::User => "user",
+      Role::Moderator => "moderator",
+      Role::Owner => "owner",
+    })
+  }
+}
+
+// ----- Sanctions -----
+// set by moderators at runtime, bans from the config are in 'Settings'
+pub struct Sanctions {
+  mutes: HashMap<String, Instant>,
+  bans: HashSet<String>,
+  // reasons for users to be disconnected, picked up by their connections
+  kicks: HashMap<String, String>,
+}
+
+impl Sanctions {
+  pub fn new() -> Sanctions {
+    Sanctions {
+      mutes: HashMap::new(),
+      bans: HashSet::new(),
+      kicks: HashMap::new(),
+    }
+  }
+
+  pub fn mute(&mut self, username: &str, duration: Duration) {
+    self.mutes.insert(username.to_owned(), Instant::now() + duration.min(MAX_MUTE));
+  }
+
+  // time left, expired mutes are dropped
+  pub fn muted_for(&mut self, username: &str) -> Option<Duration> {
+    let left = self.mutes.get(username)?.checked_duration_since(Instant::now());
+    if left.is_none() {
+      self.mutes.remove(username);
+    }
+    left
+  }
+
+  pub fn ban(&mut self, username: &str) {
+    self.bans.insert(username.to_owned());
+  }
+
+  // false if the user wasn't banned
+  pub fn unban(&mut self, username: &str) -> bool {
+    self.bans.remove(username)
+  }
+
+  pub fn is_banned(&self, username: &str) -> bool {
+    self.bans.contains(username)
+  }
+
+  pub fn kick(&mut self, username: &str, reason: String) {
+    self.kicks.insert(username.to_owned(), reason);
+  }
+
+  pub fn take_kick(&mut self, username: &str) -> Option<String> {
+    self.kicks.remove(username)
+  }
+}
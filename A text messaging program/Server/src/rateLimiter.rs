@@ -0,0 +1,252 @@
+use std::time::{Duration, Instant};
+
+use crate::settings::RateLimits;
+
+// violations are forgotten after this much quiet time
+const STRIKES_RESET: Duration = Duration::from_secs(60);
+
+// ----- Token bucket -----
+// refills 'rate' tokens per second up to 'rate * burst_secs',
+// rate 0 means no limit
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+  tokens: f64,
+  last_refill: Instant,
+}
+
+impl TokenBucket {
+  pub fn new() -> TokenBucket {
+    TokenBucket {
+      tokens: f64::INFINITY,
+      last_refill: Instant::now(),
+    }
+  }
+
+  // rate and burst are passed on each call, so reloaded limits apply at once
+  pub fn allows(&mut self, amount: f64, rate: u32, burst_secs: u32) -> bool {
+    if rate == 0 {
+      return true;
+    }
+
+    let now = Instant::now();
+    let capacity = f64::from(rate) * f64::from(burst_secs.max(1));
+    let refill = now.duration_since(self.last_refill).as_secs_f64() * f64::from(rate);
+    self.tokens = (self.tokens + refill).min(capacity);
+    self.last_refill = now;
+    self.tokens >= amount
+  }
+
+  // only after 'allows', nothing is spent without a limit
+  pub fn spend(&mut self, amount: f64, rate: u32) {
+    if rate != 0 {
+      self.tokens -= amount;
+    }
+  }
+}
+
+// ----- Messages and bytes buckets -----
+#[derive(Debug, Clone)]
+pub struct RateBuckets {
+  messages: TokenBucket,
+  bytes: TokenBucket,
+  pub last_used: Instant,
+}
+
+impl RateBuckets {
+  pub fn new() -> RateBuckets {
+    RateBuckets {
+      messages: TokenBucket::new(),
+      bytes: TokenBucket::new(),
+      last_used: Instant::now(),
+    }
+  }
+
+  // both buckets are refilled, a refused message costs nothing
+  pub fn allows(&mut self, bytes: usize, messages_per_sec: u32, bytes_per_sec: u32, burst_secs: u32) -> bool {
+    self.last_used = Instant::now();
+    let messages_ok = self.messages.allows(1.0, messages_per_sec, burst_secs);
+    let bytes_ok = self.bytes.allows(bytes as f64, bytes_per_sec, burst_secs);
+    messages_ok && bytes_ok
+  }
+
+  pub fn spend(&mut self, bytes: usize, messages_per_sec: u32, bytes_per_sec: u32) {
+    self.messages.spend(1.0, messages_per_sec);
+    self.bytes.spend(bytes as f64, bytes_per_sec);
+  }
+}
+
+// ----- Verdict type -----
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Verdict {
+  Pass,
+  Warn,
+  Mute(Duration),
+  Muted,
+  Disconnect,
+}
+
+// ----- Per connection guard -----
+// every limit violation is a strike: first strikes are warned about,
+// then the user is muted, after several mutes disconnected
+#[derive(Debug, Clone)]
+pub struct FloodGuard {
+  buckets: RateBuckets,
+  strikes: u32,
+  mutes: u32,
+  last_strike: Instant,
+  muted_until: Option<Instant>,
+}
+
+impl FloodGuard {
+  pub fn new() -> FloodGuard {
+    FloodGuard {
+      buckets: RateBuckets::new(),
+      strikes: 0,
+      mutes: 0,
+      last_strike: Instant::now(),
+      muted_until: None,
+    }
+  }
+
+  pub fn check(&mut self, bytes: usize, limits: &RateLimits, ip_buckets: &mut RateBuckets) -> Verdict {
+    let now = Instant::now();
+    if let Some(until) = self.muted_until {
+      if now < until {
+        return Verdict::Muted;
+      }
+      self.muted_until = None;
+    }
+    if now.duration_since(self.last_strike) > STRIKES_RESET {
+      self.strikes = 0;
+    }
+
+    // both are checked before either is spent, so a message refused by the
+    // address limit doesn't drain the connection's own bucket and back
+    let own_ok = self.buckets.allows(bytes, limits.messages_per_sec, limits.bytes_per_sec, limits.burst_secs);
+    let ip_ok = ip_buckets.allows(bytes, limits.ip_messages_per_sec, limits.ip_bytes_per_sec, limits.burst_secs);
+    if own_ok && ip_ok {
+      self.buckets.spend(bytes, limits.messages_per_sec, limits.bytes_per_sec);
+      ip_buckets.spend(bytes, limits.ip_messages_per_sec, limits.ip_bytes_per_sec);
+      return Verdict::Pass;
+    }
+
+    self.strikes += 1;
+    self.last_strike = now;
+    if self.strikes <= limits.warnings_before_mute {
+      return Verdict::Warn;
+    }
+
+    self.strikes = 0;
+    self.mutes += 1;
+    if limits.mutes_before_disconnect != 0 && self.mutes > limits.mutes_before_disconnect {
+      return Verdict::Disconnect;
+    }
+    let duration = Duration::from_secs(limits.mute_secs.into());
+    self.muted_until = Some(now + duration);
+    Verdict::Mute(duration)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn limits(messages_per_sec: u32, ip_messages_per_sec: u32) -> RateLimits {
+    RateLimits {
+      messages_per_sec,
+      bytes_per_sec: 0,
+      ip_messages_per_sec,
+      ip_bytes_per_sec: 0,
+      burst_secs: 1,
+      warnings_before_mute: 2,
+      mute_secs: 1,
+      mutes_before_disconnect: 1,
+    }
+  }
+
+  fn take(bucket: &mut TokenBucket, amount: f64, rate: u32, burst_secs: u32) -> bool {
+    let allowed = bucket.allows(amount, rate, burst_secs);
+    if allowed {
+      bucket.spend(amount, rate);
+    }
+    allowed
+  }
+
+  #[test]
+  fn bucket_allows_a_burst_then_refills() {
+    let mut bucket = TokenBucket::new();
+    assert!(take(&mut bucket, 20.0, 10, 2));
+    assert!(!take(&mut bucket, 1.0, 10, 2));
+
+    // half a second at 10 per second
+    bucket.last_refill -= Duration::from_millis(500);
+    assert!(take(&mut bucket, 4.0, 10, 2));
+    assert!(!take(&mut bucket, 2.0, 10, 2));
+
+    // a long pause fills it up to the burst only
+    bucket.last_refill -= Duration::from_secs(60);
+    assert!(!take(&mut bucket, 21.0, 10, 2));
+    assert!(take(&mut bucket, 20.0, 10, 2));
+  }
+
+  #[test]
+  fn bucket_without_rate_has_no_limit() {
+    let mut bucket = TokenBucket::new();
+    for _ in 0..1000 {
+      assert!(take(&mut bucket, 1e9, 0, 1));
+    }
+  }
+
+  #[test]
+  fn refused_message_costs_nothing() {
+    let mut buckets = RateBuckets::new();
+    // messages allow it, bytes don't
+    assert!(!buckets.allows(200, 5, 100, 1));
+    assert_eq!(buckets.messages.tokens, 5.0);
+  }
+
+  #[test]
+  fn address_limit_does_not_drain_own_bucket() {
+    let limits = limits(2, 1);
+    let mut ip_buckets = RateBuckets::new();
+    let mut first = FloodGuard::new();
+    let mut second = FloodGuard::new();
+
+    assert_eq!(first.check(10, &limits, &mut ip_buckets), Verdict::Pass);
+    assert_eq!(second.check(10, &limits, &mut ip_buckets), Verdict::Warn);
+    assert_eq!(second.buckets.messages.tokens, 2.0);
+  }
+
+  #[test]
+  fn strikes_escalate_from_warning_to_disconnect() {
+    let limits = limits(1, 0);
+    let mut ip_buckets = RateBuckets::new();
+    let mut guard = FloodGuard::new();
+
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Pass);
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Warn);
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Warn);
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Mute(Duration::from_secs(1)));
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Muted);
+
+    // the mute is over, but the user keeps flooding
+    guard.muted_until = Some(Instant::now());
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Warn);
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Warn);
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Disconnect);
+  }
+
+  #[test]
+  fn quiet_time_forgets_strikes() {
+    let limits = limits(1, 0);
+    let mut ip_buckets = RateBuckets::new();
+    let mut guard = FloodGuard::new();
+
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Pass);
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Warn);
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Warn);
+
+    guard.last_strike -= STRIKES_RESET + Duration::from_secs(1);
+    assert_eq!(guard.check(10, &limits, &mut ip_buckets), Verdict::Warn);
+  }
+}
@@ -0,0 +1,40 @@
+use anyhow::{bail, Result};
+
+// a reaction is a short token, an emoji or something like '+1'
+const MAX_REACTION_BYTES: usize = 32;
+// distinct reactions to a single message
+pub const MAX_REACTIONS: usize = 20;
+
+// ----- Reaction -----
+// users are kept in the order they reacted
+#[derive(Debug, Clone)]
+pub struct Reaction {
+  pub text: String,
+  pub users: Vec<String>,
+}
+
+// reactions go in a header as comma-separated 'text=count'
+pub fn validate(text: &str) -> Result<()> {
+  if text.is_empty() {
+    bail!("no reaction given");
+  }
+  if text.len() > MAX_REACTION_BYTES {
+    bail!("a reaction can't be longer than {MAX_REACTION_BYTES} bytes");
+  }
+  if text.chars().any(|v| v.is_whitespace() || v.is_control() || v == ',' || v == '=') {
+    bail!("a reaction can't have spaces, ',' or '='");
+  }
+  Ok(())
+}
+
+// count of every reaction and the ones 'username' made
+pub fn summary(reactions: &[Reaction], username: &str) -> (Vec<(String, u32)>, Vec<String>) {
+  let counts = reactions.iter()
+    .map(|v| (v.text.clone(), v.users.len() as u32))
+    .collect();
+  let own = reactions.iter()
+    .filter(|v| v.users.iter().any(|user| user == username))
+    .map(|v| v.text.clone())
+    .collect();
+  (counts, own)
+}
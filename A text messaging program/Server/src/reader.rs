@@ -1,32 +1,174 @@
-use std::{io::{BufReader, self, BufRead, Error, ErrorKind}, self, net::TcpStream};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, BufReader, ErrorKind},
+    net::TcpStream,
+    time::{Duration, Instant}
+  };
 
-use crate::types::SignalsHeader;
+use crate::{
+    compression::{decompress_frame, DecompressError},
+    types::SignalsHeader
+  };
+
+const FRAME_END: &[u8] = b"\r\n\r\n";
+
+// ----- Frame limits -----
+#[derive(Debug, Clone)]
+pub struct FrameLimits {
+  pub max_header_bytes: usize,
+  pub max_body_bytes: usize,
+  // how long a started frame may take to arrive completely
+  pub frame_timeout: Duration,
+}
+
+impl Default for FrameLimits {
+  fn default() -> FrameLimits {
+    FrameLimits {
+      max_header_bytes: 8 * 1024,
+      max_body_bytes: 64 * 1024,
+      frame_timeout: Duration::from_secs(10),
+    }
+  }
+}
+
+// ----- Error type -----
+#[derive(Debug)]
+pub enum ReadError {
+  // peer closed or reset the connection
+  Disconnected,
+  // frame was not completed in time
+  Timeout,
+  HeadersTooLarge(usize),
+  BodyTooLarge(usize),
+  Malformed(String),
+  Io(io::Error),
+}
+
+impl Error for ReadError {}
+impl fmt::Display for ReadError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ReadError::Disconnected => write!(f, "connection closed"),
+      ReadError::Timeout => write!(f, "frame was not completed in time"),
+      ReadError::HeadersTooLarge(v) => write!(f, "headers are larger than {v} bytes"),
+      ReadError::BodyTooLarge(v) => write!(f, "message is larger than {v} bytes"),
+      ReadError::Malformed(v) => write!(f, "malformed frame: {v}"),
+      ReadError::Io(e) => write!(f, "read error: {e}"),
+    }
+  }
+}
+
+impl ReadError {
+  // a peer going away is normal, everything else is its fault
+  pub fn is_disconnect(&self) -> bool {
+    matches!(self, ReadError::Disconnected)
+  }
+}
 
 pub trait StreamReader {
-  fn read_signal(&mut self) -> io::Result<String>;
+  // waits for the next frame as long as needed, but once it started
+  // it has to be completed within the frame timeout
+  fn read_signal(&mut self, limits: &FrameLimits) -> Result<String, ReadError>;
+  // the whole frame has to arrive within the frame timeout (handshake)
+  fn read_first_signal(&mut self, limits: &FrameLimits) -> Result<String, ReadError>;
 }
 
 impl StreamReader for BufReader<TcpStream> {
-  fn read_signal(&mut self) -> io::Result<String> {
-    let mut res_line = String::new();
-    let mut headers_read = false;
-    loop {
-      let mut buf_line = String::new();
-      match self.read_line(&mut buf_line) {
-        Err(_) => return Err(Error::new(ErrorKind::ConnectionAborted, "boom boom")),
-        Ok(0) => return Err(Error::new(ErrorKind::BrokenPipe, "boom boom")),
-        Ok(m) => m,
-      };
-      res_line.push_str(&buf_line);
-  
-      if res_line.ends_with("\r\n\r\n"){
-        if !res_line.contains(&SignalsHeader::withMess.to_string()) || headers_read {
-          break;
-        }
-        headers_read = true;
+  fn read_signal(&mut self, limits: &FrameLimits) -> Result<String, ReadError> {
+    read_frame(self, limits, None)
+  }
+
+  fn read_first_signal(&mut self, limits: &FrameLimits) -> Result<String, ReadError> {
+    read_frame(self, limits, Some(Instant::now() + limits.frame_timeout))
+  }
+}
+
+fn read_frame(
+  reader: &mut BufReader<TcpStream>,
+  limits: &FrameLimits,
+  mut deadline: Option<Instant>
+) -> Result<String, ReadError> {
+  let with_message = SignalsHeader::withMess.to_string();
+  let mut frame: Vec<u8> = Vec::new();
+  // length of the headers part, known once it is read
+  let mut headers_len: Option<usize> = None;
+
+  loop {
+    let limit = match headers_len {
+      Some(v) => v + limits.max_body_bytes,
+      None => limits.max_header_bytes,
+    };
+
+    let timeout = match deadline {
+      Some(v) => match v.checked_duration_since(Instant::now()) {
+        Some(left) if !left.is_zero() => Some(left),
+        _ => return Err(ReadError::Timeout),
+      },
+      None => None,
+    };
+    reader.get_ref().set_read_timeout(timeout).map_err(ReadError::Io)?;
+
+    let buf = match reader.fill_buf() {
+      Ok(v) => v,
+      Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+      Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+      Err(e) if is_disconnect(&e) => return Err(ReadError::Disconnected),
+      Err(e) => return Err(ReadError::Io(e)),
+    };
+    if buf.is_empty() {
+      return Err(ReadError::Disconnected);
+    }
+
+    // taking at most one line and never more than the limit allows
+    let allowed = limit + 1 - frame.len();
+    let (chunk_len, line_end) = match buf.iter().take(allowed).position(|v| *v == b'\n') {
+      Some(v) => (v + 1, true),
+      None => (buf.len().min(allowed), false),
+    };
+    frame.extend_from_slice(&buf[..chunk_len]);
+    reader.consume(chunk_len);
+
+    // frames end with an extra empty line, it doesn't start the next one
+    if frame == b"\r\n" && headers_len.is_none() {
+      frame.clear();
+      continue;
+    }
+    if deadline.is_none() {
+      deadline = Some(Instant::now() + limits.frame_timeout);
+    }
+
+    if frame.len() > limit {
+      return Err(match headers_len {
+        Some(_) => ReadError::BodyTooLarge(limits.max_body_bytes),
+        None => ReadError::HeadersTooLarge(limits.max_header_bytes),
+      });
+    }
+
+    if line_end && frame.ends_with(FRAME_END) {
+      let headers_done = headers_len.is_some();
+      if !headers_done && contains(&frame, with_message.as_bytes()) {
+        headers_len = Some(frame.len());
+        continue;
       }
+      break;
     }
-  
-    Ok(res_line)
   }
-}
\ No newline at end of file
+
+  let frame = String::from_utf8(frame).map_err(|_| ReadError::Malformed("frame is not valid UTF-8".to_owned()))?;
+  decompress_frame(frame, limits.max_body_bytes).map_err(|e| match e {
+    DecompressError::TooLarge(v) => ReadError::BodyTooLarge(v),
+    DecompressError::Invalid => ReadError::Malformed(e.to_string()),
+  })
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+  haystack.windows(needle.len()).any(|v| v == needle)
+}
+
+fn is_disconnect(error: &io::Error) -> bool {
+  matches!(
+    error.kind(),
+    ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe | ErrorKind::UnexpectedEof
+  )
+}
@@ -0,0 +1,79 @@
+use anyhow::{anyhow, Result};
+use regex_lite::{Regex, RegexBuilder};
+
+use crate::{messagesPool::PoolMessage, types::SignalsData};
+
+// the newest matches are answered, older ones are cut off
+pub const MAX_RESULTS: usize = 50;
+const MAX_QUERY_LENGTH: usize = 256;
+// keeps compiled patterns from users small
+const REGEX_SIZE_LIMIT: usize = 64 * 1024;
+
+// ----- Text matcher -----
+// 'text' is matched as a case insensitive substring, '/text/' as a regex
+enum Matcher {
+  Substring(String),
+  Regex(Regex),
+}
+
+impl Matcher {
+  fn new(query: &str) -> Result<Matcher> {
+    let pattern = query.strip_prefix('/').and_then(|v| v.strip_suffix('/'));
+    match pattern {
+      Some(v) if !v.is_empty() => {
+        let regex = RegexBuilder::new(v)
+          .case_insensitive(true)
+          .size_limit(REGEX_SIZE_LIMIT)
+          .build()
+          .map_err(|e| anyhow!("invalid pattern: {e}"))?;
+        Ok(Matcher::Regex(regex))
+      },
+      _ => Ok(Matcher::Substring(query.to_lowercase())),
+    }
+  }
+
+  fn is_match(&self, text: &str) -> bool {
+    match self {
+      Matcher::Substring(v) => text.to_lowercase().contains(v.as_str()),
+      Matcher::Regex(v) => v.is_match(text),
+    }
+  }
+}
+
+// ----- Search query -----
+// taken from a SEARCH signal: the text in the message body,
+// AUTHOR, SINCE and UNTIL headers narrow it down
+pub struct SearchQuery {
+  matcher: Matcher,
+  author: Option<String>,
+  since: Option<u64>,
+  until: Option<u64>,
+}
+
+impl SearchQuery {
+  pub fn from_signal(data: &SignalsData) -> Result<SearchQuery> {
+    let text = data.message.as_deref().unwrap_or_default().trim();
+    if text.is_empty() && data.author.is_none() {
+      return Err(anyhow!("search needs a text or an author"));
+    }
+    if text.len() > MAX_QUERY_LENGTH {
+      return Err(anyhow!("search text is longer than {MAX_QUERY_LENGTH} bytes"));
+    }
+
+    Ok(SearchQuery {
+      matcher: Matcher::new(text)?,
+      author: data.author.clone(),
+      since: data.since,
+      until: data.until,
+    })
+  }
+
+  // server notices are not a part of the discussion, so they're never found
+  pub fn matches(&self, message: &PoolMessage) -> bool {
+    !message.from_server
+      && self.author.as_ref().is_none_or(|v| v.eq_ignore_ascii_case(&message.username))
+      && self.since.is_none_or(|v| message.timestamp >= v)
+      && self.until.is_none_or(|v| message.timestamp <= v)
+      && self.matcher.is_match(&message.message)
+  }
+}
@@ -1,20 +1,188 @@
-use std::{net::TcpListener, thread, sync::Arc};
-use anyhow::Result;
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    thread,
+    sync::Arc,
+    time::Duration
+  };
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
 use parking_lot::Mutex;
+use socket2::{Domain, Protocol, Socket, Type};
 
-use crate::{state::State, manageConnection::Manager, messagesPool::MessagesPool};
+use crate::{
+    chatServer::ServerHandle,
+    federation::Link,
+    irc::IrcSession,
+    state::State,
+    manageConnection::Manager,
+    messagesPool::MessagesPool
+  };
+
+// for reading a metrics request and writing the answer
+const METRICS_TIMEOUT: Duration = Duration::from_secs(2);
 
 pub struct Service;
 
 impl Service {
+  // the command line server: runs until killed, reloads the config on SIGHUP
   pub fn run(state: State) -> Result<()> {
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", state.get().settings.port))?;
+    let handle = Self::start(state.clone())?;
+    // before the addresses are printed, a SIGHUP sent right away must not kill the server
+    #[cfg(unix)]
+    Self::watch_config(state)?;
 
-    println!("Running!");
+    for address in handle.addresses() {
+      println!("Running on {address}");
+    }
+    for address in handle.irc_addresses() {
+      println!("IRC on {address}");
+    }
+    if let Some(address) = handle.metrics_address() {
+      println!("Metrics on http://{address}");
+    }
 
-    let messages_pool = Arc::new(Mutex::new(MessagesPool::new()));
+    handle.wait();
+    Ok(())
+  }
 
+  // binds and accepts connections in background threads
+  pub fn start(state: State) -> Result<ServerHandle> {
+    let settings = state.get().settings.clone();
+    let listeners = Self::bind(&settings.bind, settings.port)?;
+    let mut addresses = Vec::new();
+    for listener in listeners.iter() {
+      addresses.push(listener.local_addr()?);
+    }
+    let irc_listeners = match settings.irc_port {
+      Some(port) => Self::bind(&settings.bind, port)?,
+      None => Vec::new(),
+    };
+    let mut irc_addresses = Vec::new();
+    for listener in irc_listeners.iter() {
+      irc_addresses.push(listener.local_addr()?);
+    }
+
+    let messages_pool = Arc::new(Mutex::new(MessagesPool::new(settings.history)));
+
+    let metrics_listener = match settings.metrics_port {
+      Some(port) => {
+        let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+        Some(TcpListener::bind(address).map_err(|e| Self::bind_error(e, address))?)
+      },
+      None => None,
+    };
+    let metrics_address = match &metrics_listener {
+      Some(v) => Some(v.local_addr()?),
+      None => None,
+    };
+
+    let mut threads: Vec<_> = listeners.into_iter()
+      .map(|listener| {
+        let cloned_state = state.clone();
+        let cloned_messages_pool = messages_pool.clone();
+        thread::spawn(move || Self::accept(listener, cloned_state, cloned_messages_pool))
+      })
+      .collect();
+    for listener in irc_listeners {
+      let cloned_state = state.clone();
+      let cloned_messages_pool = messages_pool.clone();
+      threads.push(thread::spawn(move || Self::accept_irc(listener, cloned_state, cloned_messages_pool)));
+    }
+    if let Some(listener) = metrics_listener {
+      let cloned_state = state.clone();
+      threads.push(thread::spawn(move || Self::serve_metrics(listener, cloned_state)));
+    }
+    for address in settings.links.iter().filter(|_| settings.federation) {
+      let address = address.clone();
+      let cloned_state = state.clone();
+      let cloned_messages_pool = messages_pool.clone();
+      threads.push(thread::spawn(move || Link::maintain(address, cloned_state, cloned_messages_pool)));
+    }
+
+    Ok(ServerHandle::new(state, addresses, irc_addresses, metrics_address, threads))
+  }
+
+  // SIGHUP rereads the config and swaps settings in the shared state,
+  // connected users banned by the new config are kicked
+  #[cfg(unix)]
+  fn watch_config(state: State) -> Result<()> {
+    use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+    let mut signals = Signals::new([SIGHUP])?;
+    thread::spawn(move || {
+      for _ in signals.forever() {
+        let current = state.get().settings.clone();
+        let reloaded = match current.reload() {
+          Ok(v) => v,
+          Err(e) => {
+            error!(error = format!("{e:#}").as_str(); "config is not reloaded");
+            continue;
+          }
+        };
+
+        log::set_max_level(reloaded.log_level);
+        let mut state = state.get();
+        state.settings = reloaded;
+        let banned: Vec<String> = state.users.iter()
+          .filter(|(username, user)| {
+            let ip = user.address.parse::<SocketAddr>().map(|v| v.ip().to_string()).unwrap_or_default();
+            state.settings.is_banned(username, &ip)
+          })
+          .map(|(username, _)| username.clone())
+          .collect();
+        for username in banned {
+          state.sanctions.kick(&username, "You were banned from this server".to_owned());
+        }
+        drop(state);
+        info!("config reloaded");
+      }
+    });
+
+    Ok(())
+  }
+
+  // plain text counters over HTTP, only for the local machine
+  fn serve_metrics(listener: TcpListener, state: State) {
     for con in listener.incoming() {
+      if state.get().stopping {
+        break;
+      }
+      let Ok(con) = con else { continue };
+      // a scraper that never sends its request only holds up its own thread
+      let cloned_state = state.clone();
+      thread::spawn(move || {
+        if let Err(e) = Self::send_metrics(con, &cloned_state) {
+          warn!(error = e.to_string().as_str(); "metrics request failed");
+        }
+      });
+    }
+  }
+
+  fn send_metrics(mut stream: TcpStream, state: &State) -> std::io::Result<()> {
+    // the request itself doesn't matter, any path gets the metrics
+    stream.set_read_timeout(Some(METRICS_TIMEOUT))?;
+    stream.set_write_timeout(Some(METRICS_TIMEOUT))?;
+    let mut request = [0u8; 1024];
+    let _ = stream.read(&mut request)?;
+
+    let body = {
+      let state = state.get();
+      format!("{}\n", state.metrics.render(state.users.len()))
+    };
+    write!(
+      stream,
+      "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+      body.len()
+    )
+  }
+
+  fn accept(listener: TcpListener, state: State, messages_pool: Arc<Mutex<MessagesPool>>) {
+    for con in listener.incoming() {
+      // the handle wakes listeners up with a connection when it stops
+      if state.get().stopping {
+        break;
+      }
       let cloned_state = state.clone();
       let cloned_messages_pool = messages_pool.clone();
       thread::spawn(move || -> Result<()> {
@@ -23,7 +191,69 @@ impl Service {
         Ok(())
       });
     }
+  }
 
-    Ok(())
+  // IRC clients, one thread per connection like the chat itself
+  fn accept_irc(listener: TcpListener, state: State, messages_pool: Arc<Mutex<MessagesPool>>) {
+    for con in listener.incoming() {
+      if state.get().stopping {
+        break;
+      }
+      let cloned_state = state.clone();
+      let cloned_messages_pool = messages_pool.clone();
+      thread::spawn(move || -> Result<()> {
+        IrcSession::serve(con?, cloned_state, cloned_messages_pool)
+      });
+    }
+  }
+
+  fn bind(bind: &[IpAddr], port: u16) -> Result<Vec<TcpListener>> {
+    // '::' accepts IPv4 too, unless IPv4 addresses are bound separately
+    let dual_stack = !bind.iter().any(|v| v.is_ipv4());
+
+    let mut port = port;
+    let mut listeners = Vec::new();
+    for address in bind.iter() {
+      let listener = match Self::bind_one(SocketAddr::new(*address, port), dual_stack) {
+        Ok(v) => v,
+        // no IPv6 on this host - falling back to IPv4
+        Err(e) if address.is_ipv6() && address.is_unspecified() && e.kind() != ErrorKind::AddrInUse => {
+          let fallback = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+          Self::bind_one(fallback, false).map_err(|e| Self::bind_error(e, fallback))?
+        },
+        Err(e) => return Err(Self::bind_error(e, SocketAddr::new(*address, port))),
+      };
+      // with '--port 0' every address gets the same port, picked by the first bind
+      port = listener.local_addr()?.port();
+      listeners.push(listener);
+    }
+
+    Ok(listeners)
+  }
+
+  fn bind_one(address: SocketAddr, dual_stack: bool) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+    if address.is_ipv6() {
+      socket.set_only_v6(!(dual_stack && address.ip().is_unspecified()))?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(128)?;
+
+    Ok(socket.into())
+  }
+
+  fn bind_error(error: std::io::Error, address: SocketAddr) -> anyhow::Error {
+    let port = address.port();
+    match error.kind() {
+      ErrorKind::AddrInUse => anyhow!(
+        "port {port} is already in use on {}, choose another one or pass '--port 0' to pick a free port",
+        address.ip()
+      ),
+      ErrorKind::AddrNotAvailable => anyhow!("address {} is not available on this host", address.ip()),
+      ErrorKind::PermissionDenied => anyhow!("not allowed to listen on {address}: {error}"),
+      _ => anyhow!("cannot listen on {address}: {error}"),
+    }
   }
-}
\ No newline at end of file
+}
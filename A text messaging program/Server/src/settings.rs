@@ -1,31 +1,322 @@
-use clap::{self, arg, Parser};
+use std::{net::{IpAddr, Ipv6Addr}, path::PathBuf, time::Duration};
+
+use anyhow::{anyhow, Result};
+use clap::{self, Parser};
+use log::{warn, LevelFilter};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    config::{Config, RateLimitConfig},
+    federation::{default_server_id, is_valid_server_id},
+    messagesPool::MAX_CAPACITY,
+    moderation::Role,
+    reader::FrameLimits
+  };
 
 // using macros for generating parser for command args
-#[derive(Parser)] 
+#[derive(Parser, Debug, Clone, Default)]
 pub struct Args {
-  #[arg(short, long, help = "Port that the server will serve")]
-  pub port: u16,
+  #[arg(short, long, help = "Port that the server will serve (0 - any free port)")]
+  pub port: Option<u16>,
 
   #[arg(short, long, help = "Maximum amount of chat users")]
   pub max_users: Option<u16>,
+
+  #[arg(long, value_parser = parse_history, help = "How many last messages the server keeps (default - 256)")]
+  pub history: Option<usize>,
+
+  #[arg(short, long, help = "Server name shown to connected users")]
+  pub name: Option<String>,
+
+  #[arg(
+    short,
+    long,
+    value_parser = parse_bind_address,
+    help = "Address to listen on, IPv4 or IPv6 (can be repeated, default - all interfaces, dual-stack)"
+  )]
+  pub bind: Vec<IpAddr>,
+
+  #[arg(short, long, help = "Path to the TOML config file, reloaded on SIGHUP")]
+  pub config: Option<PathBuf>,
+
+  #[arg(long, help = "Serve plain text metrics on this port of 127.0.0.1")]
+  pub metrics_port: Option<u16>,
+
+  #[arg(long, help = "Also serve IRC clients on this port (0 - any free port)")]
+  pub irc_port: Option<u16>,
+
+  #[arg(long, help = "Log level: off, error, warn, info, debug or trace")]
+  pub log_level: Option<LevelFilter>,
+
+  #[arg(long, help = "Id of this server in links to other servers, remote users are shown as user@id (default - from the name)")]
+  pub server_id: Option<String>,
+
+  #[arg(long = "link", help = "Address of a server to link to, host:port (can be repeated)")]
+  pub links: Vec<String>,
+
+  #[arg(long, help = "Link to other servers and accept their links, off by default")]
+  pub federation: bool,
+}
+
+// accepts IPv6 literals with or without brackets
+pub fn parse_bind_address(s: &str) -> Result<IpAddr, String> {
+  let trimmed = s.trim().trim_start_matches('[').trim_end_matches(']');
+  trimmed.parse::<IpAddr>()
+    .map_err(|_| format!("'{s}' is not an IPv4 or IPv6 address"))
+}
+
+// tokens and link secrets are compared in constant time, so the time
+// of an answer tells nothing about how much of a guess was right
+pub fn tokens_match(expected: &str, given: &str) -> bool {
+  expected.as_bytes().ct_eq(given.as_bytes()).into()
+}
+
+fn parse_history(s: &str) -> Result<usize, String> {
+  match s.parse::<usize>() {
+    Ok(v) if (1..=MAX_CAPACITY).contains(&v) => Ok(v),
+    _ => Err(format!("'{s}' is not a number between 1 and {MAX_CAPACITY}")),
+  }
+}
+
+// flood protection, rates of 0 mean no limit
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+  pub messages_per_sec: u32,
+  pub bytes_per_sec: u32,
+  pub ip_messages_per_sec: u32,
+  pub ip_bytes_per_sec: u32,
+  pub burst_secs: u32,
+  pub warnings_before_mute: u32,
+  pub mute_secs: u32,
+  // 0 - never disconnect
+  pub mutes_before_disconnect: u32,
+}
+
+impl RateLimits {
+  fn from_config(config: &RateLimitConfig) -> RateLimits {
+    RateLimits {
+      messages_per_sec: config.messages_per_sec.unwrap_or(5),
+      bytes_per_sec: config.bytes_per_sec.unwrap_or(16 * 1024),
+      ip_messages_per_sec: config.ip_messages_per_sec.unwrap_or(20),
+      ip_bytes_per_sec: config.ip_bytes_per_sec.unwrap_or(64 * 1024),
+      burst_secs: config.burst_secs.unwrap_or(2),
+      warnings_before_mute: config.warnings_before_mute.unwrap_or(3),
+      mute_secs: config.mute_secs.unwrap_or(30),
+      mutes_before_disconnect: config.mutes_before_disconnect.unwrap_or(2),
+    }
+  }
 }
 
-// using macros for generating code for right output ({:?}) and 
+// using macros for generating code for right output ({:?}) and
 // rewrited method 'clone'
 #[derive(Debug, Clone)]
 pub struct Settings {
   pub port: u16,
   pub max_users: u16,
+  pub bind: Vec<IpAddr>,
+  pub name: String,
+  pub motd: Option<String>,
+  pub banned: Vec<String>,
+  pub admins: Vec<String>,
+  pub owners: Vec<String>,
+  pub moderators: Vec<String>,
+  // the users above log in with these, see 'authorize'
+  pub owner_token: Option<String>,
+  pub moderator_token: Option<String>,
+  pub admin_token: Option<String>,
+  pub history: usize,
+  pub mailbox_size: u16,
+  pub rate_limits: RateLimits,
+  pub frame_limits: FrameLimits,
+  pub metrics_port: Option<u16>,
+  // IRC gateway, see 'irc.rs'
+  pub irc_port: Option<u16>,
+  pub log_level: LevelFilter,
+  // federation, see 'federation.rs'
+  pub federation: bool,
+  pub server_id: String,
+  pub links: Vec<String>,
+  pub link_secret: Option<String>,
+  // ids and IP addresses of servers allowed to link
+  pub link_allow: Vec<String>,
+  args: Args,
 }
 
 impl Settings {
-  pub fn new() -> Settings {
+  pub fn new() -> Result<Settings> {
     let args = Args::parse(); // getting args
-    
+    Self::from_args(args)
+  }
+
+  // reads the config file again, command line args still win over it;
+  // listeners, the pool and links are already created, so port, bind,
+  // history and federation ids stay as they are until restart
+  pub fn reload(&self) -> Result<Settings> {
+    let reloaded = Self::from_args(self.args.clone())?;
+    if reloaded.port != self.port
+      || reloaded.bind != self.bind
+      || reloaded.history != self.history
+      || reloaded.metrics_port != self.metrics_port
+      || reloaded.irc_port != self.irc_port
+      || reloaded.federation != self.federation
+      || reloaded.server_id != self.server_id
+      || reloaded.links != self.links {
+      warn!("changes of port, bind, history, metrics and IRC ports, server id and links are applied after restart");
+    }
+
+    Ok(Settings {
+      port: self.port,
+      bind: self.bind.clone(),
+      history: self.history,
+      metrics_port: self.metrics_port,
+      irc_port: self.irc_port,
+      federation: self.federation,
+      server_id: self.server_id.clone(),
+      links: self.links.clone(),
+      ..reloaded
+    })
+  }
+
+  pub fn from_args(args: Args) -> Result<Settings> {
+    let config = match &args.config {
+      Some(path) => Config::read(path)?,
+      None => Config::default(),
+    };
+
+    let bind = if !args.bind.is_empty() {
+      args.bind.clone()
+    } else {
+      config.bind_addresses()?
+    };
+
+    let name = args.name.clone().or(config.name.clone()).unwrap_or("chat-server".to_owned());
+    let server_id = match args.server_id.clone().or(config.federation.server_id.clone()) {
+      Some(v) if !is_valid_server_id(&v) => {
+        return Err(anyhow!("invalid server id '{v}': use letters, digits, '-', '_' and '.' only"))
+      },
+      Some(v) => v,
+      None => default_server_id(&name),
+    };
+    let federation = args.federation || config.federation.enabled;
+    let links = if !args.links.is_empty() {
+      args.links.clone()
+    } else {
+      config.federation.links.clone()
+    };
+    if !federation && !links.is_empty() {
+      return Err(anyhow!("links are set, but federation is off: pass '--federation' or set `federation.enabled = true`"));
+    }
+
     // creating new instance
-    Settings { 
-      port: args.port, 
-      max_users: args.max_users.unwrap_or(10), 
+    Ok(Settings {
+      port: args.port
+        .or(config.port)
+        .ok_or_else(|| anyhow!("port is not set, pass '--port' or set `port` in the config"))?,
+      max_users: args.max_users.or(config.max_users).unwrap_or(10),
+      bind: if bind.is_empty() {
+        vec![IpAddr::V6(Ipv6Addr::UNSPECIFIED)]
+      } else {
+        bind
+      },
+      name,
+      motd: config.motd.clone(),
+      banned: config.banned.clone(),
+      admins: config.admins.clone(),
+      owners: config.owners.clone(),
+      moderators: config.moderators.clone(),
+      // header values are trimmed, so the tokens are too
+      owner_token: config.tokens.owner.as_ref().map(|v| v.trim().to_owned()),
+      moderator_token: config.tokens.moderator.as_ref().map(|v| v.trim().to_owned()),
+      admin_token: config.tokens.admin.as_ref().map(|v| v.trim().to_owned()),
+      history: args.history.or(config.history).unwrap_or(256),
+      mailbox_size: config.mailbox_size.unwrap_or(50),
+      rate_limits: RateLimits::from_config(&config.rate_limit),
+      frame_limits: {
+        let default = FrameLimits::default();
+        FrameLimits {
+          max_header_bytes: config.limits.max_header_bytes.unwrap_or(default.max_header_bytes),
+          max_body_bytes: config.limits.max_body_bytes.unwrap_or(default.max_body_bytes),
+          frame_timeout: config.limits.frame_timeout_secs
+            .map(Duration::from_secs)
+            .unwrap_or(default.frame_timeout),
+        }
+      },
+      metrics_port: args.metrics_port.or(config.metrics.port),
+      irc_port: args.irc_port.or(config.irc.port),
+      log_level: match args.log_level {
+        Some(v) => v,
+        None => config.log_level()?.unwrap_or(LevelFilter::Info),
+      },
+      federation,
+      server_id,
+      links,
+      // header values are trimmed, so the secret is too
+      link_secret: config.federation.secret.as_ref().map(|v| v.trim().to_owned()),
+      link_allow: config.federation.allow.clone(),
+      args,
+    })
+  }
+
+  pub fn is_banned(&self, username: &str, ip: &str) -> bool {
+    self.banned.iter().any(|v| v == username || v == ip)
+  }
+
+  // a server links in only when federation is on and it is trusted: with
+  // the secret, from the allowlist, or both when both are set
+  pub fn accepts_link(&self, server_id: &str, ip: IpAddr, secret: Option<&str>) -> Result<()> {
+    if !self.federation {
+      return Err(anyhow!("federation is off"));
+    }
+    if self.link_secret.is_none() && self.link_allow.is_empty() {
+      return Err(anyhow!("neither a secret nor allowed servers are set"));
+    }
+    if let Some(expected) = self.link_secret.as_deref() {
+      if !secret.is_some_and(|v| tokens_match(expected, v)) {
+        return Err(anyhow!("wrong secret"));
+      }
     }
+    // IPv4 peers of a dual-stack listener come as IPv4-mapped IPv6
+    let ip = ip.to_canonical();
+    let allowed = |v: &String| v == server_id || v.parse::<IpAddr>().is_ok_and(|v| v.to_canonical() == ip);
+    if !self.link_allow.is_empty() && !self.link_allow.iter().any(allowed) {
+      return Err(anyhow!("{server_id} ({ip}) is not allowed to link"));
+    }
+    Ok(())
   }
-}
\ No newline at end of file
+
+  // role and admin rights of a user logging in with 'token'; a user from
+  // the lists gets what their token matches, none of it is refused
+  pub fn authorize(&self, username: &str, token: Option<&str>) -> Result<(Role, bool)> {
+    let listed = |users: &[String], expected: &Option<String>| {
+      let listed = users.iter().any(|v| v == username);
+      let matches = listed && expected.as_deref().zip(token).is_some_and(|(a, b)| tokens_match(a, b));
+      (listed, matches)
+    };
+    let (owner, is_owner) = listed(&self.owners, &self.owner_token);
+    let (moderator, is_moderator) = listed(&self.moderators, &self.moderator_token);
+    let (admin, is_admin) = listed(&self.admins, &self.admin_token);
+
+    if (owner || moderator || admin) && !(is_owner || is_moderator || is_admin) {
+      return Err(anyhow!("{username} needs a valid token"));
+    }
+    let role = if is_owner {
+      Role::Owner
+    } else if is_moderator {
+      Role::Moderator
+    } else {
+      Role::User
+    };
+    Ok((role, is_admin))
+  }
+
+  // the configured role, for users who aren't connected
+  pub fn role_of(&self, username: &str) -> Role {
+    if self.owners.iter().any(|v| v == username) {
+      Role::Owner
+    } else if self.moderators.iter().any(|v| v == username) {
+      Role::Moderator
+    } else {
+      Role::User
+    }
+  }
+}
@@ -1,19 +1,56 @@
 use std::{
     sync::Arc, 
-    collections::HashMap
+    collections::HashMap,
+    net::{IpAddr, TcpStream},
+    time::Duration
   };
 use parking_lot::{Mutex, MutexGuard};
-use crate::settings::Settings;
+use crate::{
+  settings::Settings,
+  rateLimiter::RateBuckets,
+  metrics::Metrics,
+  mailbox::Mailboxes,
+  federation::Federation,
+  moderation::{Role, Sanctions},
+  transfers::Transfers,
+  types::Capability
+};
 
 #[derive(Debug, Clone)]
 pub struct UserData {
   pub address: String,
+  pub role: Role,
+  // allowed to request server stats
+  pub admin: bool,
+  // what the user's client announced, nothing for IRC users
+  pub capabilities: Vec<Capability>,
 }
 
-#[derive(Debug, Clone)]
 pub struct StateData {
   pub settings: Settings,
   pub users: HashMap<String, UserData>,
+  // shared by all connections from one address
+  pub ip_limits: HashMap<IpAddr, RateBuckets>,
+  pub metrics: Arc<Metrics>,
+  // every open connection by peer address, so they can be closed on stop
+  pub connections: HashMap<String, TcpStream>,
+  pub stopping: bool,
+  pub mailboxes: Mailboxes,
+  pub sanctions: Sanctions,
+  pub federation: Federation,
+  pub transfers: Transfers,
+}
+
+// buckets of addresses that were quiet this long are dropped
+const IP_LIMITS_TTL: Duration = Duration::from_secs(600);
+
+impl StateData {
+  pub fn ip_buckets(&mut self, ip: IpAddr) -> &mut RateBuckets {
+    if !self.ip_limits.contains_key(&ip) {
+      self.ip_limits.retain(|_, v| v.last_used.elapsed() < IP_LIMITS_TTL);
+    }
+    self.ip_limits.entry(ip).or_insert_with(RateBuckets::new)
+  }
 }
 
 pub struct State(Arc<Mutex<StateData>>);
@@ -23,7 +60,15 @@ impl State {
     State(
       Arc::new(Mutex::new(StateData { 
         settings, 
-        users: HashMap::new()
+        users: HashMap::new(),
+        ip_limits: HashMap::new(),
+        metrics: Arc::new(Metrics::new()),
+        connections: HashMap::new(),
+        stopping: false,
+        mailboxes: Mailboxes::new(),
+        sanctions: Sanctions::new(),
+        federation: Federation::new(),
+        transfers: Transfers::new()
       }))
     )
   }
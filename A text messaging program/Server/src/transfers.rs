@@ -0,0 +1,81 @@
+use std::collections::{HashMap, VecDeque};
+use anyhow::{bail, Result};
+
+// offers a user may have waiting for an answer at once
+const MAX_OFFERS: usize = 16;
+// a file name is only a name, the recipient picks the directory
+const MAX_NAME_LENGTH: usize = 255;
+
+// ----- Offer -----
+struct Offer {
+  from: String,
+  to: String,
+}
+
+// ----- File transfer broker -----
+// files go straight between the clients, relaying them would hold up
+// the chat; the server passes offers and answers only and gives the
+// recipient the address it sees the sender on
+pub struct Transfers {
+  offers: HashMap<String, Offer>,
+  // frames waiting for the connection of a user to pick them up
+  outbox: HashMap<String, VecDeque<String>>,
+}
+
+impl Transfers {
+  pub fn new() -> Transfers {
+    Transfers {
+      offers: HashMap::new(),
+      outbox: HashMap::new(),
+    }
+  }
+
+  // the recipient gets 'frame', the answer is let through once
+  pub fn offer(&mut self, id: &str, from: &str, to: &str, frame: String) -> Result<()> {
+    if self.offers.contains_key(id) {
+      bail!("transfer {id} is already offered");
+    }
+    if self.offers.values().filter(|v| v.from == from).count() >= MAX_OFFERS {
+      bail!("you can't have more than {MAX_OFFERS} offers waiting for an answer");
+    }
+    self.offers.insert(id.to_owned(), Offer { from: from.to_owned(), to: to.to_owned() });
+    self.post(to, frame);
+    Ok(())
+  }
+
+  // only the recipient answers an offer, the sender is returned
+  pub fn answer(&mut self, id: &str, username: &str) -> Result<String> {
+    match self.offers.get(id) {
+      Some(v) if v.to == username => Ok(self.offers.remove(id).map(|v| v.from).unwrap_or_default()),
+      _ => bail!("there is no offer {id} for you"),
+    }
+  }
+
+  pub fn post(&mut self, username: &str, frame: String) {
+    self.outbox.entry(username.to_owned()).or_default().push_back(frame);
+  }
+
+  pub fn take(&mut self, username: &str) -> Vec<String> {
+    self.outbox.remove(username).map(Vec::from).unwrap_or_default()
+  }
+
+  // offers of a user who left can't be answered any more
+  pub fn forget(&mut self, username: &str) {
+    self.offers.retain(|_, v| v.from != username && v.to != username);
+    self.outbox.remove(username);
+  }
+}
+
+// a name without directories, so nothing is written outside the
+// recipient's download directory
+pub fn is_valid_file_name(name: &str) -> bool {
+  !name.is_empty()
+    && name.len() <= MAX_NAME_LENGTH
+    && name != "."
+    && name != ".."
+    && !name.chars().any(|c| c == '/' || c == '\\' || c.is_control())
+}
+
+pub fn is_valid_sha256(hash: &str) -> bool {
+  hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
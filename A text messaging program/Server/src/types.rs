@@ -15,6 +15,24 @@ impl fmt::Display for SignalError{
 pub enum Signal{
     Connection,
     Message,
+    Warning,
+    Stats,
+    Search,
+    // moderation, the user acted on is in TARGET
+    Kick,
+    Mute,
+    Ban,
+    Unban,
+    // opens a link between two servers, see 'federation.rs'
+    Link,
+    // adds or, with REMOVE, takes back a reaction to MESSAGE_ID
+    React,
+    // messages of the history, or with THREAD only the ones of a thread
+    History,
+    // a file for the user in TO and the answer to it, REJECTED if it's declined;
+    // the file itself goes straight between the clients
+    FileOffer,
+    FileAccept,
 }
 
 impl FromStr for Signal{
@@ -24,6 +42,18 @@ impl FromStr for Signal{
         match s {
             "CONNECTION" => Ok(Signal::Connection),
             "MESSAGE" => Ok(Signal::Message),
+            "WARNING" => Ok(Signal::Warning),
+            "STATS" => Ok(Signal::Stats),
+            "SEARCH" => Ok(Signal::Search),
+            "KICK" => Ok(Signal::Kick),
+            "MUTE" => Ok(Signal::Mute),
+            "BAN" => Ok(Signal::Ban),
+            "UNBAN" => Ok(Signal::Unban),
+            "LINK" => Ok(Signal::Link),
+            "REACT" => Ok(Signal::React),
+            "HISTORY" => Ok(Signal::History),
+            "FILE_OFFER" => Ok(Signal::FileOffer),
+            "FILE_ACCEPT" => Ok(Signal::FileAccept),
             _ => Err(SignalError)
         }
     }
@@ -34,12 +64,91 @@ impl ToString for Signal{
         match self {
             Signal::Connection => "CONNECTION".to_owned(),
             Signal::Message => "MESSAGE".to_owned(),
+            Signal::Warning => "WARNING".to_owned(),
+            Signal::Stats => "STATS".to_owned(),
+            Signal::Search => "SEARCH".to_owned(),
+            Signal::Kick => "KICK".to_owned(),
+            Signal::Mute => "MUTE".to_owned(),
+            Signal::Ban => "BAN".to_owned(),
+            Signal::Unban => "UNBAN".to_owned(),
+            Signal::Link => "LINK".to_owned(),
+            Signal::React => "REACT".to_owned(),
+            Signal::History => "HISTORY".to_owned(),
+            Signal::FileOffer => "FILE_OFFER".to_owned(),
+            Signal::FileAccept => "FILE_ACCEPT".to_owned(),
+        }
+    }
+}
+
+// ----- Protocol version -----
+// frames without PROTOCOL_VERSION come from peers older than versioning
+pub const PROTOCOL_VERSION: u16 = 1;
+pub const LEGACY_PROTOCOL_VERSION: u16 = 0;
+
+// ----- Capability type -----
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Capability{
+    MessageIds,
+    Motd,
+    Warnings,
+    // server stamps messages with the authorized username itself
+    ServerIdentity,
+    Stats,
+    Search,
+    // MESSAGE with TO goes only to that user, queued while they're away
+    DirectMessages,
+    Moderation,
+    // large bodies may be sent deflated, see 'compression.rs'
+    Deflate,
+    Reactions,
+    // REPLY_TO on messages and HISTORY requests
+    Threads,
+    FileTransfer,
+}
+
+impl FromStr for Capability{
+    type Err = SignalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "MESSAGE_IDS" => Ok(Capability::MessageIds),
+            "MOTD" => Ok(Capability::Motd),
+            "WARNINGS" => Ok(Capability::Warnings),
+            "SERVER_IDENTITY" => Ok(Capability::ServerIdentity),
+            "STATS" => Ok(Capability::Stats),
+            "SEARCH" => Ok(Capability::Search),
+            "DIRECT_MESSAGES" => Ok(Capability::DirectMessages),
+            "MODERATION" => Ok(Capability::Moderation),
+            "DEFLATE" => Ok(Capability::Deflate),
+            "REACTIONS" => Ok(Capability::Reactions),
+            "THREADS" => Ok(Capability::Threads),
+            "FILE_TRANSFER" => Ok(Capability::FileTransfer),
+            _ => Err(SignalError)
+        }
+    }
+}
+
+impl ToString for Capability{
+    fn to_string(&self) -> String{
+        match self {
+            Capability::MessageIds => "MESSAGE_IDS".to_owned(),
+            Capability::Motd => "MOTD".to_owned(),
+            Capability::Warnings => "WARNINGS".to_owned(),
+            Capability::ServerIdentity => "SERVER_IDENTITY".to_owned(),
+            Capability::Stats => "STATS".to_owned(),
+            Capability::Search => "SEARCH".to_owned(),
+            Capability::DirectMessages => "DIRECT_MESSAGES".to_owned(),
+            Capability::Moderation => "MODERATION".to_owned(),
+            Capability::Deflate => "DEFLATE".to_owned(),
+            Capability::Reactions => "REACTIONS".to_owned(),
+            Capability::Threads => "THREADS".to_owned(),
+            Capability::FileTransfer => "FILE_TRANSFER".to_owned(),
         }
     }
 }
 
 // ----- Authoritation type -----
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Authoritation{
     Accepted,
     Denied,
@@ -66,12 +175,90 @@ impl ToString for Authoritation{
     }
 }
 
+// ----- Presence type -----
+// set on server messages about users coming and going
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Presence{
+    Joined,
+    Left,
+}
+
+impl FromStr for Presence{
+    type Err = SignalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "JOINED" => Ok(Presence::Joined),
+            "LEFT" => Ok(Presence::Left),
+            _ => Err(SignalError)
+        }
+    }
+}
+
+impl ToString for Presence{
+    fn to_string(&self) -> String{
+        match self {
+            Presence::Joined => "JOINED".to_owned(),
+            Presence::Left => "LEFT".to_owned(),
+        }
+    }
+}
+
 // ----- Signal's header type -----
 pub enum SignalsHeader{
     username(String), 
     key(String),
+    id(String),
     auth(Authoritation),
     signalType(Signal),
+    protocolVersion(u16),
+    // unknown capabilities are skipped, so newer peers can announce more
+    capabilities(Vec<Capability>),
+    serverName(String),
+    presence(Presence),
+    // UTC milliseconds since the Unix epoch, set by the server
+    timestamp(u64),
+    // grows by one with every message of the server
+    sequence(u64),
+    // SEARCH filters, times are UTC milliseconds like TIMESTAMP
+    author(String),
+    since(u64),
+    until(u64),
+    // number of matches, ends the answer to SEARCH
+    results(u32),
+    // recipient of a direct message
+    recipient(String),
+    // set on direct messages that came while the recipient was offline
+    away,
+    // user a moderation signal is about and seconds of a mute
+    target(String),
+    duration(u64),
+    // server links: id of a server, the shared secret of links, the server
+    // a relayed message comes from and every server it passed through;
+    // SECRET is also the token of owners, moderators and admins on login
+    serverId(String),
+    secret(String),
+    origin(String),
+    via(Vec<String>),
+    // reaction of a REACT, REMOVE takes it back
+    reaction(String),
+    remove,
+    // every reaction to a message with its count, and the ones of the
+    // recipient, the values are comma-separated
+    reactions(Vec<(String, u32)>),
+    reacted(Vec<String>),
+    // message a message answers, and any message of a thread to fetch
+    replyTo(String),
+    thread(String),
+    // file offers: id of the transfer, the file, the port the sender listens
+    // on and the address the server saw it on, which it gives the recipient
+    transfer(String),
+    fileName(String),
+    fileSize(u64),
+    sha256(String),
+    port(u16),
+    address(String),
+    rejected,
     withMess,
     serverMess,
 }
@@ -85,6 +272,7 @@ impl FromStr for SignalsHeader {
       match header {
         "USERNAME" => Ok(SignalsHeader::username(value.trim().to_owned())),
         "KEY" => Ok(SignalsHeader::key(value.trim().to_owned())),
+        "MESSAGE_ID" => Ok(SignalsHeader::id(value.trim().to_owned())),
         "AUTH_STATUS" => {
           match Authoritation::from_str(value.trim()) {
             Ok(v) => return Ok(SignalsHeader::auth(v)),
@@ -97,6 +285,110 @@ impl FromStr for SignalsHeader {
             Err(_) => Err(SignalError)
           }
         }
+        "PROTOCOL_VERSION" => {
+          match value.trim().parse::<u16>() {
+            Ok(v) => Ok(SignalsHeader::protocolVersion(v)),
+            Err(_) => Err(SignalError)
+          }
+        },
+        "CAPABILITIES" => Ok(SignalsHeader::capabilities(
+          value.split(',')
+            .filter_map(|v| Capability::from_str(v.trim()).ok())
+            .collect()
+        )),
+        "SERVER_NAME" => Ok(SignalsHeader::serverName(value.trim().to_owned())),
+        "TIMESTAMP" => {
+          match value.trim().parse::<u64>() {
+            Ok(v) => Ok(SignalsHeader::timestamp(v)),
+            Err(_) => Err(SignalError)
+          }
+        },
+        "SEQUENCE" => {
+          match value.trim().parse::<u64>() {
+            Ok(v) => Ok(SignalsHeader::sequence(v)),
+            Err(_) => Err(SignalError)
+          }
+        },
+        "AUTHOR" => Ok(SignalsHeader::author(value.trim().to_owned())),
+        "SINCE" => {
+          match value.trim().parse::<u64>() {
+            Ok(v) => Ok(SignalsHeader::since(v)),
+            Err(_) => Err(SignalError)
+          }
+        },
+        "UNTIL" => {
+          match value.trim().parse::<u64>() {
+            Ok(v) => Ok(SignalsHeader::until(v)),
+            Err(_) => Err(SignalError)
+          }
+        },
+        "RESULTS" => {
+          match value.trim().parse::<u32>() {
+            Ok(v) => Ok(SignalsHeader::results(v)),
+            Err(_) => Err(SignalError)
+          }
+        },
+        "PRESENCE" => {
+          match Presence::from_str(value.trim()) {
+            Ok(v) => Ok(SignalsHeader::presence(v)),
+            Err(_) => Err(SignalError)
+          }
+        },
+        "TO" => Ok(SignalsHeader::recipient(value.trim().to_owned())),
+        "AWAY" => Ok(SignalsHeader::away),
+        "TARGET" => Ok(SignalsHeader::target(value.trim().to_owned())),
+        "DURATION" => {
+          match value.trim().parse::<u64>() {
+            Ok(v) => Ok(SignalsHeader::duration(v)),
+            Err(_) => Err(SignalError)
+          }
+        },
+        "SERVER_ID" => Ok(SignalsHeader::serverId(value.trim().to_owned())),
+        "SECRET" => Ok(SignalsHeader::secret(value.trim().to_owned())),
+        "ORIGIN" => Ok(SignalsHeader::origin(value.trim().to_owned())),
+        "VIA" => Ok(SignalsHeader::via(
+          value.split(',')
+            .map(|v| v.trim().to_owned())
+            .filter(|v| !v.is_empty())
+            .collect()
+        )),
+        "REACTION" => Ok(SignalsHeader::reaction(value.trim().to_owned())),
+        "REMOVE" => Ok(SignalsHeader::remove),
+        "REACTIONS" => {
+          let mut reactions = Vec::new();
+          for item in value.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+            match item.rsplit_once('=').map(|(text, count)| (text, count.parse::<u32>())) {
+              Some((text, Ok(count))) if !text.is_empty() => reactions.push((text.to_owned(), count)),
+              _ => return Err(SignalError)
+            }
+          }
+          Ok(SignalsHeader::reactions(reactions))
+        },
+        "REACTED" => Ok(SignalsHeader::reacted(
+          value.split(',')
+            .map(|v| v.trim().to_owned())
+            .filter(|v| !v.is_empty())
+            .collect()
+        )),
+        "REPLY_TO" => Ok(SignalsHeader::replyTo(value.trim().to_owned())),
+        "THREAD" => Ok(SignalsHeader::thread(value.trim().to_owned())),
+        "TRANSFER" => Ok(SignalsHeader::transfer(value.trim().to_owned())),
+        "FILE_NAME" => Ok(SignalsHeader::fileName(value.trim().to_owned())),
+        "FILE_SIZE" => {
+          match value.trim().parse::<u64>() {
+            Ok(v) => Ok(SignalsHeader::fileSize(v)),
+            Err(_) => Err(SignalError)
+          }
+        },
+        "SHA256" => Ok(SignalsHeader::sha256(value.trim().to_owned())),
+        "PORT" => {
+          match value.trim().parse::<u16>() {
+            Ok(v) => Ok(SignalsHeader::port(v)),
+            Err(_) => Err(SignalError)
+          }
+        },
+        "ADDRESS" => Ok(SignalsHeader::address(value.trim().to_owned())),
+        "REJECTED" => Ok(SignalsHeader::rejected),
         "WITH_MESSAGE" => Ok(SignalsHeader::withMess),
         "SERVER_MESSAGE" => Ok(SignalsHeader::serverMess),
         _ => Err(SignalError)
@@ -109,8 +401,46 @@ impl ToString for SignalsHeader {
       match self {
         SignalsHeader::username(v) => format!("USERNAME: {v}\r\n"),
         SignalsHeader::key(v) => format!("KEY: {v}\r\n"),
+        SignalsHeader::id(v) => format!("MESSAGE_ID: {v}\r\n"),
         SignalsHeader::auth(v) => format!("AUTH_STATUS: {}\r\n", v.to_string()),
         SignalsHeader::signalType(v) => format!("SIGNAL_TYPE: {}\r\n", v.to_string()),
+        SignalsHeader::protocolVersion(v) => format!("PROTOCOL_VERSION: {v}\r\n"),
+        SignalsHeader::capabilities(v) => format!(
+          "CAPABILITIES: {}\r\n", 
+          v.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(",")
+        ),
+        SignalsHeader::serverName(v) => format!("SERVER_NAME: {v}\r\n"),
+        SignalsHeader::presence(v) => format!("PRESENCE: {}\r\n", v.to_string()),
+        SignalsHeader::timestamp(v) => format!("TIMESTAMP: {v}\r\n"),
+        SignalsHeader::sequence(v) => format!("SEQUENCE: {v}\r\n"),
+        SignalsHeader::author(v) => format!("AUTHOR: {v}\r\n"),
+        SignalsHeader::since(v) => format!("SINCE: {v}\r\n"),
+        SignalsHeader::until(v) => format!("UNTIL: {v}\r\n"),
+        SignalsHeader::results(v) => format!("RESULTS: {v}\r\n"),
+        SignalsHeader::recipient(v) => format!("TO: {v}\r\n"),
+        SignalsHeader::away => "AWAY\r\n".to_owned(),
+        SignalsHeader::target(v) => format!("TARGET: {v}\r\n"),
+        SignalsHeader::duration(v) => format!("DURATION: {v}\r\n"),
+        SignalsHeader::serverId(v) => format!("SERVER_ID: {v}\r\n"),
+        SignalsHeader::secret(v) => format!("SECRET: {v}\r\n"),
+        SignalsHeader::origin(v) => format!("ORIGIN: {v}\r\n"),
+        SignalsHeader::via(v) => format!("VIA: {}\r\n", v.join(",")),
+        SignalsHeader::reaction(v) => format!("REACTION: {v}\r\n"),
+        SignalsHeader::remove => "REMOVE\r\n".to_owned(),
+        SignalsHeader::reactions(v) => format!(
+          "REACTIONS: {}\r\n",
+          v.iter().map(|(text, count)| format!("{text}={count}")).collect::<Vec<_>>().join(",")
+        ),
+        SignalsHeader::reacted(v) => format!("REACTED: {}\r\n", v.join(",")),
+        SignalsHeader::replyTo(v) => format!("REPLY_TO: {v}\r\n"),
+        SignalsHeader::thread(v) => format!("THREAD: {v}\r\n"),
+        SignalsHeader::transfer(v) => format!("TRANSFER: {v}\r\n"),
+        SignalsHeader::fileName(v) => format!("FILE_NAME: {v}\r\n"),
+        SignalsHeader::fileSize(v) => format!("FILE_SIZE: {v}\r\n"),
+        SignalsHeader::sha256(v) => format!("SHA256: {v}\r\n"),
+        SignalsHeader::port(v) => format!("PORT: {v}\r\n"),
+        SignalsHeader::address(v) => format!("ADDRESS: {v}\r\n"),
+        SignalsHeader::rejected => "REJECTED\r\n".to_owned(),
         SignalsHeader::withMess => "WITH_MESSAGE\r\n".to_owned(),
         SignalsHeader::serverMess => "SERVER_MESSAGE\r\n".to_owned()
       }
@@ -122,8 +452,40 @@ impl ToString for SignalsHeader {
 pub struct SignalsData {
     pub username: Option<String>,
     pub key: Option<String>,
+    pub id: Option<String>,
     pub auth: Option<Authoritation>,
     pub signalType: Option<Signal>,
+    pub protocolVersion: Option<u16>,
+    pub capabilities: Option<Vec<Capability>>,
+    pub serverName: Option<String>,
+    pub presence: Option<Presence>,
+    pub timestamp: Option<u64>,
+    pub sequence: Option<u64>,
+    pub author: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub results: Option<u32>,
+    pub recipient: Option<String>,
+    pub away: bool,
+    pub target: Option<String>,
+    pub duration: Option<u64>,
+    pub serverId: Option<String>,
+    pub secret: Option<String>,
+    pub origin: Option<String>,
+    pub via: Option<Vec<String>>,
+    pub reaction: Option<String>,
+    pub remove: bool,
+    pub reactions: Option<Vec<(String, u32)>>,
+    pub reacted: Option<Vec<String>>,
+    pub replyTo: Option<String>,
+    pub thread: Option<String>,
+    pub transfer: Option<String>,
+    pub fileName: Option<String>,
+    pub fileSize: Option<u64>,
+    pub sha256: Option<String>,
+    pub port: Option<u16>,
+    pub address: Option<String>,
+    pub rejected: bool,
     pub withMess: bool,
     pub message: Option<String>,
     pub serverMess: bool
@@ -134,8 +496,40 @@ impl SignalsData {
       let mut data = SignalsData {
         username: None,
         key: None,
+        id: None,
         auth: None,
         signalType: None,
+        protocolVersion: None,
+        capabilities: None,
+        serverName: None,
+        presence: None,
+        timestamp: None,
+        sequence: None,
+        author: None,
+        since: None,
+        until: None,
+        results: None,
+        recipient: None,
+        away: false,
+        target: None,
+        duration: None,
+        serverId: None,
+        secret: None,
+        origin: None,
+        via: None,
+        reaction: None,
+        remove: false,
+        reactions: None,
+        reacted: None,
+        replyTo: None,
+        thread: None,
+        transfer: None,
+        fileName: None,
+        fileSize: None,
+        sha256: None,
+        port: None,
+        address: None,
+        rejected: false,
         withMess: false,
         message: None,
         serverMess: false
@@ -149,12 +543,108 @@ impl SignalsData {
           SignalsHeader::key(v) => {
             data.key = Some(v);
           },
+          SignalsHeader::id(v) => {
+            data.id = Some(v);
+          },
           SignalsHeader::auth(v) => {
             data.auth = Some(v);
           },
           SignalsHeader::signalType(v) => {
             data.signalType = Some(v);
           },
+          SignalsHeader::protocolVersion(v) => {
+            data.protocolVersion = Some(v);
+          },
+          SignalsHeader::capabilities(v) => {
+            data.capabilities = Some(v);
+          },
+          SignalsHeader::serverName(v) => {
+            data.serverName = Some(v);
+          },
+          SignalsHeader::presence(v) => {
+            data.presence = Some(v);
+          },
+          SignalsHeader::timestamp(v) => {
+            data.timestamp = Some(v);
+          },
+          SignalsHeader::sequence(v) => {
+            data.sequence = Some(v);
+          },
+          SignalsHeader::author(v) => {
+            data.author = Some(v);
+          },
+          SignalsHeader::since(v) => {
+            data.since = Some(v);
+          },
+          SignalsHeader::until(v) => {
+            data.until = Some(v);
+          },
+          SignalsHeader::results(v) => {
+            data.results = Some(v);
+          },
+          SignalsHeader::recipient(v) => {
+            data.recipient = Some(v);
+          },
+          SignalsHeader::away => {
+            data.away = true;
+          },
+          SignalsHeader::target(v) => {
+            data.target = Some(v);
+          },
+          SignalsHeader::duration(v) => {
+            data.duration = Some(v);
+          },
+          SignalsHeader::serverId(v) => {
+            data.serverId = Some(v);
+          },
+          SignalsHeader::secret(v) => {
+            data.secret = Some(v);
+          },
+          SignalsHeader::origin(v) => {
+            data.origin = Some(v);
+          },
+          SignalsHeader::via(v) => {
+            data.via = Some(v);
+          },
+          SignalsHeader::reaction(v) => {
+            data.reaction = Some(v);
+          },
+          SignalsHeader::remove => {
+            data.remove = true;
+          },
+          SignalsHeader::reactions(v) => {
+            data.reactions = Some(v);
+          },
+          SignalsHeader::reacted(v) => {
+            data.reacted = Some(v);
+          },
+          SignalsHeader::replyTo(v) => {
+            data.replyTo = Some(v);
+          },
+          SignalsHeader::thread(v) => {
+            data.thread = Some(v);
+          },
+          SignalsHeader::transfer(v) => {
+            data.transfer = Some(v);
+          },
+          SignalsHeader::fileName(v) => {
+            data.fileName = Some(v);
+          },
+          SignalsHeader::fileSize(v) => {
+            data.fileSize = Some(v);
+          },
+          SignalsHeader::sha256(v) => {
+            data.sha256 = Some(v);
+          },
+          SignalsHeader::port(v) => {
+            data.port = Some(v);
+          },
+          SignalsHeader::address(v) => {
+            data.address = Some(v);
+          },
+          SignalsHeader::rejected => {
+            data.rejected = true;
+          },
           SignalsHeader::withMess => {
             data.withMess = true;
             data.message = Some(message.unwrap_or("").to_owned());
@@ -167,6 +657,15 @@ impl SignalsData {
   
       data
     }
+
+    // version the peer speaks, peers without the header are legacy ones
+    pub fn peer_version(&self) -> u16 {
+      self.protocolVersion.unwrap_or(LEGACY_PROTOCOL_VERSION)
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+      self.capabilities.as_ref().is_some_and(|v| v.contains(&capability))
+    }
   }
   
   impl FromStr for SignalsData {
@@ -176,14 +675,48 @@ impl SignalsData {
       let mut data = SignalsData { 
         username: None, 
         key: None, 
+        id: None,
         auth: None, 
         signalType: None,
+        protocolVersion: None,
+        capabilities: None,
+        serverName: None,
+        presence: None,
+        timestamp: None,
+        sequence: None,
+        author: None,
+        since: None,
+        until: None,
+        results: None,
+        recipient: None,
+        away: false,
+        target: None,
+        duration: None,
+        serverId: None,
+        secret: None,
+        origin: None,
+        via: None,
+        reaction: None,
+        remove: false,
+        reactions: None,
+        reacted: None,
+        replyTo: None,
+        thread: None,
+        transfer: None,
+        fileName: None,
+        fileSize: None,
+        sha256: None,
+        port: None,
+        address: None,
+        rejected: false,
         withMess: false,
         message: None,
         serverMess: false,
       };
-      let splitted = s.split("\r\n");
-      for string in splitted {
+      // headers end at the first blank line, lines of the body are never
+      // read as headers, whatever they look like
+      let headers = s.split_once("\r\n\r\n").map_or(s, |v| v.0);
+      for string in headers.split("\r\n") {
         let header = match SignalsHeader::from_str(string) {
           Ok(v) => v,
           Err(_) => continue
@@ -196,12 +729,108 @@ impl SignalsData {
           SignalsHeader::key(v) => {
             data.key = Some(v);
           },
+          SignalsHeader::id(v) => {
+            data.id = Some(v);
+          },
           SignalsHeader::auth(v) => {
             data.auth = Some(v);
           },
           SignalsHeader::signalType(v) => {
             data.signalType = Some(v);
           }
+          SignalsHeader::protocolVersion(v) => {
+            data.protocolVersion = Some(v);
+          },
+          SignalsHeader::capabilities(v) => {
+            data.capabilities = Some(v);
+          },
+          SignalsHeader::serverName(v) => {
+            data.serverName = Some(v);
+          },
+          SignalsHeader::presence(v) => {
+            data.presence = Some(v);
+          },
+          SignalsHeader::timestamp(v) => {
+            data.timestamp = Some(v);
+          },
+          SignalsHeader::sequence(v) => {
+            data.sequence = Some(v);
+          },
+          SignalsHeader::author(v) => {
+            data.author = Some(v);
+          },
+          SignalsHeader::since(v) => {
+            data.since = Some(v);
+          },
+          SignalsHeader::until(v) => {
+            data.until = Some(v);
+          },
+          SignalsHeader::results(v) => {
+            data.results = Some(v);
+          },
+          SignalsHeader::recipient(v) => {
+            data.recipient = Some(v);
+          },
+          SignalsHeader::away => {
+            data.away = true;
+          },
+          SignalsHeader::target(v) => {
+            data.target = Some(v);
+          },
+          SignalsHeader::duration(v) => {
+            data.duration = Some(v);
+          },
+          SignalsHeader::serverId(v) => {
+            data.serverId = Some(v);
+          },
+          SignalsHeader::secret(v) => {
+            data.secret = Some(v);
+          },
+          SignalsHeader::origin(v) => {
+            data.origin = Some(v);
+          },
+          SignalsHeader::via(v) => {
+            data.via = Some(v);
+          },
+          SignalsHeader::reaction(v) => {
+            data.reaction = Some(v);
+          },
+          SignalsHeader::remove => {
+            data.remove = true;
+          },
+          SignalsHeader::reactions(v) => {
+            data.reactions = Some(v);
+          },
+          SignalsHeader::reacted(v) => {
+            data.reacted = Some(v);
+          },
+          SignalsHeader::replyTo(v) => {
+            data.replyTo = Some(v);
+          },
+          SignalsHeader::thread(v) => {
+            data.thread = Some(v);
+          },
+          SignalsHeader::transfer(v) => {
+            data.transfer = Some(v);
+          },
+          SignalsHeader::fileName(v) => {
+            data.fileName = Some(v);
+          },
+          SignalsHeader::fileSize(v) => {
+            data.fileSize = Some(v);
+          },
+          SignalsHeader::sha256(v) => {
+            data.sha256 = Some(v);
+          },
+          SignalsHeader::port(v) => {
+            data.port = Some(v);
+          },
+          SignalsHeader::address(v) => {
+            data.address = Some(v);
+          },
+          SignalsHeader::rejected => {
+            data.rejected = true;
+          },
           SignalsHeader::withMess => {
             data.withMess = true;
           },
@@ -245,12 +874,108 @@ impl SignalsData {
       if let Some(v) = &self.key {
         res_str.push_str(&SignalsHeader::key(v.to_owned()).to_string());
       }
+      if let Some(v) = &self.id {
+        res_str.push_str(&SignalsHeader::id(v.to_owned()).to_string());
+      }
       if let Some(v) = &self.auth {
         res_str.push_str(&SignalsHeader::auth(v.clone()).to_string());
       }
       if let Some(v) = &self.signalType {
         res_str.push_str(&SignalsHeader::signalType(v.clone()).to_string());
       }
+      if let Some(v) = &self.protocolVersion {
+        res_str.push_str(&SignalsHeader::protocolVersion(*v).to_string());
+      }
+      if let Some(v) = &self.capabilities {
+        res_str.push_str(&SignalsHeader::capabilities(v.clone()).to_string());
+      }
+      if let Some(v) = &self.serverName {
+        res_str.push_str(&SignalsHeader::serverName(v.to_owned()).to_string());
+      }
+      if let Some(v) = &self.presence {
+        res_str.push_str(&SignalsHeader::presence(*v).to_string());
+      }
+      if let Some(v) = &self.timestamp {
+        res_str.push_str(&SignalsHeader::timestamp(*v).to_string());
+      }
+      if let Some(v) = &self.sequence {
+        res_str.push_str(&SignalsHeader::sequence(*v).to_string());
+      }
+      if let Some(v) = &self.author {
+        res_str.push_str(&SignalsHeader::author(v.to_owned()).to_string());
+      }
+      if let Some(v) = &self.since {
+        res_str.push_str(&SignalsHeader::since(*v).to_string());
+      }
+      if let Some(v) = &self.until {
+        res_str.push_str(&SignalsHeader::until(*v).to_string());
+      }
+      if let Some(v) = &self.results {
+        res_str.push_str(&SignalsHeader::results(*v).to_string());
+      }
+      if let Some(v) = &self.recipient {
+        res_str.push_str(&SignalsHeader::recipient(v.to_owned()).to_string());
+      }
+      if self.away {
+        res_str.push_str(&SignalsHeader::away.to_string());
+      }
+      if let Some(v) = &self.target {
+        res_str.push_str(&SignalsHeader::target(v.to_owned()).to_string());
+      }
+      if let Some(v) = &self.duration {
+        res_str.push_str(&SignalsHeader::duration(*v).to_string());
+      }
+      if let Some(v) = &self.serverId {
+        res_str.push_str(&SignalsHeader::serverId(v.to_owned()).to_string());
+      }
+      if let Some(v) = &self.secret {
+        res_str.push_str(&SignalsHeader::secret(v.to_owned()).to_string());
+      }
+      if let Some(v) = &self.origin {
+        res_str.push_str(&SignalsHeader::origin(v.to_owned()).to_string());
+      }
+      if let Some(v) = &self.via {
+        res_str.push_str(&SignalsHeader::via(v.clone()).to_string());
+      }
+      if let Some(v) = &self.reaction {
+        res_str.push_str(&SignalsHeader::reaction(v.to_owned()).to_string());
+      }
+      if self.remove {
+        res_str.push_str(&SignalsHeader::remove.to_string());
+      }
+      if let Some(v) = &self.reactions {
+        res_str.push_str(&SignalsHeader::reactions(v.clone()).to_string());
+      }
+      if let Some(v) = &self.reacted {
+        res_str.push_str(&SignalsHeader::reacted(v.clone()).to_string());
+      }
+      if let Some(v) = &self.replyTo {
+        res_str.push_str(&SignalsHeader::replyTo(v.to_owned()).to_string());
+      }
+      if let Some(v) = &self.thread {
+        res_str.push_str(&SignalsHeader::thread(v.to_owned()).to_string());
+      }
+      if let Some(v) = &self.transfer {
+        res_str.push_str(&SignalsHeader::transfer(v.to_owned()).to_string());
+      }
+      if let Some(v) = &self.fileName {
+        res_str.push_str(&SignalsHeader::fileName(v.to_owned()).to_string());
+      }
+      if let Some(v) = &self.fileSize {
+        res_str.push_str(&SignalsHeader::fileSize(*v).to_string());
+      }
+      if let Some(v) = &self.sha256 {
+        res_str.push_str(&SignalsHeader::sha256(v.to_owned()).to_string());
+      }
+      if let Some(v) = &self.port {
+        res_str.push_str(&SignalsHeader::port(*v).to_string());
+      }
+      if let Some(v) = &self.address {
+        res_str.push_str(&SignalsHeader::address(v.to_owned()).to_string());
+      }
+      if self.rejected {
+        res_str.push_str(&SignalsHeader::rejected.to_string());
+      }
       if self.serverMess {
         res_str.push_str(&SignalsHeader::serverMess.to_string());
       }
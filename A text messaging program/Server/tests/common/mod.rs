@@ -0,0 +1,174 @@
+#![allow(dead_code)]
+
+use std::{
+    env,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant}
+  };
+use server::RateLimits;
+
+static NEXT_CONFIG: AtomicUsize = AtomicUsize::new(0);
+
+// server binary running on a free port of the loopback interface
+pub struct TestServer {
+  child: Child,
+  pub port: u16,
+  config: PathBuf,
+}
+
+impl TestServer {
+  pub fn start(config: &str) -> TestServer {
+    let path = env::temp_dir().join(format!(
+      "chat-server-test-{}-{}.toml",
+      std::process::id(),
+      NEXT_CONFIG.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::write(&path, config).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_server"))
+      .args(["--port", "0", "--bind", "127.0.0.1", "--log-level", "off", "--config"])
+      .arg(&path)
+      .stdout(Stdio::piped())
+      .stderr(Stdio::null())
+      .spawn()
+      .unwrap();
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+    let port = line.trim().rsplit(':').next().unwrap().parse().unwrap();
+
+    TestServer { child, port, config: path }
+  }
+
+  pub fn connect(&self) -> TcpStream {
+    connect(SocketAddr::from(([127, 0, 0, 1], self.port)))
+  }
+
+  pub fn join(&self, username: &str) -> TcpStream {
+    join(SocketAddr::from(([127, 0, 0, 1], self.port)), username)
+  }
+
+  // rewrites the config file and asks the server to reread it
+  pub fn reload(&self, config: &str) {
+    fs::write(&self.config, config).unwrap();
+    let status = Command::new("kill").args(["-HUP", &self.child.id().to_string()]).status().unwrap();
+    assert!(status.success(), "SIGHUP was not sent");
+  }
+}
+
+impl Drop for TestServer {
+  fn drop(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+    let _ = fs::remove_file(&self.config);
+  }
+}
+
+// for tests sending more than flood protection lets through
+pub fn no_rate_limits() -> RateLimits {
+  RateLimits {
+    messages_per_sec: 0,
+    bytes_per_sec: 0,
+    ip_messages_per_sec: 0,
+    ip_bytes_per_sec: 0,
+    burst_secs: 1,
+    warnings_before_mute: 1,
+    mute_secs: 1,
+    mutes_before_disconnect: 0,
+  }
+}
+
+pub fn connect(address: SocketAddr) -> TcpStream {
+  let stream = TcpStream::connect(address).unwrap();
+  stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+  stream
+}
+
+// connects and passes the handshake
+pub fn join(address: SocketAddr, username: &str) -> TcpStream {
+  let mut stream = connect(address);
+  stream.write_all(&handshake(username)).unwrap();
+  let response = read_for(&mut stream, Duration::from_millis(300));
+  assert!(response.contains("AUTH_STATUS: ACCEPTED"), "{username} was not accepted: {response}");
+  stream
+}
+
+// like 'join', for owners, moderators and admins
+pub fn join_with_token(address: SocketAddr, username: &str, token: &str) -> TcpStream {
+  let mut stream = connect(address);
+  stream.write_all(&handshake_with_token(username, token)).unwrap();
+  let response = read_for(&mut stream, Duration::from_millis(300));
+  assert!(response.contains("AUTH_STATUS: ACCEPTED"), "{username} was not accepted: {response}");
+  stream
+}
+
+// connects and sends the handshake with the given capabilities, the
+// answer is left for the caller
+pub fn join_with(address: SocketAddr, username: &str, capabilities: &str) -> TcpStream {
+  let mut stream = connect(address);
+  let handshake = format!(
+    "SIGNAL_TYPE: CONNECTION\r\nUSERNAME: {username}\r\nPROTOCOL_VERSION: 1\r\nCAPABILITIES: {capabilities}\r\n\r\n\r\n"
+  );
+  stream.write_all(handshake.as_bytes()).unwrap();
+  stream
+}
+
+pub fn handshake_with_token(username: &str, token: &str) -> Vec<u8> {
+  format!("SIGNAL_TYPE: CONNECTION\r\nUSERNAME: {username}\r\nSECRET: {token}\r\nPROTOCOL_VERSION: 1\r\nCAPABILITIES: MESSAGE_IDS,WARNINGS\r\n\r\n\r\n")
+    .into_bytes()
+}
+
+pub fn handshake(username: &str) -> Vec<u8> {
+  format!("SIGNAL_TYPE: CONNECTION\r\nUSERNAME: {username}\r\nPROTOCOL_VERSION: 1\r\nCAPABILITIES: MESSAGE_IDS,WARNINGS\r\n\r\n\r\n")
+    .into_bytes()
+}
+
+pub fn message(username: &str, text: &str) -> Vec<u8> {
+  format!("USERNAME: {username}\r\nSIGNAL_TYPE: MESSAGE\r\nWITH_MESSAGE\r\n\r\n{text}\r\n\r\n").into_bytes()
+}
+
+// headers of the message with the given text
+pub fn headers_of(received: &str, text: &str) -> String {
+  let parts: Vec<&str> = received.split("\r\n\r\n").collect();
+  let index = parts.iter().position(|v| *v == text).unwrap_or_else(|| panic!("no {text} in {received}"));
+  parts[index - 1].to_owned()
+}
+
+pub fn id_of(received: &str, text: &str) -> String {
+  headers_of(received, text).lines().find_map(|v| v.strip_prefix("MESSAGE_ID: ")).unwrap().to_owned()
+}
+
+// everything the server sent within the given time
+pub fn read_for(stream: &mut TcpStream, duration: Duration) -> String {
+  let start = Instant::now();
+  let mut received = Vec::new();
+  let mut buf = [0u8; 4096];
+  while start.elapsed() < duration {
+    match stream.read(&mut buf) {
+      Ok(0) => break,
+      Ok(v) => received.extend_from_slice(&buf[..v]),
+      Err(_) => continue,
+    }
+  }
+  String::from_utf8_lossy(&received).to_string()
+}
+
+// true if the server closed the connection within the given time
+pub fn is_closed_within(stream: &mut TcpStream, duration: Duration) -> bool {
+  let start = Instant::now();
+  let mut buf = [0u8; 4096];
+  while start.elapsed() < duration {
+    match stream.read(&mut buf) {
+      Ok(0) => return true,
+      Ok(_) => continue,
+      Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => continue,
+      Err(_) => return true,
+    }
+  }
+  false
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use std::{io::Write, time::Duration};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::{write::DeflateEncoder, Compression};
+use server::ChatServer;
+
+use common::{join, join_with, message, no_rate_limits, read_for};
+
+const WAIT: Duration = Duration::from_millis(500);
+
+fn compressed(text: &str) -> Vec<u8> {
+  let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+  encoder.write_all(text.as_bytes()).unwrap();
+  let body = STANDARD.encode(encoder.finish().unwrap());
+  format!("COMPRESSION: DEFLATE\r\nSIGNAL_TYPE: MESSAGE\r\nWITH_MESSAGE\r\n\r\n{body}\r\n\r\n").into_bytes()
+}
+
+// a log paste, the kind of message compression is for
+fn paste(index: usize) -> String {
+  (0..40)
+    .map(|v| format!("[{index:03}:{v:02}] INFO worker-{} finished job in {} ms", v % 4, v * 7))
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+#[test]
+fn history_replay_is_smaller_with_deflate() {
+  let server = ChatServer::builder().rate_limits(no_rate_limits()).start().unwrap();
+  let mut alice = join(server.local_addr(), "alice");
+  for index in 0..20 {
+    alice.write_all(&message("alice", &paste(index))).unwrap();
+  }
+  read_for(&mut alice, WAIT);
+
+  let mut plain = join_with(server.local_addr(), "plain", "MESSAGE_IDS,WARNINGS");
+  let plain_replay = read_for(&mut plain, WAIT);
+  let mut deflate = join_with(server.local_addr(), "deflate", "MESSAGE_IDS,WARNINGS,DEFLATE");
+  let deflate_replay = read_for(&mut deflate, WAIT);
+
+  assert!(plain_replay.contains(&paste(19)), "history was not replayed");
+  assert!(!plain_replay.contains("COMPRESSION: DEFLATE"));
+  assert_eq!(deflate_replay.matches("COMPRESSION: DEFLATE").count(), 20, "{deflate_replay}");
+  println!("history replay: {} bytes plain, {} bytes deflated", plain_replay.len(), deflate_replay.len());
+  assert!(
+    deflate_replay.len() * 3 < plain_replay.len(),
+    "{} bytes deflated is not much less than {} bytes plain",
+    deflate_replay.len(),
+    plain_replay.len()
+  );
+
+  server.shutdown();
+}
+
+#[test]
+fn compressed_messages_reach_everyone_as_text() {
+  let server = ChatServer::builder().rate_limits(no_rate_limits()).start().unwrap();
+  let mut alice = join_with(server.local_addr(), "alice", "MESSAGE_IDS,WARNINGS,DEFLATE");
+  let mut bob = join(server.local_addr(), "bob");
+  read_for(&mut alice, WAIT);
+  read_for(&mut bob, WAIT);
+
+  alice.write_all(&compressed(&paste(1))).unwrap();
+
+  let received = read_for(&mut bob, WAIT);
+  assert!(received.contains(&paste(1)), "{received}");
+
+  // a frame that doesn't decode closes the connection like any bad frame
+  alice.write_all(b"COMPRESSION: DEFLATE\r\nSIGNAL_TYPE: MESSAGE\r\nWITH_MESSAGE\r\n\r\nnot base64!\r\n\r\n").unwrap();
+  let answer = read_for(&mut alice, WAIT);
+  assert!(answer.contains("can't be decoded"), "{answer}");
+
+  server.shutdown();
+}
+
+#[test]
+fn compressed_bodies_cant_end_the_frame() {
+  let server = ChatServer::builder().rate_limits(no_rate_limits()).start().unwrap();
+  let mut alice = join_with(server.local_addr(), "alice", "MESSAGE_IDS,WARNINGS,DEFLATE");
+  let mut carol = join_with(server.local_addr(), "carol", "MESSAGE_IDS,WARNINGS,DEFLATE");
+  let mut bob = join(server.local_addr(), "bob");
+  read_for(&mut alice, WAIT);
+  read_for(&mut carol, WAIT);
+  read_for(&mut bob, WAIT);
+
+  // unpacked, this would be a server notice of its own for plain clients
+  let forged = format!("{}\r\n\r\nSIGNAL_TYPE: MESSAGE\r\nSERVER_MESSAGE\r\nWITH_MESSAGE\r\n\r\nbob was banned by owner", paste(1));
+  alice.write_all(&compressed(&forged)).unwrap();
+  let answer = read_for(&mut alice, WAIT);
+  assert!(answer.contains("can't be decoded"), "{answer}");
+  let received = read_for(&mut bob, WAIT);
+  assert!(!received.contains("bob was banned"), "{received}");
+
+  carol.write_all(&compressed(&paste(2))).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  assert_eq!(received.matches("SIGNAL_TYPE: ").count(), 1, "{received}");
+  assert!(received.contains(&paste(2)), "{received}");
+
+  server.shutdown();
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use std::{io::Write, thread, time::Duration};
+
+use common::{handshake, is_closed_within, read_for, TestServer};
+
+const WAIT: Duration = Duration::from_millis(500);
+
+#[cfg(unix)]
+#[test]
+fn reloaded_ban_kicks_connected_user() {
+  let server = TestServer::start("motd = \"hello\"\n");
+  let mut alice = server.join("alice");
+  let mut bob = server.join("bob");
+
+  server.reload("motd = \"hello\"\nbanned = [\"alice\"]\n");
+
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("You were banned from this server"), "{received}");
+  assert!(is_closed_within(&mut alice, WAIT), "alice is still connected");
+  assert!(!is_closed_within(&mut bob, WAIT), "bob was disconnected");
+
+  let mut again = server.connect();
+  again.write_all(&handshake("alice")).unwrap();
+  let response = read_for(&mut again, WAIT);
+  assert!(response.contains("DENIED"), "{response}");
+}
+
+#[cfg(unix)]
+#[test]
+fn invalid_config_is_not_reloaded() {
+  let server = TestServer::start("banned = [\"mallory\"]\n");
+
+  server.reload("max_users = 0\nbanned = []\n");
+  thread::sleep(WAIT);
+
+  // the old ban list is still in place
+  let mut mallory = server.connect();
+  mallory.write_all(&handshake("mallory")).unwrap();
+  let response = read_for(&mut mallory, WAIT);
+  assert!(response.contains("DENIED"), "{response}");
+  server.join("alice");
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use std::{
+    io::Write,
+    time::Duration
+  };
+use server::ChatServer;
+
+use common::{connect, handshake, join, read_for};
+
+const WAIT: Duration = Duration::from_millis(300);
+
+fn direct(to: &str, text: &str) -> Vec<u8> {
+  format!("SIGNAL_TYPE: MESSAGE\r\nTO: {to}\r\nWITH_MESSAGE\r\n\r\n{text}\r\n\r\n").into_bytes()
+}
+
+#[test]
+fn direct_messages_reach_only_the_recipient() {
+  let server = ChatServer::builder().start().unwrap();
+  let mut alice = join(server.local_addr(), "alice");
+  let mut bob = join(server.local_addr(), "bob");
+  let mut carol = join(server.local_addr(), "carol");
+  read_for(&mut alice, WAIT);
+  read_for(&mut bob, WAIT);
+  read_for(&mut carol, WAIT);
+
+  alice.write_all(&direct("bob", "just for you")).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  assert!(received.contains("just for you"), "{received}");
+  assert!(received.contains("USERNAME: alice"), "{received}");
+  assert!(received.contains("TO: bob"), "{received}");
+  assert!(!received.contains("AWAY"), "{received}");
+
+  let echo = read_for(&mut alice, WAIT);
+  assert!(echo.contains("just for you"), "{echo}");
+  let received = read_for(&mut carol, WAIT);
+  assert!(!received.contains("just for you"), "{received}");
+
+  server.shutdown();
+}
+
+#[test]
+fn offline_users_get_messages_on_login() {
+  let server = ChatServer::builder().mailbox_size(1).start().unwrap();
+  drop(join(server.local_addr(), "alice"));
+  let mut bob = join(server.local_addr(), "bob");
+  read_for(&mut bob, WAIT);
+
+  bob.write_all(&direct("alice", "see you tomorrow")).unwrap();
+  let echo = read_for(&mut bob, WAIT);
+  assert!(echo.contains("see you tomorrow"), "{echo}");
+
+  bob.write_all(&direct("alice", "one more")).unwrap();
+  let answer = read_for(&mut bob, WAIT);
+  assert!(answer.contains("mailbox of alice is full"), "{answer}");
+
+  bob.write_all(&direct("nobody", "hello?")).unwrap();
+  let answer = read_for(&mut bob, WAIT);
+  assert!(answer.contains("nobody is not a known user"), "{answer}");
+
+  // the mailbox comes right after the handshake answer
+  let mut alice = connect(server.local_addr());
+  alice.write_all(&handshake("alice")).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("AUTH_STATUS: ACCEPTED"), "{received}");
+  assert!(received.contains("see you tomorrow"), "{received}");
+  assert!(received.contains("AWAY"), "{received}");
+  assert!(!received.contains("one more"), "{received}");
+
+  // delivered once only
+  drop(alice);
+  read_for(&mut bob, WAIT);
+  let mut alice = connect(server.local_addr());
+  alice.write_all(&handshake("alice")).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("AUTH_STATUS: ACCEPTED"), "{received}");
+  assert!(!received.contains("see you tomorrow"), "{received}");
+
+  server.shutdown();
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use std::{
+    io::Write,
+    net::TcpStream,
+    time::Duration
+  };
+use server::ChatServer;
+
+use common::{connect, handshake, is_closed_within, join, message, read_for};
+
+const WAIT: Duration = Duration::from_millis(300);
+
+#[test]
+fn users_join_chat_and_leave() {
+  let server = ChatServer::builder().start().unwrap();
+  let address = server.local_addr();
+  assert_ne!(address.port(), 0);
+
+  let mut alice = join(address, "alice");
+  let mut bob = join(address, "bob");
+  let carol = join(address, "carol");
+  assert_eq!(server.users(), ["alice", "bob", "carol"]);
+
+  read_for(&mut alice, WAIT);
+  read_for(&mut bob, WAIT);
+  alice.write_all(&message("alice", "hello everyone")).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  assert!(received.contains("USERNAME: alice"), "{received}");
+  assert!(received.contains("hello everyone"), "{received}");
+
+  drop(carol);
+  let received = read_for(&mut bob, WAIT);
+  assert!(received.contains("carol left the chat!"), "{received}");
+  assert!(received.contains("PRESENCE: LEFT"), "{received}");
+  assert_eq!(server.users(), ["alice", "bob"]);
+
+  server.shutdown();
+}
+
+#[test]
+fn builder_settings_are_applied() {
+  let server = ChatServer::builder()
+    .name("embedded")
+    .motd("welcome")
+    .max_users(1)
+    .start()
+    .unwrap();
+
+  let mut first = connect(server.local_addr());
+  first.write_all(&handshake("first")).unwrap();
+  let response = read_for(&mut first, WAIT);
+  assert!(response.contains("SERVER_NAME: embedded"), "{response}");
+  assert!(response.contains("welcome"), "{response}");
+
+  let mut second = connect(server.local_addr());
+  second.write_all(&handshake("second")).unwrap();
+  let response = read_for(&mut second, WAIT);
+  assert!(response.contains("AUTH_STATUS: DENIED"), "{response}");
+
+  server.shutdown();
+}
+
+#[test]
+fn servers_run_side_by_side() {
+  let first = ChatServer::builder().start().unwrap();
+  let second = ChatServer::builder().start().unwrap();
+  assert_ne!(first.local_addr(), second.local_addr());
+
+  let _alice = join(first.local_addr(), "alice");
+  let _bob = join(second.local_addr(), "bob");
+  assert_eq!(first.users(), ["alice"]);
+  assert_eq!(second.users(), ["bob"]);
+
+  first.shutdown();
+  second.shutdown();
+}
+
+#[test]
+fn shutdown_disconnects_everyone() {
+  let server = ChatServer::builder().start().unwrap();
+  let address = server.local_addr();
+  let mut alice = join(address, "alice");
+  let mut bob = join(address, "bob");
+
+  server.shutdown();
+
+  assert!(is_closed_within(&mut alice, Duration::from_secs(1)));
+  assert!(is_closed_within(&mut bob, Duration::from_secs(1)));
+  assert!(TcpStream::connect(address).is_err());
+}
@@ -0,0 +1,250 @@
+mod common;
+
+use std::{
+    io::Write,
+    net::SocketAddr,
+    thread,
+    time::{Duration, Instant}
+  };
+use server::{ChatServer, ServerHandle};
+
+use common::{connect, handshake, join, message, read_for};
+
+const WAIT: Duration = Duration::from_millis(500);
+const LINK_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn wait_for_remote_users(server: &ServerHandle, expected: &[&str]) {
+  let deadline = Instant::now() + LINK_TIMEOUT;
+  while server.remote_users() != expected {
+    assert!(Instant::now() < deadline, "remote users are {:?}, expected {expected:?}", server.remote_users());
+    thread::sleep(Duration::from_millis(20));
+  }
+}
+
+#[test]
+fn linked_servers_share_messages_and_presence() {
+  let a = ChatServer::builder()
+    .federation(true)
+    .server_id("a")
+    .link_allow(vec!["b".to_owned(), "c".to_owned()])
+    .start()
+    .unwrap();
+  let mut alice = join(a.local_addr(), "alice");
+  let b = ChatServer::builder()
+    .federation(true)
+    .server_id("b")
+    .links(vec![a.local_addr().to_string()])
+    .start()
+    .unwrap();
+  let mut bob = join(b.local_addr(), "bob");
+
+  wait_for_remote_users(&a, &["bob@b"]);
+  wait_for_remote_users(&b, &["alice@a"]);
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("bob@b joined the chat!"), "{received}");
+  read_for(&mut bob, WAIT);
+
+  alice.write_all(&message("alice", "hello from a")).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  assert!(received.contains("USERNAME: alice@a"), "{received}");
+  assert!(received.contains("hello from a"), "{received}");
+
+  bob.write_all(&message("bob", "hello from b")).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("USERNAME: bob@b"), "{received}");
+  assert!(received.contains("hello from b"), "{received}");
+
+  drop(bob);
+  wait_for_remote_users(&a, &[]);
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("bob@b left the chat!"), "{received}");
+
+  // users of a server that goes away leave with it
+  b.shutdown();
+  let mut carol = join(a.local_addr(), "carol");
+  read_for(&mut carol, WAIT);
+  let c = ChatServer::builder()
+    .federation(true)
+    .server_id("c")
+    .links(vec![a.local_addr().to_string()])
+    .start()
+    .unwrap();
+  wait_for_remote_users(&c, &["alice@a", "carol@a"]);
+  c.shutdown();
+  wait_for_remote_users(&a, &[]);
+
+  a.shutdown();
+}
+
+#[test]
+fn messages_go_around_a_ring_of_links_once() {
+  let a = ChatServer::builder()
+    .federation(true)
+    .server_id("a")
+    .link_allow(vec!["b".to_owned(), "c".to_owned()])
+    .start()
+    .unwrap();
+  let b = ChatServer::builder()
+    .federation(true)
+    .server_id("b")
+    .link_allow(vec!["c".to_owned()])
+    .links(vec![a.local_addr().to_string()])
+    .start()
+    .unwrap();
+  let c = ChatServer::builder()
+    .federation(true)
+    .server_id("c")
+    .links(vec![a.local_addr().to_string(), b.local_addr().to_string()])
+    .start()
+    .unwrap();
+
+  let mut alice = join(a.local_addr(), "alice");
+  let mut bob = join(b.local_addr(), "bob");
+  let mut carol = join(c.local_addr(), "carol");
+  wait_for_remote_users(&a, &["bob@b", "carol@c"]);
+  wait_for_remote_users(&b, &["alice@a", "carol@c"]);
+  wait_for_remote_users(&c, &["alice@a", "bob@b"]);
+  read_for(&mut alice, WAIT);
+  read_for(&mut bob, WAIT);
+  read_for(&mut carol, WAIT);
+
+  alice.write_all(&message("alice", "around the ring")).unwrap();
+  for (name, stream) in [("alice", &mut alice), ("bob", &mut bob), ("carol", &mut carol)] {
+    let received = read_for(stream, WAIT);
+    assert_eq!(received.matches("around the ring").count(), 1, "{name} got: {received}");
+  }
+
+  a.shutdown();
+  b.shutdown();
+  c.shutdown();
+}
+
+#[test]
+fn links_need_the_secret_and_honest_origins() {
+  let a = ChatServer::builder().federation(true).server_id("a").link_secret("swordfish").start().unwrap();
+  let b = ChatServer::builder()
+    .federation(true)
+    .server_id("b")
+    .link_secret("password")
+    .links(vec![a.local_addr().to_string()])
+    .start()
+    .unwrap();
+  let mut alice = join(a.local_addr(), "alice");
+  read_for(&mut alice, WAIT);
+  assert!(b.remote_users().is_empty());
+  assert!(a.remote_users().is_empty());
+  b.shutdown();
+
+  // a linked server can't speak for users of this one
+  let mut link = connect(a.local_addr());
+  link.write_all(b"SIGNAL_TYPE: LINK\r\nPROTOCOL_VERSION: 1\r\nSERVER_ID: evil\r\nSECRET: swordfish\r\n\r\n\r\n").unwrap();
+  let answer = read_for(&mut link, WAIT);
+  assert!(answer.contains("AUTH_STATUS: ACCEPTED"), "{answer}");
+  assert!(answer.contains("SERVER_ID: a"), "{answer}");
+  link.write_all(
+    b"SIGNAL_TYPE: MESSAGE\r\nMESSAGE_ID: 1\r\nUSERNAME: alice@a\r\nORIGIN: a\r\nVIA: a,evil\r\nWITH_MESSAGE\r\n\r\nforged\r\n\r\n"
+  ).unwrap();
+  link.write_all(
+    b"SIGNAL_TYPE: MESSAGE\r\nMESSAGE_ID: 2\r\nUSERNAME: mallory@evil\r\nORIGIN: evil\r\nVIA: evil\r\nWITH_MESSAGE\r\n\r\nhonest\r\n\r\n"
+  ).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(!received.contains("forged"), "{received}");
+  assert!(received.contains("USERNAME: mallory@evil"), "{received}");
+
+  // nor name users of a server other than the origin
+  link.write_all(
+    b"SIGNAL_TYPE: MESSAGE\r\nMESSAGE_ID: 3\r\nUSERNAME: bob@b\r\nORIGIN: evil\r\nVIA: evil\r\nWITH_MESSAGE\r\n\r\nforged\r\n\r\n"
+  ).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(!received.contains("forged"), "{received}");
+  // the link is closed for it
+  let _ = link.write_all(
+    b"SIGNAL_TYPE: MESSAGE\r\nMESSAGE_ID: 4\r\nUSERNAME: mallory@evil\r\nORIGIN: evil\r\nVIA: evil\r\nWITH_MESSAGE\r\n\r\nstill here\r\n\r\n"
+  );
+  let received = read_for(&mut alice, WAIT);
+  assert!(!received.contains("still here"), "{received}");
+
+  // and '@' in usernames is left for them
+  let mut fake = connect(a.local_addr());
+  fake.write_all(&handshake("mallory@b")).unwrap();
+  let answer = read_for(&mut fake, WAIT);
+  assert!(answer.contains("AUTH_STATUS: DENIED"), "{answer}");
+
+  a.shutdown();
+}
+
+fn link(address: SocketAddr, server_id: &str, secret: Option<&str>) -> String {
+  let mut stream = connect(address);
+  let secret = secret.map(|v| format!("SECRET: {v}\r\n")).unwrap_or_default();
+  let frame = format!("SIGNAL_TYPE: LINK\r\nPROTOCOL_VERSION: 1\r\nSERVER_ID: {server_id}\r\n{secret}\r\n\r\n");
+  stream.write_all(frame.as_bytes()).unwrap();
+  read_for(&mut stream, WAIT)
+}
+
+#[test]
+fn links_are_refused_unless_federation_is_on() {
+  let a = ChatServer::builder().server_id("a").link_secret("swordfish").start().unwrap();
+  let answer = link(a.local_addr(), "b", Some("swordfish"));
+  assert!(answer.contains("AUTH_STATUS: DENIED"), "{answer}");
+
+  // nor made
+  let b = ChatServer::builder()
+    .server_id("b")
+    .link_secret("swordfish")
+    .links(vec![a.local_addr().to_string()])
+    .start()
+    .unwrap();
+  thread::sleep(WAIT);
+  assert!(a.remote_users().is_empty());
+  assert!(b.remote_users().is_empty());
+
+  a.shutdown();
+  b.shutdown();
+}
+
+#[test]
+fn links_need_a_secret_or_an_allowed_server() {
+  let open = ChatServer::builder().federation(true).server_id("a").start().unwrap();
+  let answer = link(open.local_addr(), "b", None);
+  assert!(answer.contains("AUTH_STATUS: DENIED"), "{answer}");
+  open.shutdown();
+
+  let a = ChatServer::builder()
+    .federation(true)
+    .server_id("a")
+    .link_allow(vec!["b".to_owned()])
+    .start()
+    .unwrap();
+  let answer = link(a.local_addr(), "evil", None);
+  assert!(answer.contains("AUTH_STATUS: DENIED"), "{answer}");
+  let answer = link(a.local_addr(), "b", None);
+  assert!(answer.contains("AUTH_STATUS: ACCEPTED"), "{answer}");
+  a.shutdown();
+
+  // by address, IPv4 peers of dual-stack listeners included
+  let a = ChatServer::builder()
+    .federation(true)
+    .server_id("a")
+    .link_allow(vec!["127.0.0.1".to_owned()])
+    .start()
+    .unwrap();
+  let answer = link(SocketAddr::from(([127, 0, 0, 1], a.local_addr().port())), "b", None);
+  assert!(answer.contains("AUTH_STATUS: ACCEPTED"), "{answer}");
+  a.shutdown();
+
+  // both have to match when both are set
+  let a = ChatServer::builder()
+    .federation(true)
+    .server_id("a")
+    .link_secret("swordfish")
+    .link_allow(vec!["b".to_owned()])
+    .start()
+    .unwrap();
+  let answer = link(a.local_addr(), "b", Some("password"));
+  assert!(answer.contains("AUTH_STATUS: DENIED"), "{answer}");
+  let answer = link(a.local_addr(), "c", Some("swordfish"));
+  assert!(answer.contains("AUTH_STATUS: DENIED"), "{answer}");
+  let answer = link(a.local_addr(), "b", Some("swordfish"));
+  assert!(answer.contains("AUTH_STATUS: ACCEPTED"), "{answer}");
+  a.shutdown();
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::{io::Write, time::Duration};
+use server::ChatServer;
+
+use common::{connect, handshake, join, message, no_rate_limits, read_for};
+
+const WAIT: Duration = Duration::from_millis(500);
+
+fn sequences(received: &str) -> Vec<u64> {
+  received.lines().filter_map(|v| v.strip_prefix("SEQUENCE: ")).map(|v| v.parse().unwrap()).collect()
+}
+
+#[test]
+fn only_the_last_messages_are_kept_and_replayed() {
+  let server = ChatServer::builder().history(3).start().unwrap();
+  let mut alice = join(server.local_addr(), "alice");
+  for index in 1..=5 {
+    alice.write_all(&message("alice", &format!("message {index}"))).unwrap();
+  }
+  read_for(&mut alice, WAIT);
+
+  let mut bob = connect(server.local_addr());
+  bob.write_all(&handshake("bob")).unwrap();
+  let replay = read_for(&mut bob, WAIT);
+  assert!(!replay.contains("message 3"), "{replay}");
+  assert_eq!(replay.matches("message 4").count(), 1, "{replay}");
+  assert_eq!(replay.matches("message 5").count(), 1, "{replay}");
+  assert_eq!(sequences(&replay), vec![5, 6, 7], "{replay}");
+
+  // a burst bigger than the history comes once and in order; whether
+  // bob's connection reads it in time is up to the scheduler, if not,
+  // the messages pushed out are counted as missed
+  for index in 6..=9 {
+    alice.write_all(&message("alice", &format!("message {index}"))).unwrap();
+  }
+  let received = read_for(&mut bob, WAIT);
+  let received_sequences = sequences(&received);
+  let missed = received.lines()
+    .find_map(|v| v.strip_suffix(" messages were missed"))
+    .map_or(0, |v| v.parse::<usize>().unwrap());
+  assert!(received_sequences.windows(2).all(|v| v[0] + 1 == v[1]), "{received}");
+  assert_eq!(received_sequences.last(), Some(&11), "{received}");
+  assert_eq!(received_sequences.len() + missed, 4, "{received}");
+
+  server.shutdown();
+}
+
+#[test]
+fn a_reader_that_fell_behind_gets_a_gap() {
+  let server = ChatServer::builder().history(4).rate_limits(no_rate_limits()).start().unwrap();
+  let mut alice = join(server.local_addr(), "alice");
+  let mut bob = join(server.local_addr(), "bob");
+  read_for(&mut alice, WAIT);
+
+  // bob doesn't read, so his connection is stuck on a full socket
+  // while the history moves on
+  let padding = "x".repeat(60 * 1024 - 16);
+  let writer = std::thread::spawn(move || {
+    for index in 0..400 {
+      alice.write_all(&message("alice", &format!("{index:04} {padding}"))).unwrap();
+    }
+    alice
+  });
+  let alice = writer.join().unwrap();
+
+  let received = read_for(&mut bob, Duration::from_secs(5));
+  assert!(received.contains("messages were missed"), "no gap reported");
+  let sequences = sequences(&received);
+  assert!(sequences.windows(2).all(|v| v[0] < v[1]), "messages came twice or out of order");
+  assert!(received.contains("0399 "), "the newest message is missing");
+  drop(alice);
+
+  server.shutdown();
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use std::{io::Write, time::Duration};
+
+use common::{message, read_for, TestServer};
+
+fn message_without_username(text: &str) -> Vec<u8> {
+  format!("SIGNAL_TYPE: MESSAGE\r\nWITH_MESSAGE\r\n\r\n{text}\r\n\r\n").into_bytes()
+}
+
+#[test]
+fn impersonation_is_rejected() {
+  let server = TestServer::start("");
+  let mut alice = server.join("alice");
+  let mut bob = server.join("bob");
+  read_for(&mut alice, Duration::from_millis(300));
+  read_for(&mut bob, Duration::from_millis(300));
+
+  alice.write_all(&message("bob", "I owe alice 100$")).unwrap();
+
+  let seen_by_bob = read_for(&mut bob, Duration::from_millis(500));
+  assert!(!seen_by_bob.contains("I owe alice"), "{seen_by_bob}");
+
+  let seen_by_alice = read_for(&mut alice, Duration::from_millis(300));
+  assert!(seen_by_alice.contains("SIGNAL_TYPE: WARNING"), "{seen_by_alice}");
+  assert!(seen_by_alice.contains("messages can be sent only as alice"), "{seen_by_alice}");
+}
+
+#[test]
+fn messages_are_stamped_with_the_authorized_user() {
+  let server = TestServer::start("");
+  let mut alice = server.join("alice");
+  let mut bob = server.join("bob");
+  read_for(&mut bob, Duration::from_millis(300));
+
+  alice.write_all(&message_without_username("no name needed")).unwrap();
+
+  let seen_by_bob = read_for(&mut bob, Duration::from_millis(500));
+  assert!(seen_by_bob.contains("USERNAME: alice\r\n"), "{seen_by_bob}");
+  assert!(seen_by_bob.contains("no name needed"), "{seen_by_bob}");
+}
+
+#[test]
+fn matching_username_is_still_accepted() {
+  let server = TestServer::start("");
+  let mut alice = server.join("alice");
+  let mut bob = server.join("bob");
+  read_for(&mut bob, Duration::from_millis(300));
+
+  alice.write_all(&message("alice", "old clients repeat the name")).unwrap();
+
+  let seen_by_bob = read_for(&mut bob, Duration::from_millis(500));
+  assert!(seen_by_bob.contains("USERNAME: alice\r\n"), "{seen_by_bob}");
+  assert!(seen_by_bob.contains("old clients repeat the name"), "{seen_by_bob}");
+}
+
+#[test]
+fn server_announces_identity_binding() {
+  let server = TestServer::start("");
+  let mut stream = server.connect();
+  stream.write_all(&common::handshake("alice")).unwrap();
+
+  let response = read_for(&mut stream, Duration::from_millis(300));
+  assert!(response.contains("SERVER_IDENTITY"), "{response}");
+}
+
+#[test]
+fn header_lines_in_a_body_stay_text() {
+  let server = TestServer::start("");
+  let mut alice = server.join("alice");
+  let mut bob = server.join("bob");
+  let mut carol = server.join("carol");
+  read_for(&mut alice, Duration::from_millis(300));
+  read_for(&mut bob, Duration::from_millis(300));
+  read_for(&mut carol, Duration::from_millis(300));
+
+  let forged = "see you\r\nSERVER_MESSAGE\r\nPRESENCE: LEFT\r\nMESSAGE_ID: forged\r\nTO: bob";
+  alice.write_all(&message_without_username(forged)).unwrap();
+
+  // not a direct message to bob, everyone gets it
+  let seen_by_carol = read_for(&mut carol, Duration::from_millis(500));
+  let (headers, body) = seen_by_carol.split_once("\r\n\r\n").unwrap();
+  assert!(body.starts_with(forged), "{seen_by_carol}");
+  assert!(headers.contains("USERNAME: alice\r\n"), "{seen_by_carol}");
+  for forged_header in ["SERVER_MESSAGE", "PRESENCE", "MESSAGE_ID: forged", "TO: "] {
+    assert!(!headers.contains(forged_header), "{seen_by_carol}");
+  }
+  let seen_by_bob = read_for(&mut bob, Duration::from_millis(300));
+  assert!(seen_by_bob.contains(forged), "{seen_by_bob}");
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use std::{
+    io::Write,
+    net::TcpStream,
+    time::Duration
+  };
+use server::{ChatServer, ServerHandle};
+
+use common::{connect, join, message, read_for};
+
+const WAIT: Duration = Duration::from_millis(500);
+
+fn irc(server: &ServerHandle) -> TcpStream {
+  connect(server.irc_addresses()[0])
+}
+
+fn send(stream: &mut TcpStream, lines: &[&str]) {
+  for line in lines {
+    stream.write_all(format!("{line}\r\n").as_bytes()).unwrap();
+  }
+}
+
+#[test]
+fn irc_and_chat_users_see_each_other() {
+  let server = ChatServer::builder().irc_port(0).motd("Hello there").start().unwrap();
+  let mut alice = join(server.local_addr(), "alice");
+  read_for(&mut alice, WAIT);
+
+  let mut dave = irc(&server);
+  send(&mut dave, &["CAP LS 302", "NICK dave", "USER dave 0 * :Dave", "CAP END"]);
+  let welcome = read_for(&mut dave, WAIT);
+  assert!(welcome.contains(" 001 dave :Welcome to chat-server, dave"), "{welcome}");
+  assert!(welcome.contains(" 372 dave :- Hello there"), "{welcome}");
+
+  send(&mut dave, &["JOIN #chat"]);
+  let joined = read_for(&mut dave, WAIT);
+  assert!(joined.contains(":dave!dave@chat-server JOIN #chat"), "{joined}");
+  assert!(joined.contains(" 353 dave = #chat :alice dave"), "{joined}");
+  assert!(joined.contains(" 366 dave #chat :End of /NAMES list"), "{joined}");
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("dave joined the chat!"), "{received}");
+
+  alice.write_all(&message("alice", "hello irc\nsecond line")).unwrap();
+  let received = read_for(&mut dave, WAIT);
+  assert!(received.contains(":alice!alice@chat-server PRIVMSG #chat :hello irc\r\n"), "{received}");
+  assert!(received.contains(":alice!alice@chat-server PRIVMSG #chat :second line\r\n"), "{received}");
+
+  send(&mut dave, &["PRIVMSG #chat :hello chat"]);
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("USERNAME: dave"), "{received}");
+  assert!(received.contains("hello chat"), "{received}");
+  // IRC clients show their own messages themselves
+  let echo = read_for(&mut dave, WAIT);
+  assert!(!echo.contains("hello chat"), "{echo}");
+
+  send(&mut dave, &["PING :lag-check"]);
+  let pong = read_for(&mut dave, WAIT);
+  assert!(pong.contains("PONG chat-server :lag-check"), "{pong}");
+
+  send(&mut dave, &["PART #chat"]);
+  let parted = read_for(&mut dave, WAIT);
+  assert!(parted.contains(":dave!dave@chat-server PART #chat"), "{parted}");
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("dave left the chat!"), "{received}");
+
+  send(&mut dave, &["QUIT :bye"]);
+  let quit = read_for(&mut dave, WAIT);
+  assert!(quit.contains("ERROR :Closing link"), "{quit}");
+  assert_eq!(server.users(), vec!["alice"]);
+
+  server.shutdown();
+}
+
+#[test]
+fn irc_registration_errors_and_direct_messages() {
+  let server = ChatServer::builder().irc_port(0).start().unwrap();
+  let mut alice = join(server.local_addr(), "alice");
+  read_for(&mut alice, WAIT);
+
+  let mut dave = irc(&server);
+  send(&mut dave, &["JOIN #chat", "NICK 1dave", "NICK alice", "USER dave 0 * :Dave"]);
+  let answer = read_for(&mut dave, WAIT);
+  assert!(answer.contains(" 451 * :You have not registered"), "{answer}");
+  assert!(answer.contains(" 432 * 1dave :Erroneous nickname"), "{answer}");
+  assert!(answer.contains(" 433 * alice :Nickname is already in use"), "{answer}");
+  assert!(!answer.contains(" 001 "), "{answer}");
+
+  send(&mut dave, &["NICK dave"]);
+  let answer = read_for(&mut dave, WAIT);
+  assert!(answer.contains(" 001 dave "), "{answer}");
+
+  send(&mut dave, &["JOIN #other", "PRIVMSG #chat :not joined yet", "WHOIS alice"]);
+  let answer = read_for(&mut dave, WAIT);
+  assert!(answer.contains(" 403 dave #other :No such channel"), "{answer}");
+  assert!(answer.contains(" 404 dave #chat :Cannot send to channel"), "{answer}");
+  assert!(answer.contains(" 421 dave WHOIS :Unknown command"), "{answer}");
+
+  // PRIVMSG to a nick is a direct message, and back
+  send(&mut dave, &["PRIVMSG alice :just between us", "PRIVMSG nobody :hello?"]);
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("TO: alice"), "{received}");
+  assert!(received.contains("just between us"), "{received}");
+  let answer = read_for(&mut dave, WAIT);
+  assert!(answer.contains(" 401 dave nobody :nobody is not a known user"), "{answer}");
+
+  alice.write_all(b"SIGNAL_TYPE: MESSAGE\r\nTO: dave\r\nWITH_MESSAGE\r\n\r\nsure thing\r\n\r\n").unwrap();
+  let received = read_for(&mut dave, WAIT);
+  assert!(received.contains(":alice!alice@chat-server PRIVMSG dave :sure thing"), "{received}");
+
+  // a CR or NUL in a message can't end the line early
+  alice.write_all(b"SIGNAL_TYPE: MESSAGE\r\nTO: dave\r\nWITH_MESSAGE\r\n\r\nsure\rQUIT :x\0 thing\r\n\r\n").unwrap();
+  let received = read_for(&mut dave, WAIT);
+  assert!(received.contains(":alice!alice@chat-server PRIVMSG dave :sureQUIT :x thing\r\n"), "{received}");
+  send(&mut dave, &["JOIN #chat"]);
+  read_for(&mut dave, WAIT);
+  alice.write_all(&message("alice", "hello\rQUIT :x")).unwrap();
+  let received = read_for(&mut dave, WAIT);
+  assert!(received.contains(":alice!alice@chat-server PRIVMSG #chat :helloQUIT :x\r\n"), "{received}");
+
+  server.shutdown();
+}
+
+#[test]
+fn privileged_irc_nicks_log_in_with_pass() {
+  let server = ChatServer::builder()
+    .irc_port(0)
+    .moderators(vec!["mod".to_owned()])
+    .moderator_token("mod-token")
+    .start()
+    .unwrap();
+
+  let mut impostor = irc(&server);
+  send(&mut impostor, &["PASS wrong", "NICK mod", "USER mod 0 * :Mod"]);
+  let answer = read_for(&mut impostor, WAIT);
+  assert!(answer.contains("ERROR :Closing link: password incorrect"), "{answer}");
+  assert!(!answer.contains(" 001 "), "{answer}");
+
+  let mut moderator = irc(&server);
+  send(&mut moderator, &["PASS mod-token", "NICK mod", "USER mod 0 * :Mod"]);
+  let answer = read_for(&mut moderator, WAIT);
+  assert!(answer.contains(" 001 mod "), "{answer}");
+
+  server.shutdown();
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant}
+  };
+use server::ChatServer;
+
+use common::{join, join_with_token, read_for};
+
+const WAIT: Duration = Duration::from_millis(500);
+
+fn stats_request() -> &'static [u8] {
+  b"SIGNAL_TYPE: STATS\r\n\r\n"
+}
+
+#[test]
+fn stats_are_sent_to_admins_only() {
+  let server = ChatServer::builder().admins(vec!["alice".to_owned()]).admin_token("stats").start().unwrap();
+  let mut alice = join_with_token(server.local_addr(), "alice", "stats");
+  let mut bob = join(server.local_addr(), "bob");
+  read_for(&mut alice, WAIT);
+
+  alice.write_all(stats_request()).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("SIGNAL_TYPE: STATS"), "{received}");
+  assert!(received.contains("chat_connected_users 2"), "{received}");
+  assert!(received.contains("chat_connections_total 2"), "{received}");
+
+  bob.write_all(stats_request()).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  assert!(!received.contains("chat_connected_users"), "{received}");
+  assert!(received.contains("Stats are available only to admins"), "{received}");
+
+  server.shutdown();
+}
+
+#[test]
+fn endpoint_answers_while_another_request_stalls() {
+  let server = ChatServer::builder().metrics_port(0).start().unwrap();
+  let metrics = server.metrics_address().unwrap();
+  let _alice = join(server.local_addr(), "alice");
+
+  // connected, but the request never comes
+  let _stalled = TcpStream::connect(metrics).unwrap();
+
+  let start = Instant::now();
+  let mut scraper = TcpStream::connect(metrics).unwrap();
+  scraper.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+  scraper.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+  let mut response = String::new();
+  scraper.read_to_string(&mut response).unwrap();
+  assert!(start.elapsed() < Duration::from_secs(1), "answered after {:?}", start.elapsed());
+
+  assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+  assert!(response.contains("chat_connected_users 1\n"), "{response}");
+
+  server.shutdown();
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use std::{
+    io::Write,
+    time::Duration
+  };
+use server::ChatServer;
+
+use common::{connect, handshake, handshake_with_token, is_closed_within, join, join_with_token, message, read_for};
+
+const WAIT: Duration = Duration::from_millis(300);
+
+fn moderation(action: &str, target: &str, extra: &str) -> Vec<u8> {
+  format!("SIGNAL_TYPE: {action}\r\nTARGET: {target}\r\n{extra}\r\n\r\n").into_bytes()
+}
+
+#[test]
+fn moderators_mute_and_kick_users() {
+  let server = ChatServer::builder()
+    .moderators(vec!["mod".to_owned()])
+    .moderator_token("mod-token")
+    .start()
+    .unwrap();
+  let mut moderator = join_with_token(server.local_addr(), "mod", "mod-token");
+  let mut alice = join(server.local_addr(), "alice");
+  read_for(&mut moderator, WAIT);
+  read_for(&mut alice, WAIT);
+
+  moderator.write_all(&moderation("MUTE", "alice", "DURATION: 60\r\nWITH_MESSAGE\r\n\r\nspam")).unwrap();
+  let announcement = read_for(&mut moderator, WAIT);
+  assert!(announcement.contains("alice was muted for 60 seconds by mod: spam"), "{announcement}");
+
+  read_for(&mut alice, WAIT);
+  alice.write_all(&message("alice", "can I talk?")).unwrap();
+  let answer = read_for(&mut alice, WAIT);
+  assert!(answer.contains("You are muted by a moderator"), "{answer}");
+  let received = read_for(&mut moderator, WAIT);
+  assert!(!received.contains("can I talk?"), "{received}");
+
+  moderator.write_all(&moderation("KICK", "alice", "")).unwrap();
+  assert!(is_closed_within(&mut alice, Duration::from_secs(1)));
+  let announcement = read_for(&mut moderator, WAIT);
+  assert!(announcement.contains("alice was kicked by mod"), "{announcement}");
+
+  server.shutdown();
+}
+
+#[test]
+fn users_cant_moderate_and_banned_users_cant_join() {
+  let server = ChatServer::builder()
+    .owners(vec!["owner".to_owned()])
+    .moderators(vec!["mod".to_owned()])
+    .owner_token("owner-token")
+    .moderator_token("mod-token")
+    .start()
+    .unwrap();
+  let mut owner = join_with_token(server.local_addr(), "owner", "owner-token");
+  let mut moderator = join_with_token(server.local_addr(), "mod", "mod-token");
+  let mut alice = join(server.local_addr(), "alice");
+  read_for(&mut owner, WAIT);
+  read_for(&mut moderator, WAIT);
+  read_for(&mut alice, WAIT);
+
+  alice.write_all(&moderation("KICK", "mod", "")).unwrap();
+  let answer = read_for(&mut alice, WAIT);
+  assert!(answer.contains("only moderators and owners can do that"), "{answer}");
+
+  moderator.write_all(&moderation("BAN", "owner", "")).unwrap();
+  let answer = read_for(&mut moderator, WAIT);
+  assert!(answer.contains("can't moderate owner"), "{answer}");
+
+  owner.write_all(&moderation("BAN", "alice", "")).unwrap();
+  assert!(is_closed_within(&mut alice, Duration::from_secs(1)));
+  read_for(&mut owner, WAIT);
+
+  let mut again = connect(server.local_addr());
+  again.write_all(&handshake("alice")).unwrap();
+  let response = read_for(&mut again, WAIT);
+  assert!(response.contains("AUTH_STATUS: DENIED"), "{response}");
+
+  owner.write_all(&moderation("UNBAN", "alice", "")).unwrap();
+  let announcement = read_for(&mut owner, WAIT);
+  assert!(announcement.contains("alice was unbanned by owner"), "{announcement}");
+  join(server.local_addr(), "alice");
+
+  server.shutdown();
+}
+
+#[test]
+fn privileged_names_need_their_token() {
+  let server = ChatServer::builder()
+    .owners(vec!["owner".to_owned()])
+    .moderators(vec!["mod".to_owned()])
+    .owner_token("owner-token")
+    .moderator_token("mod-token")
+    .start()
+    .unwrap();
+
+  for attempt in [handshake("owner"), handshake_with_token("owner", "mod-token"), handshake_with_token("mod", "owner-toke")] {
+    let mut stream = connect(server.local_addr());
+    stream.write_all(&attempt).unwrap();
+    let answer = read_for(&mut stream, WAIT);
+    assert!(answer.contains("AUTH_STATUS: DENIED"), "{answer}");
+  }
+
+  // a token of someone else's role gives a plain user nothing
+  let mut alice = join_with_token(server.local_addr(), "alice", "owner-token");
+  read_for(&mut alice, WAIT);
+  alice.write_all(&moderation("KICK", "bob", "")).unwrap();
+  let answer = read_for(&mut alice, WAIT);
+  assert!(answer.contains("only moderators and owners can do that"), "{answer}");
+
+  server.shutdown();
+}
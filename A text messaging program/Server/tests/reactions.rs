@@ -0,0 +1,93 @@
+mod common;
+
+use std::{io::Write, time::Duration};
+use server::ChatServer;
+
+use common::{headers_of, id_of, join, join_with, message, read_for};
+
+const WAIT: Duration = Duration::from_millis(500);
+const REACTING: &str = "MESSAGE_IDS,WARNINGS,REACTIONS";
+
+fn react(id: &str, reaction: &str, remove: bool) -> Vec<u8> {
+  let remove = if remove { "REMOVE\r\n" } else { "" };
+  format!("SIGNAL_TYPE: REACT\r\nMESSAGE_ID: {id}\r\nREACTION: {reaction}\r\n{remove}\r\n\r\n").into_bytes()
+}
+
+#[test]
+fn reactions_are_counted_and_replayed() {
+  let server = ChatServer::builder().start().unwrap();
+  let mut alice = join_with(server.local_addr(), "alice", REACTING);
+  let mut bob = join_with(server.local_addr(), "bob", REACTING);
+  let mut old = join(server.local_addr(), "old");
+  read_for(&mut alice, WAIT);
+  read_for(&mut old, WAIT);
+
+  alice.write_all(&message("alice", "lunch at noon?")).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  let id = id_of(&received, "lunch at noon?");
+  read_for(&mut alice, WAIT);
+  read_for(&mut old, WAIT);
+
+  bob.write_all(&react(&id, "👍", false)).unwrap();
+  alice.write_all(&react(&id, "👍", false)).unwrap();
+  read_for(&mut bob, WAIT);
+  alice.write_all(&react(&id, "🎉", false)).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("SIGNAL_TYPE: REACT"), "{received}");
+  assert!(received.contains("REACTIONS: 👍=2,🎉=1\r\nREACTED: 👍,🎉"), "{received}");
+  let received = read_for(&mut bob, WAIT);
+  assert!(received.contains("REACTIONS: 👍=2,🎉=1\r\nREACTED: 👍\r\n"), "{received}");
+  // peers without the capability get no updates
+  let received = read_for(&mut old, WAIT);
+  assert!(!received.contains("REACT"), "{received}");
+
+  // history replay carries the reactions
+  let mut carol = join_with(server.local_addr(), "carol", REACTING);
+  let replay = read_for(&mut carol, WAIT);
+  let frame = headers_of(&replay, "lunch at noon?");
+  assert!(frame.contains("REACTIONS: 👍=2,🎉=1\r\nREACTED: \r\n"), "{frame}");
+
+  server.shutdown();
+}
+
+#[test]
+fn only_the_author_removes_a_reaction() {
+  let server = ChatServer::builder().start().unwrap();
+  let mut alice = join_with(server.local_addr(), "alice", REACTING);
+  let mut bob = join_with(server.local_addr(), "bob", REACTING);
+  read_for(&mut alice, WAIT);
+
+  alice.write_all(&message("alice", "ship it")).unwrap();
+  let id = id_of(&read_for(&mut bob, WAIT), "ship it");
+  read_for(&mut alice, WAIT);
+
+  alice.write_all(&react(&id, "🚀", false)).unwrap();
+  read_for(&mut alice, WAIT);
+  read_for(&mut bob, WAIT);
+
+  bob.write_all(&react(&id, "🚀", true)).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  assert!(received.contains("Reaction failed: you haven't reacted with 🚀"), "{received}");
+  assert!(!received.contains("SIGNAL_TYPE: REACT"), "{received}");
+
+  alice.write_all(&react(&id, "🚀", true)).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  assert!(received.contains(&format!("MESSAGE_ID: {id}\r\nSIGNAL_TYPE: REACT\r\nREACTIONS: \r\n")), "{received}");
+
+  // bad reactions and unknown messages are refused
+  alice.write_all(&react(&id, "a,b", false)).unwrap();
+  alice.write_all(&react("no-such-message", "👍", false)).unwrap();
+  alice.write_all(&react(&id, "👀", false)).unwrap();
+  alice.write_all(&react(&id, "👀", false)).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("a reaction can't have spaces, ',' or '='"), "{received}");
+  assert!(received.contains("the message is not in the history"), "{received}");
+  assert!(received.contains("you have already reacted with 👀"), "{received}");
+
+  let mut carol = join_with(server.local_addr(), "carol", REACTING);
+  let replay = read_for(&mut carol, WAIT);
+  let frame = headers_of(&replay, "ship it");
+  assert!(frame.contains("REACTIONS: 👀=1\r\n"), "{frame}");
+
+  server.shutdown();
+}
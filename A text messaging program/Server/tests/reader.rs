@@ -0,0 +1,111 @@
+mod common;
+
+use std::{io::Write, thread, time::Duration};
+
+use common::{is_closed_within, message, read_for, TestServer};
+
+const LIMITS: &str = "
+[limits]
+max_header_bytes = 1024
+max_body_bytes = 2048
+frame_timeout_secs = 1
+";
+
+#[test]
+fn endless_header_line_closes_connection() {
+  let server = TestServer::start(LIMITS);
+  let mut stream = server.connect();
+
+  // no line end at all, the reader must not keep buffering it
+  let _ = stream.write_all(&vec![b'A'; 64 * 1024]);
+
+  assert!(is_closed_within(&mut stream, Duration::from_secs(2)));
+}
+
+#[test]
+fn too_many_header_lines_close_connection() {
+  let server = TestServer::start(LIMITS);
+  let mut stream = server.join("alice");
+
+  for _ in 0..200 {
+    if stream.write_all(b"X-PADDING: aaaaaaaaaaaaaaaa\r\n").is_err() {
+      break;
+    }
+  }
+
+  assert!(is_closed_within(&mut stream, Duration::from_secs(2)));
+}
+
+#[test]
+fn oversized_body_is_rejected_with_reason() {
+  let server = TestServer::start(LIMITS);
+  let mut stream = server.join("alice");
+
+  let _ = stream.write_all(&message("alice", &"b".repeat(16 * 1024)));
+
+  let received = read_for(&mut stream, Duration::from_millis(500));
+  assert!(received.contains("SIGNAL_TYPE: WARNING"), "{received}");
+  assert!(received.contains("message is larger than 2048 bytes"), "{received}");
+  assert!(!received.contains("bbbb"));
+  assert!(is_closed_within(&mut stream, Duration::from_secs(2)));
+}
+
+#[test]
+fn incomplete_frame_times_out() {
+  let server = TestServer::start(LIMITS);
+  let mut stream = server.join("alice");
+
+  stream.write_all(b"USERNAME: alice\r\nSIGNAL_TYPE: MESSAGE\r\n").unwrap();
+
+  let received = read_for(&mut stream, Duration::from_millis(1500));
+  assert!(received.contains("frame was not completed in time"), "{received}");
+  assert!(is_closed_within(&mut stream, Duration::from_secs(2)));
+}
+
+#[test]
+fn silent_connection_times_out_before_handshake() {
+  let server = TestServer::start(LIMITS);
+  let mut stream = server.connect();
+
+  assert!(is_closed_within(&mut stream, Duration::from_secs(3)));
+}
+
+#[test]
+fn idle_user_is_not_disconnected() {
+  let server = TestServer::start(LIMITS);
+  let mut stream = server.join("alice");
+
+  // longer than the frame timeout, but no frame was started
+  assert!(!is_closed_within(&mut stream, Duration::from_millis(2500)));
+
+  stream.write_all(&message("alice", "still here")).unwrap();
+  let received = read_for(&mut stream, Duration::from_millis(500));
+  assert!(received.contains("still here"), "{received}");
+}
+
+#[test]
+fn invalid_utf8_closes_connection() {
+  let server = TestServer::start(LIMITS);
+  let mut stream = server.join("alice");
+
+  stream.write_all(b"USERNAME: alice\r\nSIGNAL_TYPE: MESSAGE\r\nWITH_MESSAGE\r\n\r\n\xff\xfe\xfd\r\n\r\n").unwrap();
+
+  let received = read_for(&mut stream, Duration::from_millis(500));
+  assert!(received.contains("not valid UTF-8"), "{received}");
+  assert!(is_closed_within(&mut stream, Duration::from_secs(2)));
+}
+
+#[test]
+fn fragmented_frame_is_accepted() {
+  let server = TestServer::start(LIMITS);
+  let mut stream = server.join("alice");
+  stream.set_nodelay(true).unwrap();
+
+  for byte in message("alice", "slow but fine") {
+    stream.write_all(&[byte]).unwrap();
+    thread::sleep(Duration::from_millis(2));
+  }
+
+  let received = read_for(&mut stream, Duration::from_millis(500));
+  assert!(received.contains("slow but fine"), "{received}");
+}
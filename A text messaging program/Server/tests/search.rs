@@ -0,0 +1,65 @@
+mod common;
+
+use std::{
+    io::Write,
+    time::Duration
+  };
+use server::ChatServer;
+
+use common::{join, message, read_for};
+
+const WAIT: Duration = Duration::from_millis(300);
+
+fn search(query: &str, headers: &str) -> Vec<u8> {
+  format!("SIGNAL_TYPE: SEARCH\r\n{headers}WITH_MESSAGE\r\n\r\n{query}\r\n\r\n").into_bytes()
+}
+
+#[test]
+fn search_finds_matching_messages() {
+  let server = ChatServer::builder().start().unwrap();
+  let mut alice = join(server.local_addr(), "alice");
+  let mut bob = join(server.local_addr(), "bob");
+
+  alice.write_all(&message("alice", "the build is broken")).unwrap();
+  bob.write_all(&message("bob", "Build fixed in 42")).unwrap();
+  alice.write_all(&message("alice", "thanks")).unwrap();
+  read_for(&mut alice, WAIT);
+  read_for(&mut bob, WAIT);
+
+  bob.write_all(&search("build", "")).unwrap();
+  let answer = read_for(&mut bob, WAIT);
+  assert!(answer.contains("the build is broken"), "{answer}");
+  assert!(answer.contains("Build fixed in 42"), "{answer}");
+  assert!(!answer.contains("thanks"), "{answer}");
+  assert!(answer.contains("RESULTS: 2"), "{answer}");
+
+  bob.write_all(&search("/fixed in \\d+/", "AUTHOR: bob\r\n")).unwrap();
+  let answer = read_for(&mut bob, WAIT);
+  assert!(answer.contains("MESSAGE_ID: "), "{answer}");
+  assert!(answer.contains("RESULTS: 1"), "{answer}");
+
+  bob.write_all(&search("build", "AUTHOR: alice\r\nSINCE: 18446744073709551615\r\n")).unwrap();
+  let answer = read_for(&mut bob, WAIT);
+  assert!(answer.contains("RESULTS: 0"), "{answer}");
+
+  server.shutdown();
+}
+
+#[test]
+fn invalid_pattern_gives_a_warning() {
+  let server = ChatServer::builder().start().unwrap();
+  let mut alice = join(server.local_addr(), "alice");
+  read_for(&mut alice, WAIT);
+
+  alice.write_all(&search("/(unclosed/", "")).unwrap();
+  let answer = read_for(&mut alice, WAIT);
+  assert!(answer.contains("SIGNAL_TYPE: WARNING"), "{answer}");
+  assert!(answer.contains("Search failed"), "{answer}");
+
+  // the connection is still usable
+  alice.write_all(&search("anything", "")).unwrap();
+  let answer = read_for(&mut alice, WAIT);
+  assert!(answer.contains("RESULTS: 0"), "{answer}");
+
+  server.shutdown();
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use std::{io::Write, time::Duration};
+use server::ChatServer;
+
+use common::{headers_of, id_of, join, message, read_for};
+
+const WAIT: Duration = Duration::from_millis(500);
+
+fn reply(id: &str, text: &str) -> Vec<u8> {
+  format!("SIGNAL_TYPE: MESSAGE\r\nREPLY_TO: {id}\r\nWITH_MESSAGE\r\n\r\n{text}\r\n\r\n").into_bytes()
+}
+
+fn history(thread: Option<&str>) -> Vec<u8> {
+  let thread = thread.map(|v| format!("THREAD: {v}\r\n")).unwrap_or_default();
+  format!("SIGNAL_TYPE: HISTORY\r\n{thread}\r\n\r\n").into_bytes()
+}
+
+#[test]
+fn replies_name_their_parent_and_make_a_thread() {
+  let server = ChatServer::builder().start().unwrap();
+  let mut alice = join(server.local_addr(), "alice");
+  let mut bob = join(server.local_addr(), "bob");
+  read_for(&mut alice, WAIT);
+
+  alice.write_all(&message("alice", "who's up for lunch?")).unwrap();
+  alice.write_all(&message("alice", "the build is red")).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  let lunch = id_of(&received, "who's up for lunch?");
+  let build = id_of(&received, "the build is red");
+
+  bob.write_all(&reply(&lunch, "me!")).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(headers_of(&received, "me!").contains(&format!("REPLY_TO: {lunch}")), "{received}");
+  let me = id_of(&received, "me!");
+  alice.write_all(&reply(&me, "noon then")).unwrap();
+  bob.write_all(&reply(&build, "looking")).unwrap();
+  read_for(&mut alice, WAIT);
+  read_for(&mut bob, WAIT);
+
+  // any message of the thread fetches all of it, oldest first
+  bob.write_all(&history(Some(&me))).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  let lunch_at = received.find("who's up for lunch?").unwrap();
+  let me_at = received.find("me!").unwrap();
+  let noon_at = received.find("noon then").unwrap();
+  assert!(lunch_at < me_at && me_at < noon_at, "{received}");
+  assert!(!received.contains("looking"), "{received}");
+  assert!(!received.contains("the build is red"), "{received}");
+  assert!(received.contains(&format!("RESULTS: 3\r\nTHREAD: {me}\r\n")), "{received}");
+
+  // and no thread is the whole history, without server notices
+  bob.write_all(&history(None)).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  assert!(received.contains("RESULTS: 5\r\n"), "{received}");
+  assert!(!received.contains("joined the chat"), "{received}");
+
+  server.shutdown();
+}
+
+#[test]
+fn replies_need_a_message_in_the_history() {
+  let server = ChatServer::builder().start().unwrap();
+  let mut alice = join(server.local_addr(), "alice");
+  let mut bob = join(server.local_addr(), "bob");
+  let received = read_for(&mut alice, WAIT);
+  let joined = id_of(&received, "bob joined the chat!");
+
+  alice.write_all(&reply("no-such-message", "hello?")).unwrap();
+  alice.write_all(&reply(&joined, "welcome")).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert_eq!(received.matches("Message rejected: the message replied to is not in the history").count(), 2, "{received}");
+  assert!(!read_for(&mut bob, WAIT).contains("hello?"));
+
+  alice.write_all(&history(Some("no-such-message"))).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("History failed: the message is not in the history"), "{received}");
+
+  server.shutdown();
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use std::{io::Write, time::Duration};
+use server::ChatServer;
+
+use common::{join, join_with, read_for};
+
+const WAIT: Duration = Duration::from_millis(500);
+const WITH_FILES: &str = "MESSAGE_IDS,WARNINGS,FILE_TRANSFER";
+const HASH: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+fn offer(to: &str, transfer: &str, name: &str) -> Vec<u8> {
+  format!(
+    "SIGNAL_TYPE: FILE_OFFER\r\nTO: {to}\r\nTRANSFER: {transfer}\r\nFILE_NAME: {name}\r\nFILE_SIZE: 4\r\nSHA256: {HASH}\r\nPORT: 40123\r\n\r\n\r\n"
+  ).into_bytes()
+}
+
+fn answer(transfer: &str, rejected: bool) -> Vec<u8> {
+  let rejected = if rejected { "REJECTED\r\n" } else { "" };
+  format!("SIGNAL_TYPE: FILE_ACCEPT\r\nTRANSFER: {transfer}\r\n{rejected}\r\n\r\n").into_bytes()
+}
+
+#[test]
+fn offers_and_answers_go_through_the_server() {
+  let server = ChatServer::builder().start().unwrap();
+  let mut alice = join_with(server.local_addr(), "alice", WITH_FILES);
+  let mut bob = join_with(server.local_addr(), "bob", WITH_FILES);
+  let mut carol = join_with(server.local_addr(), "carol", WITH_FILES);
+  read_for(&mut alice, WAIT);
+  read_for(&mut bob, WAIT);
+  read_for(&mut carol, WAIT);
+
+  alice.write_all(&offer("bob", "t1", "notes.txt")).unwrap();
+  let received = read_for(&mut bob, WAIT);
+  assert!(received.contains("USERNAME: alice\r\n"), "{received}");
+  assert!(received.contains("SIGNAL_TYPE: FILE_OFFER\r\n"), "{received}");
+  assert!(received.contains("TRANSFER: t1\r\nFILE_NAME: notes.txt\r\nFILE_SIZE: 4\r\n"), "{received}");
+  // the sender's address as the server sees it
+  assert!(received.contains("ADDRESS: 127.0.0.1:40123\r\n"), "{received}");
+  assert!(!read_for(&mut carol, WAIT).contains("FILE_OFFER"));
+
+  // only the recipient answers, and only once
+  carol.write_all(&answer("t1", false)).unwrap();
+  let received = read_for(&mut carol, WAIT);
+  assert!(received.contains("File transfer failed: there is no offer t1 for you"), "{received}");
+  bob.write_all(&answer("t1", false)).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("USERNAME: bob\r\nSIGNAL_TYPE: FILE_ACCEPT\r\nTRANSFER: t1\r\n\r\n"), "{received}");
+  bob.write_all(&answer("t1", true)).unwrap();
+  assert!(read_for(&mut bob, WAIT).contains("there is no offer t1 for you"));
+
+  alice.write_all(&offer("bob", "t2", "notes.txt")).unwrap();
+  read_for(&mut bob, WAIT);
+  bob.write_all(&answer("t2", true)).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("TRANSFER: t2\r\nREJECTED\r\n"), "{received}");
+
+  server.shutdown();
+}
+
+#[test]
+fn bad_offers_are_refused() {
+  let server = ChatServer::builder().start().unwrap();
+  let mut alice = join_with(server.local_addr(), "alice", WITH_FILES);
+  let mut old = join(server.local_addr(), "old");
+  read_for(&mut alice, WAIT);
+
+  alice.write_all(&offer("old", "t1", "notes.txt")).unwrap();
+  alice.write_all(&offer("nobody", "t2", "notes.txt")).unwrap();
+  alice.write_all(&offer("old", "t3", "../../.bashrc")).unwrap();
+  alice.write_all(&offer("alice", "t4", "notes.txt")).unwrap();
+  let received = read_for(&mut alice, WAIT);
+  assert!(received.contains("File transfer failed: old can't take files"), "{received}");
+  assert!(received.contains("File transfer failed: nobody is not online"), "{received}");
+  assert!(received.contains("File transfer failed: invalid file name"), "{received}");
+  assert!(received.contains("File transfer failed: you can't send files to yourself"), "{received}");
+  assert!(!read_for(&mut old, WAIT).contains("FILE_OFFER"));
+
+  server.shutdown();
+}